@@ -0,0 +1,19 @@
+use std::process::Command;
+
+// Bakes build metadata into the binary for `iz version --verbose`.
+fn main() {
+    let target = std::env::var("TARGET").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=IZ_BUILD_TARGET={target}");
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=IZ_RUSTC_VERSION={rustc_version}");
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
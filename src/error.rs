@@ -0,0 +1,98 @@
+//! Errors returned by the library's config, command and parameter functions,
+//! so callers can match on the failure instead of its message. The binary
+//! adds context to them with anyhow.
+
+use crate::ParamType;
+use std::path::PathBuf;
+
+#[derive(thiserror::Error, Debug)]
+pub enum IzError {
+    #[error("izconfig.json not found at {}. Example content:\n{example}", path.display())]
+    ConfigNotFound { path: PathBuf, example: String },
+
+    #[error("Failed to read config file: {}", path.display())]
+    ConfigRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to parse config file: {}", path.display())]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("Command '{0}' not found in izconfig.json")]
+    CommandNotFound(String),
+
+    /// `--fuzzy` found no command starting with the given prefix.
+    #[error("Command '{0}' not found in izconfig.json (no command starts with '{0}')")]
+    NoPrefixMatch(String),
+
+    #[error("Command prefix '{prefix}' is ambiguous: {}", candidates.join(", "))]
+    AmbiguousPrefix {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+
+    #[error("Alias '{alias}' points to unknown command '{target}'")]
+    UnknownAliasTarget { alias: String, target: String },
+
+    /// The aliases followed, ending with the one seen twice.
+    #[error("Alias cycle: {}", .0.join(" -> "))]
+    AliasCycle(Vec<String>),
+
+    /// A placeholder without a value, e.g. `name` or `config.db.user`.
+    #[error("Required parameter not found: {0}")]
+    MissingParam(String),
+
+    #[error("Missing declared parameters: {}", .0.join(", "))]
+    MissingDeclaredParams(Vec<String>),
+
+    #[error("Parameter '{0}' is not a scalar value")]
+    NonScalarParam(String),
+
+    #[error("Parameter '{name}' must be of type {expected}, got '{value}'")]
+    InvalidParamType {
+        name: String,
+        expected: ParamType,
+        value: String,
+    },
+
+    /// `--set` named a field commands don't have, or one it can't override.
+    #[error(
+        "Unknown field '{field}' for command '{command}': can set {}",
+        crate::OVERRIDABLE_COMMAND_FIELDS.join(", ")
+    )]
+    UnknownCommandField { command: String, field: String },
+
+    #[error("Invalid override for command.{command}.{field}: {message}")]
+    InvalidOverride {
+        command: String,
+        field: String,
+        message: String,
+    },
+
+    /// A `#{file:...}` placeholder names a path the checkout doesn't have.
+    #[error("File referenced by #{{file:{0}}} not found in the checkout")]
+    MissingCheckoutFile(String),
+
+    #[error("#{{file:{0}}} must be a relative path inside the checkout")]
+    InvalidFilePath(String),
+
+    #[error("Failed to checkout commit")]
+    CheckoutFailed { commit: String },
+
+    /// `code` is `None` when the command was killed by a signal.
+    #[error("Command failed with {}", describe_exit(*.code))]
+    CommandFailed { code: Option<i32> },
+}
+
+fn describe_exit(code: Option<i32>) -> String {
+    match code {
+        Some(code) => format!("exit code {code}"),
+        None => "no exit code (killed by a signal)".to_string(),
+    }
+}
@@ -0,0 +1,82 @@
+//! Machine-readable progress events for wrapper UIs, written to stderr as one
+//! JSON object per line while `--events` is active.
+
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Minimum gap between two progress events of the same kind (at most 10/sec).
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Files written so far while checking a commit out.
+    CheckoutProgress {
+        phase: CheckoutPhase,
+        total: usize,
+        completed: usize,
+        /// The file being written, if git reported one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckoutPhase {
+    /// Writing the commit's files into the temporary directory.
+    Files,
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Writes `event` to stderr; does nothing unless events are enabled.
+pub fn emit(event: &Event) {
+    if !enabled() {
+        return;
+    }
+    if let Ok(line) = serde_json::to_string(event) {
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+}
+
+/// Lets through at most one emission per interval.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Throttle {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Whether an event may be emitted at `now`; records it if so.
+    pub fn ready_at(&mut self, now: Instant) -> bool {
+        match self.last {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+
+    pub fn ready(&mut self) -> bool {
+        self.ready_at(Instant::now())
+    }
+}
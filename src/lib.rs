@@ -1,265 +1,4951 @@
+pub mod error;
+pub mod events;
+pub mod notifier;
+pub mod runner;
+
+pub use error::IzError;
+pub use runner::{run_hooked, CommandRunner, HookedCommand, HookedOutcome, Step, StepEvent};
+
 use anyhow::Result;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Default)]
+pub struct IzConfig {
+    #[serde(default)]
+    pub commands: HashMap<String, CommandEntry>,
+    #[serde(default)]
+    pub temp_dir: Option<String>,
+    #[serde(default)]
+    pub keep: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_commands: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precreate_dirs: Option<bool>,
+    /// Exit codes that count as a successful run (default `[0]`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_codes: Option<Vec<i32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleanup_strategy: Option<CleanupStrategy>,
+    /// Container image to run commands in, like `--container`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    /// Default the temp dir to `<system temp>/iz` instead of `./.iztemp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_temp: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_clean: Option<AutoClean>,
+    /// Disk space the `iz-` directories in a base temp dir may use; the oldest
+    /// are removed before a run that wouldn't fit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_temp_bytes: Option<u64>,
+    /// Answer used when the `clean` prompt gets an empty line (default no).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clean_confirm_default: Option<bool>,
+}
+
+/// Removes stale `iz-` directories from the base temp dir before a run.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AutoClean {
+    /// Minimum age of a directory to remove, e.g. `7d` or `12h`.
+    pub older_than: String,
+}
+
+/// Auto-clean scans a base directory at most this often.
+pub const AUTO_CLEAN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Written to the base temp dir after each auto-clean; its age rate-limits the next one.
+pub const AUTO_CLEAN_STAMP_FILE: &str = ".iz-auto-clean";
+
+/// Base directory under the operating system's temp location, used by
+/// `--system-temp` and `"system_temp": true`.
+pub fn system_temp_base() -> std::path::PathBuf {
+    std::env::temp_dir().join("iz")
+}
+
+/// Base directory on a RAM-backed filesystem (`/dev/shm` on Linux), used by
+/// `--ram`. `None` where no such filesystem is available.
+pub fn ram_temp_base() -> Option<std::path::PathBuf> {
+    let shm = std::path::Path::new("/dev/shm");
+    (cfg!(target_os = "linux") && shm.is_dir()).then(|| shm.join("iz"))
+}
+
+/// Picks the base directory for temporary checkouts: `--temp-dir`, then
+/// `IZTEMP`, then the command's own `temp_dir`, then the config's `temp_dir`,
+/// then the system temp location if the config asks for it, and finally
+/// `.iztemp` in `current_dir`.
+pub fn resolve_temp_base(
+    cli_temp_dir: Option<&str>,
+    env_temp_dir: Option<String>,
+    command_temp_dir: Option<&str>,
+    config: &IzConfig,
+    current_dir: &std::path::Path,
+) -> std::path::PathBuf {
+    if let Some(temp_dir) = cli_temp_dir {
+        return std::path::PathBuf::from(temp_dir);
+    }
+    if let Some(temp_dir) = env_temp_dir {
+        return std::path::PathBuf::from(temp_dir);
+    }
+    if let Some(temp_dir) = command_temp_dir {
+        return std::path::PathBuf::from(temp_dir);
+    }
+    if let Some(temp_dir) = &config.temp_dir {
+        return std::path::PathBuf::from(temp_dir);
+    }
+    if config.system_temp == Some(true) {
+        return system_temp_base();
+    }
+    current_dir.join(".iztemp")
+}
+
+/// How temporary directories are removed after a run.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CleanupStrategy {
+    /// Delete the directory in place.
+    #[default]
+    Remove,
+    /// Move it into a `.trash` folder next to it; `iz clean` deletes it later.
+    Rename,
+    /// Move it to the operating system's recycle bin.
+    Trash,
+}
+
+/// A command is either a plain template string, an object with extra metadata,
+/// an explicit argv array, an object naming a program that prints the
+/// template, or an alias for another command with preset params.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum CommandEntry {
+    Template(String),
+    Detailed(CommandDefinition),
+    Argv(ArgvDefinition),
+    Generated(GeneratorDefinition),
+    Alias(AliasDefinition),
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct CommandDefinition {
+    pub template: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<String>,
+    /// Expected type of a parameter's value, checked before substitution.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub types: HashMap<String, ParamType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post: Option<String>,
+    /// When set, failing hooks only warn instead of failing the run.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_hook_failures: bool,
+    /// Run the template through the shell; multiline templates run as a script.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shell: bool,
+    /// Params whose values are masked wherever the command is displayed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<String>,
+    /// Base directory for this command's checkouts, ahead of the config's `temp_dir`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<String>,
+    /// Default `--timeout` for this command, e.g. `30s` or `5m`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    /// Directory inside the checkout the command and its hooks run in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+}
+
+/// A command given as its argv, never split on whitespace. Each element is
+/// substituted on its own, so a value with spaces stays one argument.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct ArgvDefinition {
+    pub argv: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub types: HashMap<String, ParamType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_hook_failures: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+}
+
+/// A command whose template is the stdout of `generator`, run when the command is selected.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct GeneratorDefinition {
+    pub generator: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shell: bool,
+}
+
+/// Another command run with preset params, which `--param` values override.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct AliasDefinition {
+    pub alias: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub params: HashMap<String, String>,
+}
+
+/// The command an invoked name resolves to after following aliases.
+#[derive(Debug, PartialEq)]
+pub struct ResolvedCommand<'a> {
+    /// Name of the command that is actually run.
+    pub name: &'a str,
+    pub entry: &'a CommandEntry,
+    /// Params preset by the aliases along the way; the invoked alias wins.
+    pub params: HashMap<String, String>,
+}
+
+/// Looks up `name`, following aliases to the command they name. Fails on
+/// unknown commands and on alias cycles.
+pub fn resolve_command<'a>(
+    commands: &'a HashMap<String, CommandEntry>,
+    name: &str,
+) -> Result<ResolvedCommand<'a>, IzError> {
+    let (mut name, mut entry) = commands
+        .get_key_value(name)
+        .ok_or_else(|| IzError::CommandNotFound(name.to_string()))?;
+    let mut chain = vec![name.as_str()];
+    let mut params = HashMap::new();
+
+    while let CommandEntry::Alias(alias) = entry {
+        for (key, value) in &alias.params {
+            params.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        (name, entry) =
+            commands
+                .get_key_value(&alias.alias)
+                .ok_or_else(|| IzError::UnknownAliasTarget {
+                    alias: chain.last().unwrap_or(&"").to_string(),
+                    target: alias.alias.clone(),
+                })?;
+        if chain.contains(&name.as_str()) {
+            chain.push(name);
+            return Err(IzError::AliasCycle(
+                chain.iter().map(|name| name.to_string()).collect(),
+            ));
+        }
+        chain.push(name);
+    }
+
+    Ok(ResolvedCommand {
+        name,
+        entry,
+        params,
+    })
+}
+
+/// Expands `prefix` to the one command name starting with it, for
+/// `--fuzzy`. An exact name always wins, even if it prefixes other names.
+pub fn match_command_prefix(
+    commands: &HashMap<String, CommandEntry>,
+    prefix: &str,
+) -> Result<String, IzError> {
+    if commands.contains_key(prefix) {
+        return Ok(prefix.to_string());
+    }
+
+    let mut candidates: Vec<String> = commands
+        .keys()
+        .filter(|name| name.starts_with(prefix))
+        .cloned()
+        .collect();
+    candidates.sort();
+    match candidates.len() {
+        1 => Ok(candidates.remove(0)),
+        0 => Err(IzError::NoPrefixMatch(prefix.to_string())),
+        _ => Err(IzError::AmbiguousPrefix {
+            prefix: prefix.to_string(),
+            candidates,
+        }),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+impl ParamType {
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            ParamType::String => true,
+            ParamType::Int => value.parse::<i64>().is_ok(),
+            ParamType::Float => value.parse::<f64>().is_ok(),
+            ParamType::Bool => matches!(value, "true" | "false"),
+        }
+    }
+}
+
+impl std::fmt::Display for ParamType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ParamType::String => "string",
+            ParamType::Int => "int",
+            ParamType::Float => "float",
+            ParamType::Bool => "bool",
+        };
+        f.write_str(name)
+    }
+}
+
+impl CommandEntry {
+    /// The static template; empty for argv-form and generated commands and aliases.
+    pub fn template(&self) -> &str {
+        match self {
+            CommandEntry::Template(template) => template,
+            CommandEntry::Detailed(definition) => &definition.template,
+            CommandEntry::Argv(_) | CommandEntry::Generated(_) | CommandEntry::Alias(_) => "",
+        }
+    }
+
+    pub fn argv(&self) -> Option<&[String]> {
+        match self {
+            CommandEntry::Argv(definition) => Some(&definition.argv),
+            _ => None,
+        }
+    }
+
+    pub fn alias(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Alias(definition) => Some(&definition.alias),
+            _ => None,
+        }
+    }
+
+    pub fn generator(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Generated(definition) => Some(&definition.generator),
+            _ => None,
+        }
+    }
+
+    pub fn declared_params(&self) -> &[String] {
+        match self {
+            CommandEntry::Template(_) | CommandEntry::Alias(_) => &[],
+            CommandEntry::Detailed(definition) => &definition.params,
+            CommandEntry::Argv(definition) => &definition.params,
+            CommandEntry::Generated(definition) => &definition.params,
+        }
+    }
+
+    pub fn param_types(&self) -> Option<&HashMap<String, ParamType>> {
+        match self {
+            CommandEntry::Detailed(definition) => Some(&definition.types),
+            CommandEntry::Argv(definition) => Some(&definition.types),
+            _ => None,
+        }
+    }
+
+    pub fn pre_hook(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Detailed(definition) => definition.pre.as_deref(),
+            CommandEntry::Argv(definition) => definition.pre.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn post_hook(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Detailed(definition) => definition.post.as_deref(),
+            CommandEntry::Argv(definition) => definition.post.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn temp_dir(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Detailed(definition) => definition.temp_dir.as_deref(),
+            CommandEntry::Argv(definition) => definition.temp_dir.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Detailed(definition) => definition.timeout.as_deref(),
+            CommandEntry::Argv(definition) => definition.timeout.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn workdir(&self) -> Option<&str> {
+        match self {
+            CommandEntry::Detailed(definition) => definition.workdir.as_deref(),
+            CommandEntry::Argv(definition) => definition.workdir.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn secret_params(&self) -> &[String] {
+        match self {
+            CommandEntry::Detailed(definition) => &definition.secrets,
+            CommandEntry::Argv(definition) => &definition.secrets,
+            _ => &[],
+        }
+    }
+
+    pub fn ignore_hook_failures(&self) -> bool {
+        match self {
+            CommandEntry::Detailed(definition) => definition.ignore_hook_failures,
+            CommandEntry::Argv(definition) => definition.ignore_hook_failures,
+            _ => false,
+        }
+    }
+
+    pub fn shell(&self) -> bool {
+        match self {
+            CommandEntry::Template(_) | CommandEntry::Argv(_) | CommandEntry::Alias(_) => false,
+            CommandEntry::Detailed(definition) => definition.shell,
+            CommandEntry::Generated(definition) => definition.shell,
+        }
+    }
+}
+
+impl From<&str> for CommandEntry {
+    fn from(template: &str) -> Self {
+        CommandEntry::Template(template.to_string())
+    }
+}
+
+impl IzConfig {
+    /// Layers `local` on top of `self`; local commands and fields win.
+    pub fn merge(mut self, local: IzConfig) -> IzConfig {
+        self.commands.extend(local.commands);
+        IzConfig {
+            commands: self.commands,
+            temp_dir: local.temp_dir.or(self.temp_dir),
+            keep: local.keep.or(self.keep),
+            allowed_commands: local.allowed_commands.or(self.allowed_commands),
+            precreate_dirs: local.precreate_dirs.or(self.precreate_dirs),
+            success_codes: local.success_codes.or(self.success_codes),
+            cleanup_strategy: local.cleanup_strategy.or(self.cleanup_strategy),
+            container: local.container.or(self.container),
+            system_temp: local.system_temp.or(self.system_temp),
+            auto_clean: local.auto_clean.or(self.auto_clean),
+            max_temp_bytes: local.max_temp_bytes.or(self.max_temp_bytes),
+            clean_confirm_default: local.clean_confirm_default.or(self.clean_confirm_default),
+        }
+    }
+}
+
+/// Keeps only the most recent `limit` lines of a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTail {
+    limit: usize,
+    lines: VecDeque<String>,
+}
+
+impl OutputTail {
+    pub fn new(limit: usize) -> Self {
+        OutputTail {
+            limit,
+            lines: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.lines.len() == self.limit {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        self.lines.iter()
+    }
+
+    pub fn contents(&self) -> String {
+        self.lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+/// Collects at most `limit` bytes of a stream and drops the rest.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CappedBuffer {
+    limit: Option<usize>,
+    bytes: Vec<u8>,
+    truncated: bool,
+}
+
+impl CappedBuffer {
+    pub fn new(limit: Option<usize>) -> Self {
+        CappedBuffer {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Appends as much of `data` as still fits and returns the part that was kept.
+    pub fn push<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        let room = match self.limit {
+            Some(limit) => limit.saturating_sub(self.bytes.len()),
+            None => data.len(),
+        };
+        let kept = &data[..data.len().min(room)];
+        if kept.len() < data.len() {
+            self.truncated = true;
+        }
+        self.bytes.extend_from_slice(kept);
+        kept
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The collected bytes, without a truncation marker.
+    pub fn into_raw_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// The collected bytes, followed by a marker line when output was dropped.
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = self.bytes;
+        if let (true, Some(limit)) = (self.truncated, self.limit) {
+            if !bytes.is_empty() && !bytes.ends_with(b"\n") {
+                bytes.push(b'\n');
+            }
+            bytes.extend_from_slice(truncation_marker(limit).as_bytes());
+            bytes.push(b'\n');
+        }
+        bytes
+    }
+}
+
+/// Lines of unchanged context around each change in [`unified_diff`].
+const DIFF_CONTEXT: usize = 3;
+/// Largest line-comparison table [`unified_diff`] builds; bigger changed
+/// regions are shown as one block of removed and added lines.
+const DIFF_MAX_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// A unified diff of two texts by line, labelled `old_label` and
+/// `new_label`. Empty when the texts are identical.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff_ops(&old_lines, &new_lines);
+    if ops.iter().all(|op| *op == DiffOp::Equal) {
+        return String::new();
+    }
+
+    // Line indices in each text before every op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_index, mut new_index) = (0, 0);
+    for op in &ops {
+        positions.push((old_index, new_index));
+        if *op != DiffOp::Insert {
+            old_index += 1;
+        }
+        if *op != DiffOp::Delete {
+            new_index += 1;
+        }
+    }
+    positions.push((old_index, new_index));
+
+    // Op ranges of the hunks: each change plus its context, merged when they touch
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, op) in ops.iter().enumerate() {
+        if *op == DiffOp::Equal {
+            continue;
+        }
+        let start = index.saturating_sub(DIFF_CONTEXT);
+        let end = (index + 1 + DIFF_CONTEXT).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = format!("--- {old_label}\n+++ {new_label}\n");
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let range = |start: usize, count: usize| match count {
+            0 => format!("{start},0"),
+            _ => format!("{},{count}", start + 1),
+        };
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        ));
+        for index in start..end {
+            let (old_index, new_index) = positions[index];
+            let line = match ops[index] {
+                DiffOp::Equal => format!(" {}", old_lines[old_index]),
+                DiffOp::Delete => format!("-{}", old_lines[old_index]),
+                DiffOp::Insert => format!("+{}", new_lines[new_index]),
+            };
+            diff.push_str(&line);
+            diff.push('\n');
+        }
+    }
+    diff
+}
+
+/// Edit script turning `old` into `new`, from the longest common subsequence
+/// of the region between their common prefix and suffix.
+fn diff_ops(old: &[&str], new: &[&str]) -> Vec<DiffOp> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![DiffOp::Equal; prefix];
+    let (rows, columns) = (old_middle.len(), new_middle.len());
+    if (rows + 1) * (columns + 1) > DIFF_MAX_CELLS {
+        ops.resize(ops.len() + rows, DiffOp::Delete);
+        ops.resize(ops.len() + columns, DiffOp::Insert);
+    } else {
+        // common[i][j]: length of the LCS of old_middle[i..] and new_middle[j..]
+        let width = columns + 1;
+        let mut common = vec![0u32; (rows + 1) * width];
+        for i in (0..rows).rev() {
+            for j in (0..columns).rev() {
+                common[i * width + j] = if old_middle[i] == new_middle[j] {
+                    common[(i + 1) * width + j + 1] + 1
+                } else {
+                    common[(i + 1) * width + j].max(common[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < rows || j < columns {
+            if i < rows && j < columns && old_middle[i] == new_middle[j] {
+                ops.push(DiffOp::Equal);
+                (i, j) = (i + 1, j + 1);
+            } else if j == columns
+                || (i < rows && common[(i + 1) * width + j] >= common[i * width + j + 1])
+            {
+                ops.push(DiffOp::Delete);
+                i += 1;
+            } else {
+                ops.push(DiffOp::Insert);
+                j += 1;
+            }
+        }
+    }
+    ops.resize(ops.len() + suffix, DiffOp::Equal);
+    ops
+}
+
+/// ASCII stand-ins for the emoji and symbols in iz's messages, used by `--no-emoji`.
+pub const ASCII_TAGS: &[(&str, &str)] = &[
+    ("\u{26A0}", "[warn]"),
+    ("✅", "[ok]"),
+    ("❌", "[error]"),
+    ("🚀", "[run]"),
+    ("🔄", "[start]"),
+    ("🔁", "[rerun]"),
+    ("🎯", "[commit]"),
+    ("💬", "[subject]"),
+    ("👤", "[author]"),
+    ("🧭", "[head]"),
+    ("📝", "[command]"),
+    ("📁", "[dir]"),
+    ("📄", "[output]"),
+    ("📦", "[checkout]"),
+    ("🧹", "[clean]"),
+    ("✨", "[clean]"),
+    ("💾", "[kept]"),
+    ("📋", "[list]"),
+    ("🔍", "[check]"),
+    ("🔎", "[info]"),
+    ("📊", "[result]"),
+    ("🎉", "[done]"),
+    ("🚫", "[cancel]"),
+    ("❓", "[?]"),
+    ("🛑", "[stop]"),
+    ("🪝", "[hook]"),
+    ("🙈", "[ignore]"),
+    ("✏", "[changed]"),
+    ("🔀", "[moved]"),
+    ("🏷", "[tag]"),
+    ("👀", "[watch]"),
+    ("👋", "[bye]"),
+    ("🐚", "[shell]"),
+    ("\u{23F1}", "[bench]"),
+    ("⏎", "[enter]"),
+    ("→", "->"),
+    ("•", "*"),
+];
+
+/// Replaces the emoji and symbols listed in [`ASCII_TAGS`] with their tags.
+/// Spaces after an emoji collapse to one, so `"⚠️  Warning"` becomes
+/// `"[warn] Warning"`. Anything else, such as command output, is kept as is.
+pub fn ascii_decorations(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    'scan: while let Some(c) = rest.chars().next() {
+        if !c.is_ascii() {
+            for (symbol, tag) in ASCII_TAGS {
+                let Some(after) = rest.strip_prefix(symbol) else {
+                    continue;
+                };
+                let after = after.strip_prefix('\u{FE0F}').unwrap_or(after);
+                let trimmed = after.trim_start_matches(' ');
+                result.push_str(tag);
+                if trimmed.len() != after.len() {
+                    result.push(' ');
+                }
+                rest = trimmed;
+                continue 'scan;
+            }
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    result
+}
+
+pub fn truncation_marker(limit: usize) -> String {
+    format!("... (output truncated at {limit} bytes)")
+}
+
+#[derive(Debug)]
+pub struct CommandOutput {
+    pub status: std::process::ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl CommandOutput {
+    pub fn stdout_text(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    pub fn stderr_text(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+/// Machine-readable summary of a run, printed with `--output json`.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct RunResult {
+    pub commit: String,
+    pub commit_summary: String,
+    pub commit_author: String,
+    /// The branch `HEAD` pointed at (or `detached`) when the revision was relative to `HEAD`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_ref: Option<String>,
+    pub command: String,
+    pub final_command: String,
+    /// The program and arguments actually executed.
+    pub argv: Vec<String>,
+    pub temp_dir: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    /// Set to `base64` when `stdout` holds encoded raw bytes (`--binary-output`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_encoding: Option<String>,
+    pub stderr: String,
+    /// Symlinks left out of the checkout (`--symlink-mode skip` or missing targets).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_symlinks: Vec<String>,
+    /// Outcome of the `--after` verification command, when one ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<AfterResult>,
+    /// Size summary of the checkout, with `--stats`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_stats: Option<TreeStats>,
+}
+
+/// Outcome of the verification command run with `--after`, kept apart from the main command's.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct AfterResult {
+    pub command: String,
+    pub final_command: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Timing summary of `iz bench`, in milliseconds.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct BenchStats {
+    pub runs: usize,
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+    /// Sample standard deviation; 0 for a single run.
+    pub stddev_ms: f64,
+}
+
+impl BenchStats {
+    pub fn from_durations(durations_ms: &[f64]) -> Option<BenchStats> {
+        if durations_ms.is_empty() {
+            return None;
+        }
+
+        let runs = durations_ms.len();
+        let mean_ms = durations_ms.iter().sum::<f64>() / runs as f64;
+        let variance = match runs {
+            1 => 0.0,
+            _ => {
+                durations_ms
+                    .iter()
+                    .map(|d| (d - mean_ms).powi(2))
+                    .sum::<f64>()
+                    / (runs - 1) as f64
+            }
+        };
+
+        Some(BenchStats {
+            runs,
+            min_ms: durations_ms.iter().copied().fold(f64::INFINITY, f64::min),
+            mean_ms,
+            max_ms: durations_ms
+                .iter()
+                .copied()
+                .fold(f64::NEG_INFINITY, f64::max),
+            stddev_ms: variance.sqrt(),
+        })
+    }
+}
+
+/// Summary of one or more runs, written with `--report`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Default)]
+pub struct RunReport {
+    pub runs: Vec<RunReportEntry>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default)]
+pub struct RunReportEntry {
+    pub commit: String,
+    pub subject: String,
+    pub command: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// Captured stdout, kept for `iz diff` rather than written to reports.
+    #[serde(skip)]
+    pub stdout: Vec<u8>,
+    /// Whether the command ran; a failed checkout or pre hook stops the run
+    /// before it.
+    #[serde(skip)]
+    pub executed: bool,
+    /// The run's `--output json` result, kept for `iz diff` to nest in its
+    /// own document instead of being printed.
+    #[serde(skip)]
+    pub json_result: Option<serde_json::Value>,
+}
+
+/// Totals printed at the end of a batch of runs, such as `--stdin-commits`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub passed: usize,
+    /// Runs whose command ran and failed.
+    pub failed: usize,
+    /// Runs that failed before their command ran.
+    pub skipped: usize,
+    /// Wall-clock time of the whole batch, so parallel runs overlap.
+    pub duration_ms: u64,
+}
+
+impl BatchSummary {
+    pub fn record(&mut self, run: &RunReportEntry) {
+        self.total += 1;
+        match (run.success, run.executed) {
+            (true, _) => self.passed += 1,
+            (false, true) => self.failed += 1,
+            (false, false) => self.skipped += 1,
+        }
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.passed == self.total
+    }
+}
+
+/// The first run whose result differs from the run before it, found by
+/// [`first_output_change`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OutputChange {
+    /// Index of the changed run; the previous run is `index - 1`.
+    pub index: usize,
+    pub exit_code_changed: bool,
+    pub stdout_changed: bool,
+}
+
+/// Compares each run with the one before it by exit code and a hash of its
+/// stdout, and returns the first that differs.
+pub fn first_output_change(runs: &[RunReportEntry]) -> Option<OutputChange> {
+    use std::hash::{Hash, Hasher};
+
+    let fingerprints: Vec<(Option<i32>, u64)> = runs
+        .iter()
+        .map(|run| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            run.stdout.hash(&mut hasher);
+            (run.exit_code, hasher.finish())
+        })
+        .collect();
+
+    fingerprints
+        .windows(2)
+        .position(|pair| pair[0] != pair[1])
+        .map(|position| OutputChange {
+            index: position + 1,
+            exit_code_changed: fingerprints[position].0 != fingerprints[position + 1].0,
+            stdout_changed: fingerprints[position].1 != fingerprints[position + 1].1,
+        })
+}
+
+impl RunReport {
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from(
+            "| Commit | Subject | Command | Result | Exit code | Duration |\n\
+             | --- | --- | --- | --- | --- | --- |\n",
+        );
+
+        for run in &self.runs {
+            markdown.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {:.2}s |\n",
+                run.commit,
+                run.subject.replace('|', "\\|"),
+                run.command,
+                if run.success {
+                    "✅ passed"
+                } else {
+                    "❌ failed"
+                },
+                run.exit_code
+                    .map(|code| code.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                run.duration_ms as f64 / 1000.0
+            ));
+        }
+
+        markdown
+    }
+}
+
+/// The stage of a run an error originated from.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Config,
+    Checkout,
+    Execute,
+    Cleanup,
+}
+
+/// Wraps an error with the phase it occurred in. Displays exactly like the
+/// wrapped error so human-readable output is unaffected.
+#[derive(Debug)]
+pub struct PhaseError {
+    pub phase: Phase,
+    inner: anyhow::Error,
+}
+
+impl std::fmt::Display for PhaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.inner, f)
+    }
+}
+
+impl std::error::Error for PhaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+pub trait PhaseContext<T> {
+    fn phase(self, phase: Phase) -> Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> PhaseContext<T> for Result<T, E> {
+    fn phase(self, phase: Phase) -> Result<T> {
+        self.map_err(|inner| {
+            let inner = inner.into();
+            if inner.downcast_ref::<PhaseError>().is_some() {
+                inner
+            } else {
+                PhaseError { phase, inner }.into()
+            }
+        })
+    }
+}
+
+pub fn error_phase(error: &anyhow::Error) -> Option<Phase> {
+    error
+        .downcast_ref::<PhaseError>()
+        .map(|phase_error| phase_error.phase)
+}
+
+/// The only `--spec` format version so far.
+pub const RUN_SPEC_VERSION: u32 = 1;
+
+/// A whole invocation as one JSON object, given with `--spec` or `--spec-file`.
+/// Set fields override the matching flags; `params` and `env` are merged
+/// into the flag values, with the spec winning on duplicate keys.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RunSpec {
+    /// Format version, so the layout can change without breaking callers.
+    #[serde(default = "default_run_spec_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Scalars act like `--param`, arrays and objects like `--param-json`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub params: HashMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<bool>,
+    /// Same format as `--timeout`, e.g. `"30s"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+}
+
+fn default_run_spec_version() -> u32 {
+    RUN_SPEC_VERSION
+}
+
+impl RunSpec {
+    pub fn from_json(json: &str) -> Result<RunSpec> {
+        let spec: RunSpec = serde_json::from_str(json).context("Invalid run spec")?;
+        if spec.version != RUN_SPEC_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported run spec version {} (expected {})",
+                spec.version,
+                RUN_SPEC_VERSION
+            ));
+        }
+        Ok(spec)
+    }
+
+    /// Scalar `params` as `--param` values, sorted by name.
+    pub fn plain_params(&self) -> Vec<(String, String)> {
+        let mut params: Vec<(String, String)> = self
+            .params
+            .iter()
+            .filter_map(|(name, value)| match value {
+                serde_json::Value::String(value) => Some((name.clone(), value.clone())),
+                serde_json::Value::Array(_) | serde_json::Value::Object(_) => None,
+                scalar => Some((name.clone(), scalar.to_string())),
+            })
+            .collect();
+        params.sort();
+        params
+    }
+
+    /// Array and object `params` as `--param-json` values, sorted by name.
+    pub fn json_params(&self) -> Vec<(String, serde_json::Value)> {
+        let mut params: Vec<(String, serde_json::Value)> = self
+            .params
+            .iter()
+            .filter(|(_, value)| value.is_array() || value.is_object())
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        params.sort_by(|a, b| a.0.cmp(&b.0));
+        params
+    }
+}
+
+pub fn parse_key_val(
+    s: &str,
+) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let pos = s
+        .find('=')
+        .ok_or_else(|| format!("Invalid KEY=value format: {s}"))?;
+    Ok((s[..pos].to_string(), s[pos + 1..].to_string()))
+}
+
+/// Parses a duration such as `30`, `30s`, `500ms`, `5m`, `1h` or `7d`. A bare
+/// number is seconds.
+pub fn parse_duration(
+    s: &str,
+) -> Result<std::time::Duration, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{s}': expected e.g. 30s, 500ms, 5m, 1h or 7d"))?;
+
+    let seconds_per_unit = match unit {
+        "ms" => return Ok(std::time::Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => {
+            return Err(
+                format!("Invalid duration unit '{unit}' in '{s}': use ms, s, m, h or d").into(),
+            )
+        }
+    };
+    let seconds = number
+        .checked_mul(seconds_per_unit)
+        .ok_or_else(|| format!("Duration '{s}' is too large"))?;
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// A `--keep` value: `true`/`false`, or how long a kept directory lives
+/// before a later run removes it (`--keep=1h`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeepSetting {
+    Flag(bool),
+    For(std::time::Duration),
+}
+
+impl KeepSetting {
+    pub fn keeps(self) -> bool {
+        !matches!(self, KeepSetting::Flag(false))
+    }
+
+    pub fn duration(self) -> Option<std::time::Duration> {
+        match self {
+            KeepSetting::For(duration) => Some(duration),
+            KeepSetting::Flag(_) => None,
+        }
+    }
+}
+
+/// Parses `--keep`: `true`, `false` or a duration as accepted by [`parse_duration`].
+pub fn parse_keep(
+    s: &str,
+) -> Result<KeepSetting, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    match s.trim() {
+        "true" => Ok(KeepSetting::Flag(true)),
+        "false" => Ok(KeepSetting::Flag(false)),
+        other => parse_duration(other)
+            .map(KeepSetting::For)
+            .map_err(|_| format!("Invalid --keep value '{other}': expected true, false or a duration such as 30m or 1h").into()),
+    }
+}
+
+/// Stderr lines that fail a run under `--fail-on-stderr`: every non-blank
+/// line no `allowlist` pattern matches.
+pub fn unexpected_stderr_lines<'a>(stderr: &'a str, allowlist: &[Regex]) -> Vec<&'a str> {
+    stderr
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| !allowlist.iter().any(|regex| regex.is_match(line)))
+        .collect()
+}
+
+/// Shown in place of secret values.
+pub const SECRET_MASK: &str = "****";
+
+/// Replaces every occurrence of a secret value in `text` with [`SECRET_MASK`].
+/// Longer secrets are masked first so one containing another is fully hidden.
+pub fn mask_secrets(text: &str, secrets: &[String]) -> String {
+    let mut secrets: Vec<&String> = secrets.iter().filter(|s| !s.is_empty()).collect();
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+    secrets
+        .into_iter()
+        .fold(text.to_string(), |masked, secret| {
+            masked.replace(secret.as_str(), SECRET_MASK)
+        })
+}
+
+/// Whether a changed path should trigger a `--watch` re-run. Changes inside
+/// `.git` only count when a ref or `HEAD` moves; paths under `ignored`
+/// (such as the temporary directory base) never count.
+pub fn is_watch_relevant(
+    path: &std::path::Path,
+    git_dir: &std::path::Path,
+    ignored: &[std::path::PathBuf],
+) -> bool {
+    if ignored.iter().any(|dir| path.starts_with(dir)) {
+        return false;
+    }
+
+    match path.strip_prefix(git_dir) {
+        Ok(inside) => {
+            inside.starts_with("refs")
+                || inside == std::path::Path::new("HEAD")
+                || inside == std::path::Path::new("packed-refs")
+        }
+        Err(_) => true,
+    }
+}
+
+/// Commit value that stands for the most recent tag (also `--since-tag`).
+pub const LATEST_TAG_SPEC: &str = "@latest-tag";
+
+/// The index in `stash@{N}`; `stash` alone is the newest entry, like in git.
+pub fn parse_stash_ref(spec: &str) -> Option<usize> {
+    if spec == "stash" {
+        return Some(0);
+    }
+    spec.strip_prefix("stash@{")?
+        .strip_suffix('}')?
+        .parse()
+        .ok()
+}
+
+/// Parses a `--run-id`, which becomes part of a directory name.
+pub fn parse_run_id(s: &str) -> Result<String, String> {
+    let valid = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !s.starts_with('.');
+    if !valid {
+        return Err(format!(
+            "Invalid run ID '{s}': use letters, digits, '-', '_' or '.'"
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// Parses a `KEY=JSON` pair for `--param-json`.
+pub fn parse_key_json(
+    s: &str,
+) -> Result<(String, serde_json::Value), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let (key, value) = parse_key_val(s)?;
+    let value = serde_json::from_str(&value).map_err(|e| format!("Invalid JSON for {key}: {e}"))?;
+    Ok((key, value))
+}
+
+/// Matches `#{name}` and `#{name.path.to.leaf}` placeholders.
+const PLACEHOLDER_PATTERN: &str = r"#\{(\w+(?:\.\w+)*)\}";
+
+pub fn substitute_variables(
+    template: &str,
+    params: &HashMap<String, String>,
+) -> Result<String, IzError> {
+    substitute_variables_with_json(template, params, &HashMap::new())
+}
+
+/// Like [`substitute_variables`], but `#{key.path.to.leaf}` placeholders are also
+/// resolved against structured `--param-json` values. Array elements are
+/// addressed by index, e.g. `#{hosts.0}`.
+pub fn substitute_variables_with_json(
+    template: &str,
+    params: &HashMap<String, String>,
+    json_params: &HashMap<String, serde_json::Value>,
+) -> Result<String, IzError> {
+    let re = Regex::new(PLACEHOLDER_PATTERN).unwrap();
+    let mut result = template.to_string();
+
+    for caps in re.captures_iter(template) {
+        let var_name = &caps[1];
+        let full_match = &caps[0];
+
+        let value = match params.get(var_name) {
+            Some(value) => value.clone(),
+            None => resolve_json_path(var_name, json_params)?,
+        };
+        result = result.replace(full_match, &value);
+    }
+
+    Ok(result)
+}
+
+/// Matches `#{file:path}` placeholders, which name a file in the checkout.
+const FILE_PLACEHOLDER_PATTERN: &str = r"#\{file:([^}]+)\}";
+
+/// Replaces `#{file:path}` placeholders with `path` joined to the (absolute)
+/// `checkout` directory. The checkout only exists once the commit is checked
+/// out, so this runs as a second pass after [`substitute_variables`]. The
+/// path must be relative, stay inside the checkout and exist there.
+pub fn substitute_file_paths(
+    template: &str,
+    checkout: &std::path::Path,
+) -> Result<String, IzError> {
+    let re = Regex::new(FILE_PLACEHOLDER_PATTERN).unwrap();
+    let mut result = template.to_string();
+
+    for caps in re.captures_iter(template) {
+        let relative = caps[1].trim();
+        let inside = std::path::Path::new(relative)
+            .components()
+            .all(|component| {
+                matches!(
+                    component,
+                    std::path::Component::Normal(_) | std::path::Component::CurDir
+                )
+            });
+        if !inside {
+            return Err(IzError::InvalidFilePath(relative.to_string()));
+        }
+        let path = checkout.join(relative);
+        if !path.exists() {
+            return Err(IzError::MissingCheckoutFile(relative.to_string()));
+        }
+        result = result.replace(&caps[0], &path.display().to_string());
+    }
+
+    Ok(result)
+}
+
+/// Names of the placeholders in `template`, deduplicated in order of first appearance.
+pub fn template_placeholders(template: &str) -> Vec<String> {
+    let re = Regex::new(PLACEHOLDER_PATTERN).unwrap();
+    let mut names: Vec<String> = Vec::new();
+    for caps in re.captures_iter(template) {
+        if !names.iter().any(|name| name == &caps[1]) {
+            names.push(caps[1].to_string());
+        }
+    }
+    names
+}
+
+/// A parameter a command takes, as listed by `--list-params`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ParamInfo {
+    pub name: String,
+    /// Value preset by an alias, used unless `--param` overrides it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+/// Every parameter a resolved command references: declared params first, then
+/// placeholders in the template and its hooks. Generated templates aren't
+/// known until the generator runs, so only their declared params are listed.
+pub fn command_params(resolved: &ResolvedCommand) -> Vec<ParamInfo> {
+    let entry = resolved.entry;
+    let mut names: Vec<String> = entry.declared_params().to_vec();
+    let mut templates = vec![entry.template()];
+    templates.extend(entry.argv().unwrap_or_default().iter().map(String::as_str));
+    templates.extend(entry.pre_hook());
+    templates.extend(entry.post_hook());
+    for template in templates {
+        for name in template_placeholders(template) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| ParamInfo {
+            default: resolved.params.get(&name).cloned(),
+            name,
+        })
+        .collect()
+}
+
+fn resolve_json_path(
+    path: &str,
+    json_params: &HashMap<String, serde_json::Value>,
+) -> Result<String, IzError> {
+    let mut segments = path.split('.');
+    let root = segments.next().unwrap_or_default();
+    let missing = || IzError::MissingParam(path.to_string());
+    let mut value = json_params.get(root).ok_or_else(missing)?;
+
+    for segment in segments {
+        let next = match value {
+            serde_json::Value::Object(map) => map.get(segment),
+            serde_json::Value::Array(items) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get(index)),
+            _ => None,
+        };
+        value = next.ok_or_else(missing)?;
+    }
+
+    match value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Object(_) | serde_json::Value::Array(_) => {
+            Err(IzError::NonScalarParam(path.to_string()))
+        }
+        scalar => Ok(scalar.to_string()),
+    }
+}
+
+/// Whether an exit code counts as success. A missing code (killed by a signal)
+/// never does; without a configured list only `0` is accepted.
+pub fn is_success_code(code: Option<i32>, success_codes: Option<&[i32]>) -> bool {
+    match (code, success_codes) {
+        (Some(code), Some(codes)) => codes.contains(&code),
+        (Some(code), None) => code == 0,
+        (None, _) => false,
+    }
+}
+
+/// Checks provided parameter values against their declared types. Parameters
+/// that weren't provided are left to [`check_declared_params`].
+pub fn check_param_types(
+    types: &HashMap<String, ParamType>,
+    params: &HashMap<String, String>,
+) -> Result<(), IzError> {
+    let mut names: Vec<&String> = types.keys().collect();
+    names.sort();
+
+    for name in names {
+        let param_type = types[name];
+        if let Some(value) = params.get(name) {
+            if !param_type.accepts(value) {
+                return Err(IzError::InvalidParamType {
+                    name: name.clone(),
+                    expected: param_type,
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn check_declared_params(
+    declared: &[String],
+    params: &HashMap<String, String>,
+) -> Result<(), IzError> {
+    let missing: Vec<String> = declared
+        .iter()
+        .filter(|name| !params.contains_key(*name))
+        .cloned()
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(IzError::MissingDeclaredParams(missing));
+    }
+
+    Ok(())
+}
+
+/// Splits a substituted command into the program and argument vector that is
+/// passed to `Command`.
+pub fn build_argv(command: &str) -> Result<Vec<String>> {
+    let argv: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+    if argv.is_empty() {
+        return Err(anyhow::anyhow!("Empty command"));
+    }
+    Ok(argv)
+}
+
+/// Quotes `arg` as a single word for the shell that runs shell-mode commands
+/// (`sh` on Unix, `cmd` on Windows). Plain words are left as they are.
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else if cfg!(windows) {
+        format!("\"{}\"", arg.replace('"', "\"\""))
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Shows an argv as a command line, quoting the arguments that need it.
+pub fn join_argv(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Substitutes each element of an argv-form command on its own, so values
+/// with spaces stay single arguments. Only an empty program is an error.
+pub fn substitute_argv(
+    argv: &[String],
+    substitute: impl Fn(&str) -> Result<String, IzError>,
+) -> Result<Vec<String>> {
+    let argv = argv
+        .iter()
+        .map(|arg| substitute(arg))
+        .collect::<Result<Vec<String>, IzError>>()?;
+    match argv.first() {
+        Some(program) if !program.trim().is_empty() => Ok(argv),
+        Some(_) => Err(anyhow::anyhow!(
+            "The program name is empty after substitution"
+        )),
+        None => Err(anyhow::anyhow!("Empty command")),
+    }
+}
+
+/// Appends extra arguments to a substituted command line, quoted so the
+/// shell sees each one as a single word.
+pub fn append_args(command: &str, args: &[String]) -> String {
+    args.iter().fold(command.to_string(), |command, arg| {
+        format!("{command} {}", shell_quote(arg))
+    })
+}
+
+/// Fails when the program position of `template` (e.g. `#{runner} test`)
+/// substitutes to nothing. [`build_argv`] would otherwise silently promote the
+/// first argument to the executable.
+pub fn check_program_substituted(
+    template: &str,
+    substitute: impl Fn(&str) -> Result<String, IzError>,
+) -> Result<()> {
+    let Some(program) = template.split_whitespace().next() else {
+        return Ok(());
+    };
+    if !program.contains("#{") {
+        return Ok(());
+    }
+    if substitute(program)?.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "The program name '{program}' is empty after substitution"
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_allowed_command(program: &str, allowed_commands: Option<&[String]>) -> Result<()> {
+    if let Some(allowed) = allowed_commands {
+        if !allowed.iter().any(|name| name == program) {
+            return Err(anyhow::anyhow!(
+                "Command '{}' is not in allowed_commands",
+                program
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The error for a temporary directory that couldn't be created at `path`. A
+/// permission problem gets a hint to choose another location, since retrying
+/// won't help.
+pub fn create_dir_error(error: std::io::Error, path: &std::path::Path) -> anyhow::Error {
+    if error.kind() == std::io::ErrorKind::PermissionDenied {
+        return anyhow::anyhow!(
+            "Permission denied creating temp directory at {}; choose a writable location with --temp-dir or IZTEMP",
+            path.display()
+        );
+    }
+    anyhow::Error::new(error).context(format!(
+        "Failed to create temporary directory: {}",
+        path.display()
+    ))
+}
+
+/// Wraps a failure to remove a temporary directory, pointing out permission problems.
+pub fn remove_dir_error(error: std::io::Error, path: &std::path::Path) -> anyhow::Error {
+    if error.kind() == std::io::ErrorKind::PermissionDenied {
+        return anyhow::anyhow!(
+            "Permission denied removing temp directory at {}; fix its permissions or remove it manually",
+            path.display()
+        );
+    }
+    anyhow::Error::new(error).context(format!(
+        "Failed to remove temporary directory: {}",
+        path.display()
+    ))
+}
+
+/// Wraps a failure to start `program`, telling a missing program apart from
+/// one that isn't executable.
+pub fn spawn_error(error: std::io::Error, program: &str) -> anyhow::Error {
+    let is_path = program.contains(std::path::MAIN_SEPARATOR) || program.contains('/');
+    match error.kind() {
+        std::io::ErrorKind::NotFound if is_path => {
+            anyhow::anyhow!("Command not found: {program}. Does the file exist in the checkout?")
+        }
+        std::io::ErrorKind::NotFound => {
+            anyhow::anyhow!("Command not found: {program}. Is it installed and on PATH?")
+        }
+        std::io::ErrorKind::PermissionDenied => {
+            anyhow::anyhow!("Permission denied executing {program}. Is it executable (chmod +x)?")
+        }
+        _ => anyhow::Error::new(error).context(format!("Failed to execute {program}")),
+    }
+}
+
+/// Atomically creates a new directory under `base`, asking `next_name` for a fresh
+/// name whenever the previous one already exists.
+pub fn create_dir_with_retries(
+    base: &std::path::Path,
+    attempts: usize,
+    mut next_name: impl FnMut() -> String,
+) -> Result<std::path::PathBuf> {
+    for _ in 0..attempts {
+        let path = base.join(next_name());
+        match std::fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(create_dir_error(e, &path)),
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "Failed to create a unique temporary directory in {} after {} attempts",
+        base.display(),
+        attempts
+    ))
+}
+
+pub fn read_commit_file(path: &std::path::Path) -> Result<String> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read commit file: {}", path.display()))?;
+
+    let commit_id = content.trim();
+    if commit_id.is_empty() {
+        return Err(anyhow::anyhow!("Commit file is empty: {}", path.display()));
+    }
+
+    Ok(commit_id.to_string())
+}
+
+/// Rewrites the line endings of a text file to LF, or CRLF when `crlf` is set.
+/// Returns `None` for binary content (a NUL byte in the first 8000 bytes, as
+/// git checks) or when nothing would change.
+pub fn convert_line_endings(content: &[u8], crlf: bool) -> Option<Vec<u8>> {
+    if content.iter().take(8000).any(|&byte| byte == 0) {
+        return None;
+    }
+
+    let mut converted = Vec::with_capacity(content.len());
+    for (index, &byte) in content.iter().enumerate() {
+        match byte {
+            b'\r' if content.get(index + 1) == Some(&b'\n') => {}
+            b'\n' if crlf => converted.extend_from_slice(b"\r\n"),
+            _ => converted.push(byte),
+        }
+    }
+
+    (converted != content).then_some(converted)
+}
+
+/// Parses a commit list file: one commit per line, skipping blank lines and
+/// `#` comments (whole-line or trailing). Returns each commit with its line number.
+pub fn parse_commit_list(content: &str) -> Vec<(usize, String)> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let commit = line.split('#').next().unwrap_or("").trim();
+            (!commit.is_empty()).then(|| (index + 1, commit.to_string()))
+        })
+        .collect()
+}
+
+/// Parses `.env` style `KEY=value` lines, skipping blanks and `#` comments.
+/// Values wrapped in matching single or double quotes are unquoted.
+pub fn parse_env_file(content: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) =
+            parse_key_val(line).map_err(|e| anyhow::anyhow!("Line {}: {}", index + 1, e))?;
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|v| v.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+
+        vars.push((key.trim().to_string(), value.to_string()));
+    }
+
+    Ok(vars)
+}
+
+pub fn read_env_file(path: &std::path::Path) -> Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read env file: {}", path.display()))?;
+
+    parse_env_file(&content).with_context(|| format!("Invalid env file: {}", path.display()))
+}
+
+/// Project kinds recognized by their marker files, used to tailor the example
+/// config shown when none exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectType {
+    Cargo,
+    Node,
+    Go,
+    Python,
+    Dotnet,
+}
+
+impl ProjectType {
+    /// Detects the project in `dir` from its marker files, falling back to `Dotnet`.
+    pub fn detect(dir: &std::path::Path) -> Self {
+        let markers: &[(&str, ProjectType)] = &[
+            ("Cargo.toml", ProjectType::Cargo),
+            ("package.json", ProjectType::Node),
+            ("go.mod", ProjectType::Go),
+            ("pyproject.toml", ProjectType::Python),
+            ("setup.py", ProjectType::Python),
+        ];
+        markers
+            .iter()
+            .find(|(file, _)| dir.join(file).exists())
+            .map_or(ProjectType::Dotnet, |(_, project)| *project)
+    }
+
+    /// Example `run`, `build` and `test` commands for this kind of project.
+    pub fn example_commands(self) -> [(&'static str, &'static str); 3] {
+        match self {
+            ProjectType::Cargo => [
+                ("run", "cargo run"),
+                ("build", "cargo build"),
+                ("test", "cargo test"),
+            ],
+            ProjectType::Node => [
+                ("run", "npm start"),
+                ("build", "npm run build"),
+                ("test", "npm test"),
+            ],
+            ProjectType::Go => [
+                ("run", "go run ."),
+                ("build", "go build ./..."),
+                ("test", "go test ./..."),
+            ],
+            ProjectType::Python => [
+                ("run", "python main.py"),
+                ("build", "python -m build"),
+                ("test", "python -m pytest"),
+            ],
+            ProjectType::Dotnet => [
+                ("run", "dotnet run"),
+                ("build", "dotnet build"),
+                ("test", "dotnet test"),
+            ],
+        }
+    }
+}
+
+pub fn read_config_from_path(config_path: &std::path::Path) -> Result<IzConfig, IzError> {
+    read_config_from_path_as(config_path, ConfigFormat::from_path(config_path))
+}
+
+/// Like [`read_config_from_path`], but parses the file as `format` whatever
+/// its extension (`--config-format`).
+pub fn read_config_from_path_as(
+    config_path: &std::path::Path,
+    format: ConfigFormat,
+) -> Result<IzConfig, IzError> {
+    if !config_path.exists() {
+        let project_dir = match config_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+        let commands = ProjectType::detect(project_dir)
+            .example_commands()
+            .into_iter()
+            .map(|(name, template)| (name.to_string(), template.into()))
+            .collect();
+        let example = IzConfig {
+            commands,
+            temp_dir: Some(".iztemp".to_string()),
+            keep: Some(false),
+            ..Default::default()
+        };
+        return Err(IzError::ConfigNotFound {
+            path: config_path.to_path_buf(),
+            example: serde_json::to_string_pretty(&example).unwrap_or_default(),
+        });
+    }
+
+    let file = std::fs::File::open(config_path).map_err(|source| IzError::ConfigRead {
+        path: config_path.to_path_buf(),
+        source,
+    })?;
+    read_config_from_reader(file, format, config_path)
+}
+
+/// Reads a config in `format` from `reader`; `source` names it in errors.
+pub fn read_config_from_reader(
+    mut reader: impl std::io::Read,
+    format: ConfigFormat,
+    source: &std::path::Path,
+) -> Result<IzConfig, IzError> {
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .map_err(|e| IzError::ConfigRead {
+            path: source.to_path_buf(),
+            source: e,
+        })?;
+
+    serde_json::from_value(parse_config_value(&content, format, source)?).map_err(|e| {
+        IzError::ConfigParse {
+            path: source.to_path_buf(),
+            source: e.into(),
+        }
+    })
+}
+
+/// Config file names looked up in the current directory, highest precedence first.
+pub const CONFIG_FILE_NAMES: &[&str] = &["izconfig.json", "izconfig.yaml", "izconfig.yml"];
+
+/// Syntax of a config file, normally guessed from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum ConfigFormat {
+    #[default]
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// YAML for `.yaml`/`.yml`, TOML for `.toml` and JSON otherwise.
+    pub fn from_path(path: &std::path::Path) -> ConfigFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+fn parse_config_value(
+    content: &str,
+    format: ConfigFormat,
+    path: &std::path::Path,
+) -> Result<serde_json::Value, IzError> {
+    let value = match format {
+        ConfigFormat::Json => serde_json::from_str(content).map_err(|e| e.into()),
+        ConfigFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.into()),
+        ConfigFormat::Toml => toml::from_str(content).map_err(|e| e.into()),
+    };
+    value.map_err(|source| IzError::ConfigParse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// The config file to use in `dir`: the first of [`CONFIG_FILE_NAMES`] that
+/// exists, or `izconfig.json` when none does.
+pub fn find_config_file(dir: &std::path::Path) -> std::path::PathBuf {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| dir.join(CONFIG_FILE_NAMES[0]))
+}
+
+/// Lower-precedence config files next to `config_path` that are ignored because it exists.
+pub fn shadowed_config_files(config_path: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let (Some(dir), Some(name)) = (config_path.parent(), config_path.file_name()) else {
+        return Vec::new();
+    };
+    let Some(position) = CONFIG_FILE_NAMES.iter().position(|n| *n == name) else {
+        return Vec::new();
+    };
+    if !config_path.exists() {
+        return Vec::new();
+    }
+
+    CONFIG_FILE_NAMES[position + 1..]
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Metadata file written into every generated temporary directory.
+pub const RUN_META_FILE: &str = "iz-meta.json";
+
+/// What a temporary directory was created for, read back by `iz clean --list`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct RunMeta {
+    pub commit: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    /// Checked out with `--readonly`; write permission is restored before removal.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub readonly: bool,
+    /// Seconds since the Unix epoch after which a later run removes the
+    /// directory (`--keep=<duration>`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Process that is still using the directory; cleared when the run ends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+impl RunMeta {
+    pub fn new(commit: &str, command: Option<&str>) -> RunMeta {
+        RunMeta {
+            commit: commit.to_string(),
+            command: command.map(str::to_string),
+            started_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            readonly: false,
+            expires_at: None,
+            pid: Some(std::process::id()),
+        }
+    }
+
+    /// Stamps the directory to expire `duration` after it was started.
+    pub fn expiring_after(self, duration: Option<std::time::Duration>) -> RunMeta {
+        RunMeta {
+            expires_at: duration.map(|duration| self.started_at.saturating_add(duration.as_secs())),
+            ..self
+        }
+    }
+
+    /// Whether the `--keep=<duration>` of the run has elapsed at `now`
+    /// (seconds since the Unix epoch).
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Matches a command name exactly and a commit by prefix; `None` matches anything.
+    pub fn matches(&self, command: Option<&str>, commit: Option<&str>) -> bool {
+        let command_matches = match command {
+            Some(command) => self.command.as_deref() == Some(command),
+            None => true,
+        };
+        let commit_matches = match commit {
+            Some(commit) => self.commit.starts_with(commit),
+            None => true,
+        };
+        command_matches && commit_matches
+    }
+}
+
+/// Sets or clears write permission on everything below `root` (not `root`
+/// itself, so iz can still write its own files there). Symlinks are skipped.
+#[cfg(unix)]
+pub fn set_tree_readonly(root: &std::path::Path, readonly: bool) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    for entry in std::fs::read_dir(root)? {
+        let path = entry?.path();
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if metadata.file_type().is_symlink() {
+            continue;
+        }
+        if metadata.is_dir() {
+            set_tree_readonly(&path, readonly)?;
+        }
+        let mode = metadata.permissions().mode();
+        let mode = if readonly {
+            mode & !0o222
+        } else {
+            mode | 0o200
+        };
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Makes a `--readonly` checkout writable again so it can be removed or moved.
+/// Directories not created with `--readonly` are left alone.
+pub fn restore_writable(dir: &std::path::Path) -> Result<()> {
+    if !read_run_meta(dir).is_some_and(|meta| meta.readonly) {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    set_tree_readonly(dir, false)
+        .with_context(|| format!("Failed to restore write permission in {}", dir.display()))?;
+    Ok(())
+}
+
+/// Writes the metadata through a temporary file, so it can be replaced even
+/// after `--readonly` removed write permission from the old one.
+pub fn write_run_meta(dir: &std::path::Path, meta: &RunMeta) -> Result<()> {
+    let path = dir.join(RUN_META_FILE);
+    let partial = dir.join(format!("{RUN_META_FILE}.tmp"));
+    std::fs::write(&partial, serde_json::to_string_pretty(meta)?)
+        .and_then(|()| std::fs::rename(&partial, &path))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Reads a directory's metadata; `None` for directories created before it was
+/// written, or whose file is unreadable.
+pub fn read_run_meta(dir: &std::path::Path) -> Option<RunMeta> {
+    let content = std::fs::read_to_string(dir.join(RUN_META_FILE)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// How long ago a temporary directory was created: its metadata's
+/// `started_at`, or the directory's modification time when there is none.
+pub fn run_dir_age(
+    dir: &std::path::Path,
+    now: std::time::SystemTime,
+) -> Option<std::time::Duration> {
+    let started = match read_run_meta(dir) {
+        Some(meta) => std::time::UNIX_EPOCH + std::time::Duration::from_secs(meta.started_at),
+        None => std::fs::metadata(dir).and_then(|m| m.modified()).ok()?,
+    };
+    Some(now.duration_since(started).unwrap_or_default())
+}
+
+/// How many of the biggest files `--stats` lists.
+pub const TREE_STATS_LARGEST: usize = 5;
+
+/// Size summary of a checked-out tree, printed with `--stats`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default)]
+pub struct TreeStats {
+    pub files: usize,
+    pub total_bytes: u64,
+    /// The biggest files, largest first.
+    pub largest: Vec<FileSize>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct FileSize {
+    /// Relative to the tree root, with `/` separators.
+    pub path: String,
+    pub bytes: u64,
+}
+
+/// Counts the files below `root`, keeping the `largest` biggest ones. The
+/// run's [`RUN_META_FILE`] and a `--with-git` `.git` directory aren't part of
+/// the commit and are skipped; symlinks count as files of their own size.
+pub fn tree_stats(root: &std::path::Path, largest: usize) -> std::io::Result<TreeStats> {
+    fn walk(
+        dir: &std::path::Path,
+        prefix: &str,
+        stats: &mut TreeStats,
+        sizes: &mut Vec<FileSize>,
+    ) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if prefix.is_empty() && (name == RUN_META_FILE || name == ".git") {
+                continue;
+            }
+            let path = format!("{prefix}{name}");
+            let metadata = std::fs::symlink_metadata(entry.path())?;
+            if metadata.is_dir() {
+                walk(&entry.path(), &format!("{path}/"), stats, sizes)?;
+                continue;
+            }
+            stats.files += 1;
+            stats.total_bytes += metadata.len();
+            sizes.push(FileSize {
+                path,
+                bytes: metadata.len(),
+            });
+        }
+        Ok(())
+    }
+
+    let mut stats = TreeStats::default();
+    let mut sizes = Vec::new();
+    walk(root, "", &mut stats, &mut sizes)?;
+    // Ties break by path so the listing doesn't depend on directory order
+    sizes.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    sizes.truncate(largest);
+    stats.largest = sizes;
+    Ok(stats)
+}
+
+/// Bytes used by every file below `root`, including ones [`tree_stats`]
+/// skips. Symlinks count as their own size.
+pub fn dir_size(root: &std::path::Path) -> std::io::Result<u64> {
+    let mut total = 0;
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let metadata = std::fs::symlink_metadata(entry.path())?;
+        total += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(total)
+}
+
+/// Formats a byte count with a binary unit, e.g. `512 B` or `1.5 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Name of the symlink in the base temp dir that points at the newest kept run.
+pub const LATEST_LINK: &str = "latest";
+
+/// Written instead of [`LATEST_LINK`] on Windows when symlinks aren't permitted;
+/// holds the path of the newest kept run.
+pub const LATEST_POINTER_FILE: &str = "latest.txt";
+
+/// Points `latest` in `base` at `run_dir`. The link is created under a
+/// temporary name and renamed over the old one, so readers never see it missing.
+pub fn update_latest_link(
+    base: &std::path::Path,
+    run_dir: &std::path::Path,
+) -> std::io::Result<()> {
+    let name = run_dir.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "run directory has no name",
+        )
+    })?;
+    let staged = base.join(format!(".{LATEST_LINK}-{:08x}", rand::random::<u32>()));
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(name, &staged)?;
+        let renamed = std::fs::rename(&staged, base.join(LATEST_LINK));
+        if renamed.is_err() {
+            let _ = std::fs::remove_file(&staged);
+        }
+        renamed
+    }
+
+    #[cfg(windows)]
+    {
+        if std::os::windows::fs::symlink_dir(name, &staged).is_ok() {
+            let link = base.join(LATEST_LINK);
+            // Windows won't rename over an existing directory symlink
+            let _ = std::fs::remove_dir(&link);
+            return std::fs::rename(&staged, link);
+        }
+        std::fs::write(&staged, base.join(name).display().to_string())?;
+        std::fs::rename(&staged, base.join(LATEST_POINTER_FILE))
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (name, staged);
+        Ok(())
+    }
+}
+
+/// After runs were removed from `base`, points `latest` at the newest run
+/// left, or removes it when none is. Does nothing while its target exists.
+pub fn repair_latest_link(base: &std::path::Path) -> std::io::Result<()> {
+    let link = base.join(LATEST_LINK);
+    let pointer = base.join(LATEST_POINTER_FILE);
+    let linked = std::fs::symlink_metadata(&link).is_ok();
+    let target_exists = if linked {
+        link.exists()
+    } else {
+        match std::fs::read_to_string(&pointer) {
+            Ok(target) => std::path::Path::new(target.trim()).exists(),
+            Err(_) => return Ok(()),
+        }
+    };
+    if target_exists {
+        return Ok(());
+    }
+
+    if let Some(newest) = newest_run_dir(base) {
+        return update_latest_link(base, &newest);
+    }
+    if linked {
+        // A directory symlink is removed like a directory on Windows
+        std::fs::remove_file(&link).or_else(|_| std::fs::remove_dir(&link))?;
+    }
+    if pointer.exists() {
+        std::fs::remove_file(&pointer)?;
+    }
+    Ok(())
+}
+
+/// The most recently started `iz-` directory in `base`.
+fn newest_run_dir(base: &std::path::Path) -> Option<std::path::PathBuf> {
+    let now = std::time::SystemTime::now();
+    std::fs::read_dir(base)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_type().is_ok_and(|kind| kind.is_dir())
+                && entry.file_name().to_string_lossy().starts_with("iz-")
+        })
+        .map(|entry| entry.path())
+        .filter_map(|dir| run_dir_age(&dir, now).map(|age| (age, dir)))
+        .min_by_key(|(age, _)| *age)
+        .map(|(_, dir)| dir)
+}
+
+/// Compact age like `42s`, `5m`, `3h` or `2d`.
+pub fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// Name of the folder that `rename` cleanup moves directories into.
+pub const TRASH_DIR_NAME: &str = ".trash";
+
+/// Moves `dir` into a `.trash` folder next to it for lazy deletion and returns
+/// its new location. A rename within one filesystem is much cheaper than
+/// deleting a large tree on slow or busy mounts.
+pub fn rename_to_trash(dir: &std::path::Path) -> Result<std::path::PathBuf> {
+    let parent = dir
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Cannot trash a root directory: {}", dir.display()))?;
+    let name = dir
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid directory name: {}", dir.display()))?;
+
+    let trash_dir = parent.join(TRASH_DIR_NAME);
+    std::fs::create_dir_all(&trash_dir)
+        .with_context(|| format!("Failed to create trash directory: {}", trash_dir.display()))?;
+
+    let destination = trash_dir.join(name);
+    std::fs::rename(dir, &destination).with_context(|| {
+        format!(
+            "Failed to move {} to {}",
+            dir.display(),
+            destination.display()
+        )
+    })?;
+
+    Ok(destination)
+}
+
+/// Where the checkout is mounted inside a `--container` run.
+pub const CONTAINER_WORKDIR: &str = "/work";
+
+/// Wraps `argv` in a `<runtime> run` invocation that mounts `mount_source` at
+/// [`CONTAINER_WORKDIR`] and runs in `workdir` below it. Arguments pointing
+/// inside `host_dir` or `mount_source`, such as shell-mode script files, are
+/// rewritten to their container paths.
+/// Only the names in `env` are passed; the runtime must be started with the
+/// values in its environment, so secrets never show up on its command line.
+pub fn container_argv(
+    runtime: &str,
+    image: &str,
+    host_dir: &std::path::Path,
+    mount_source: &std::path::Path,
+    workdir: Option<&str>,
+    env: &[(String, String)],
+    argv: &[String],
+) -> Vec<String> {
+    let mut wrapped = vec![
+        runtime.to_string(),
+        "run".to_string(),
+        "--rm".to_string(),
+        "-v".to_string(),
+        format!("{}:{CONTAINER_WORKDIR}", mount_source.display()),
+        "-w".to_string(),
+        match workdir {
+            Some(workdir) => format!("{CONTAINER_WORKDIR}/{workdir}"),
+            None => CONTAINER_WORKDIR.to_string(),
+        },
+    ];
+    for (key, _) in env {
+        wrapped.push("-e".to_string());
+        wrapped.push(key.clone());
+    }
+    wrapped.push(image.to_string());
+
+    wrapped.extend(argv.iter().map(|arg| {
+        let path = std::path::Path::new(arg);
+        let relative = [host_dir, mount_source]
+            .into_iter()
+            .filter(|dir| *dir != std::path::Path::new(""))
+            .find_map(|dir| path.strip_prefix(dir).ok());
+        match relative {
+            Some(relative) => format!("{CONTAINER_WORKDIR}/{}", relative.display()),
+            None => arg.clone(),
+        }
+    }));
+    wrapped
+}
+
+/// Checks that a command's `workdir` is a relative path that stays inside
+/// the checkout.
+pub fn check_workdir(workdir: &str) -> Result<()> {
+    let inside = std::path::Path::new(workdir)
+        .components()
+        .all(|component| matches!(component, std::path::Component::Normal(_)));
+    if workdir.is_empty() || !inside {
+        return Err(anyhow::anyhow!(
+            "'{workdir}' must be a relative path inside the checkout, without '.' or '..'"
+        ));
+    }
+    Ok(())
+}
+
+/// The directory below `root` a command with `workdir` runs in.
+pub fn workdir_path(root: &std::path::Path, workdir: Option<&str>) -> Result<std::path::PathBuf> {
+    let Some(workdir) = workdir else {
+        return Ok(root.to_path_buf());
+    };
+    check_workdir(workdir)?;
+    let path = root.join(workdir);
+    if !path.is_dir() {
+        return Err(anyhow::anyhow!(
+            "workdir '{workdir}' is not a directory in the checkout"
+        ));
+    }
+    Ok(path)
+}
+
+/// Command names that collide with `iz` subcommands.
+pub const RESERVED_COMMAND_NAMES: &[&str] =
+    &["bench", "clean", "diff", "list", "validate", "version"];
+
+/// The group of a command named like `db:migrate`: the part before the first
+/// colon. Names without one, or starting with one, are ungrouped.
+pub fn command_group(name: &str) -> Option<&str> {
+    name.split_once(':')
+        .map(|(group, _)| group)
+        .filter(|group| !group.is_empty())
+}
+
+/// Sorts command names into their groups for `iz list --group`: groups in
+/// name order with the ungrouped commands last, names sorted within each.
+pub fn group_command_names<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> Vec<(Option<&'a str>, Vec<&'a str>)> {
+    let mut groups: std::collections::BTreeMap<Option<&str>, Vec<&str>> = Default::default();
+    for name in names {
+        groups.entry(command_group(name)).or_default().push(name);
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    // `None` sorts first in the map; the ungrouped commands go at the end
+    if groups.first().is_some_and(|(group, _)| group.is_none()) {
+        let ungrouped = groups.remove(0);
+        groups.push(ungrouped);
+    }
+    for (_, names) in &mut groups {
+        names.sort();
+    }
+    groups
+}
+
+const CONFIG_FIELDS: &[&str] = &[
+    "commands",
+    "temp_dir",
+    "keep",
+    "allowed_commands",
+    "precreate_dirs",
+    "success_codes",
+    "cleanup_strategy",
+    "container",
+    "system_temp",
+    "auto_clean",
+    "max_temp_bytes",
+    "clean_confirm_default",
+];
+
+const COMMAND_FIELDS: &[&str] = &[
+    "template",
+    "argv",
+    "params",
+    "types",
+    "pre",
+    "post",
+    "ignore_hook_failures",
+    "shell",
+    "generator",
+    "secrets",
+    "alias",
+    "temp_dir",
+    "timeout",
+    "workdir",
+];
+
+/// The fields a [`GeneratorDefinition`] reads; any other command field would
+/// be dropped.
+const GENERATOR_FIELDS: &[&str] = &["generator", "params", "shell"];
+
+/// Command fields `--set` can override: those of a [`CommandDefinition`]. An
+/// [`ArgvDefinition`] has all of them except `template` and `shell`.
+pub const OVERRIDABLE_COMMAND_FIELDS: &[&str] = &[
+    "template",
+    "params",
+    "types",
+    "pre",
+    "post",
+    "ignore_hook_failures",
+    "shell",
+    "secrets",
+    "temp_dir",
+    "timeout",
+    "workdir",
+];
+
+/// One `--set command.<name>.<field>=<value>` override.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandOverride {
+    pub command: String,
+    pub field: String,
+    pub value: String,
+}
+
+/// Parses `command.<name>.<field>=<value>`. The field is the part after the
+/// last dot before `=`, so command names may contain dots.
+pub fn parse_command_override(
+    s: &str,
+) -> Result<CommandOverride, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let usage = || format!("Invalid override '{s}': expected command.<name>.<field>=<value>");
+    let (key, value) = s.split_once('=').ok_or_else(usage)?;
+    let (command, field) = key
+        .strip_prefix("command.")
+        .and_then(|rest| rest.rsplit_once('.'))
+        .filter(|(command, field)| !command.is_empty() && !field.is_empty())
+        .ok_or_else(usage)?;
+    Ok(CommandOverride {
+        command: command.to_string(),
+        field: field.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Applies `--set` overrides in order. A plain template command is turned into
+/// its object form first; argv-form commands keep their form, and generated
+/// commands and aliases can't be overridden.
+/// Values are read as JSON when that fits the field (`true`, `["a"]`) and as a
+/// string otherwise.
+pub fn apply_command_overrides(
+    commands: &mut HashMap<String, CommandEntry>,
+    overrides: &[CommandOverride],
+) -> Result<(), IzError> {
+    for CommandOverride {
+        command,
+        field,
+        value,
+    } in overrides
+    {
+        let invalid = |message: String| IzError::InvalidOverride {
+            command: command.clone(),
+            field: field.clone(),
+            message,
+        };
+        let entry = commands
+            .get_mut(command)
+            .ok_or_else(|| IzError::CommandNotFound(command.clone()))?;
+        if !OVERRIDABLE_COMMAND_FIELDS.contains(&field.as_str()) {
+            return Err(IzError::UnknownCommandField {
+                command: command.clone(),
+                field: field.clone(),
+            });
+        }
+
+        let argv_form = matches!(entry, CommandEntry::Argv(_));
+        let mut object = match &*entry {
+            CommandEntry::Template(template) => serde_json::json!({ "template": template }),
+            CommandEntry::Detailed(definition) => {
+                serde_json::to_value(definition).map_err(|e| invalid(e.to_string()))?
+            }
+            CommandEntry::Argv(definition) => {
+                if ["template", "shell"].contains(&field.as_str()) {
+                    return Err(invalid(format!(
+                        "argv-form commands have no '{field}' field"
+                    )));
+                }
+                serde_json::to_value(definition).map_err(|e| invalid(e.to_string()))?
+            }
+            CommandEntry::Generated(_) | CommandEntry::Alias(_) => {
+                return Err(invalid(
+                    "generators and aliases can't be overridden".to_string(),
+                ))
+            }
+        };
+
+        let candidates = serde_json::from_str(value)
+            .ok()
+            .into_iter()
+            .chain([serde_json::Value::String(value.clone())]);
+        let mut overridden = Err(invalid(format!("'{value}' is not a valid value")));
+        for candidate in candidates {
+            object[field.as_str()] = candidate;
+            let parsed = if argv_form {
+                serde_json::from_value(object.clone()).map(CommandEntry::Argv)
+            } else {
+                serde_json::from_value(object.clone()).map(CommandEntry::Detailed)
+            };
+            match parsed {
+                Ok(parsed) => {
+                    overridden = Ok(parsed);
+                    break;
+                }
+                Err(e) => overridden = Err(invalid(e.to_string())),
+            }
+        }
+        let overridden = overridden?;
+        if let Some(timeout) = overridden.timeout() {
+            parse_duration(timeout).map_err(|e| invalid(e.to_string()))?;
+        }
+        if let Some(workdir) = overridden.workdir() {
+            check_workdir(workdir).map_err(|e| invalid(e.to_string()))?;
+        }
+        *entry = overridden;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found by [`validate_config`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ValidationWarning {
+    pub severity: Severity,
+    /// The command the problem belongs to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    pub message: String,
+}
+
+impl ValidationWarning {
+    fn new(severity: Severity, command: Option<&str>, message: String) -> Self {
+        ValidationWarning {
+            severity,
+            command: command.map(str::to_string),
+            message,
+        }
+    }
+}
+
+/// Reads and deserializes a config file and lints it without running anything.
+/// Unreadable or unparsable files are returned as errors.
+pub fn validate_config(path: &std::path::Path) -> Result<Vec<ValidationWarning>> {
+    validate_config_as(path, ConfigFormat::from_path(path))
+}
+
+/// Like [`validate_config`], but parses the file as `format`.
+pub fn validate_config_as(
+    path: &std::path::Path,
+    format: ConfigFormat,
+) -> Result<Vec<ValidationWarning>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    validate_config_content(&content, format, path)
+}
+
+/// Lints config `content` in `format`; `path` names it in errors.
+pub fn validate_config_content(
+    content: &str,
+    format: ConfigFormat,
+    path: &std::path::Path,
+) -> Result<Vec<ValidationWarning>> {
+    let raw = parse_config_value(content, format, path)?;
+    let config: IzConfig = serde_json::from_value(raw.clone())
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+    let mut warnings = Vec::new();
+
+    if let Some(fields) = raw.as_object() {
+        for key in fields
+            .keys()
+            .filter(|key| !CONFIG_FIELDS.contains(&key.as_str()))
+        {
+            warnings.push(ValidationWarning::new(
+                Severity::Warning,
+                None,
+                format!("Unknown field '{key}'"),
+            ));
+        }
+    }
+
+    if let Some(auto_clean) = &config.auto_clean {
+        if let Err(error) = parse_duration(&auto_clean.older_than) {
+            warnings.push(ValidationWarning::new(
+                Severity::Error,
+                None,
+                format!("Invalid auto_clean.older_than: {error}"),
+            ));
+        }
+    }
+
+    let mut names: Vec<&String> = config.commands.keys().collect();
+    names.sort();
+
+    for name in names {
+        let entry = &config.commands[name];
+
+        if entry.alias().is_some() {
+            if let Err(error) = resolve_command(&config.commands, name) {
+                warnings.push(ValidationWarning::new(
+                    Severity::Error,
+                    Some(name),
+                    error.to_string(),
+                ));
+            }
+        } else if let Some(argv) = entry.argv() {
+            let program = argv
+                .first()
+                .map(|program| program.trim())
+                .unwrap_or_default();
+            if program.is_empty() {
+                warnings.push(ValidationWarning::new(
+                    Severity::Error,
+                    Some(name),
+                    "Command argv is empty or has an empty program".to_string(),
+                ));
+            }
+        } else {
+            match entry.generator() {
+                Some(generator) if generator.trim().is_empty() => {
+                    warnings.push(ValidationWarning::new(
+                        Severity::Error,
+                        Some(name),
+                        "Command generator is empty".to_string(),
+                    ));
+                }
+                Some(_) => {}
+                None if entry.template().trim().is_empty() => {
+                    warnings.push(ValidationWarning::new(
+                        Severity::Error,
+                        Some(name),
+                        "Command template is empty".to_string(),
+                    ));
+                }
+                None => {}
+            }
+        }
+
+        if RESERVED_COMMAND_NAMES.contains(&name.as_str()) {
+            warnings.push(ValidationWarning::new(
+                Severity::Warning,
+                Some(name),
+                format!("'{name}' is a reserved iz subcommand name"),
+            ));
+        }
+
+        let raw_entry = raw.pointer(&format!(
+            "/commands/{}",
+            name.replace('~', "~0").replace('/', "~1")
+        ));
+        if let Some(fields) = raw_entry.and_then(|entry| entry.as_object()) {
+            for key in fields
+                .keys()
+                .filter(|key| !COMMAND_FIELDS.contains(&key.as_str()))
+            {
+                warnings.push(ValidationWarning::new(
+                    Severity::Warning,
+                    Some(name),
+                    format!("Unknown field '{key}'"),
+                ));
+            }
+
+            // Known fields the matched kind of command would silently drop
+            let (kind, ignored): (&str, Vec<&String>) = match entry {
+                CommandEntry::Generated(_) => (
+                    "a generated command",
+                    fields
+                        .keys()
+                        .filter(|key| {
+                            COMMAND_FIELDS.contains(&key.as_str())
+                                && !GENERATOR_FIELDS.contains(&key.as_str())
+                        })
+                        .collect(),
+                ),
+                CommandEntry::Detailed(_) => (
+                    "a template command",
+                    fields.keys().filter(|key| *key == "generator").collect(),
+                ),
+                CommandEntry::Argv(_) => (
+                    "an argv command",
+                    fields.keys().filter(|key| *key == "generator").collect(),
+                ),
+                _ => ("", Vec::new()),
+            };
+            for key in ignored {
+                warnings.push(ValidationWarning::new(
+                    Severity::Error,
+                    Some(name),
+                    format!("Field '{key}' is not supported on {kind}"),
+                ));
+            }
+        }
+
+        if let Some(timeout) = entry.timeout() {
+            if let Err(error) = parse_duration(timeout) {
+                warnings.push(ValidationWarning::new(
+                    Severity::Error,
+                    Some(name),
+                    format!("Invalid timeout: {error}"),
+                ));
+            }
+        }
+
+        if let Some(workdir) = entry.workdir() {
+            if let Err(error) = check_workdir(workdir) {
+                warnings.push(ValidationWarning::new(
+                    Severity::Error,
+                    Some(name),
+                    format!("Invalid workdir: {error}"),
+                ));
+            }
+        }
+
+        let templates: Option<Vec<&str>> = match entry {
+            CommandEntry::Detailed(definition) => Some(vec![definition.template.as_str()]),
+            CommandEntry::Argv(definition) => {
+                Some(definition.argv.iter().map(String::as_str).collect())
+            }
+            _ => None,
+        };
+        if let Some(mut templates) = templates {
+            templates.extend(entry.pre_hook());
+            templates.extend(entry.post_hook());
+            let params = entry.declared_params();
+            let mut used = Vec::new();
+            for placeholder in templates.into_iter().flat_map(placeholder_names) {
+                if !used.contains(&placeholder) {
+                    used.push(placeholder);
+                }
+            }
+
+            for placeholder in used.iter().filter(|p| !params.contains(p)) {
+                warnings.push(ValidationWarning::new(
+                    Severity::Warning,
+                    Some(name),
+                    format!("Placeholder '#{{{placeholder}}}' is not declared in params"),
+                ));
+            }
+            for param in params.iter().filter(|p| !used.contains(p)) {
+                warnings.push(ValidationWarning::new(
+                    Severity::Warning,
+                    Some(name),
+                    format!("Declared param '{param}' is never used"),
+                ));
+            }
+        }
+    }
+
+    Ok(warnings)
+}
+
+/// Root names of the `#{...}` placeholders in a template.
+fn placeholder_names(template: &str) -> Vec<String> {
+    let re = Regex::new(r"#\{(\w+)(?:\.\w+)*\}").unwrap();
+    re.captures_iter(template)
+        .map(|caps| caps[1].to_string())
+        .collect()
+}
+
+/// Gitignore-style patterns from `.izignore` that exclude paths from checkout.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+    /// Set by [`IgnoreRules::restrict_to`]: the only files kept, and the
+    /// directories containing them.
+    only: Option<(HashSet<String>, HashSet<String>)>,
+}
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+    pub fn parse(content: &str) -> Self {
+        let rules = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let dir_only = pattern.ends_with('/');
+                let pattern = pattern.trim_end_matches('/');
+                // Like .gitignore, a pattern with a slash is relative to the root
+                let anchored = pattern.contains('/');
+                let pattern = pattern.trim_start_matches('/');
+
+                let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+                let regex = Regex::new(&format!("{prefix}{}$", glob_to_regex(pattern)))
+                    .expect("escaped glob is a valid regex");
+
+                IgnoreRule {
+                    regex,
+                    negated,
+                    dir_only,
+                }
+            })
+            .collect();
+
+        IgnoreRules { rules, only: None }
+    }
+
+    /// Additionally excludes every file not in `files`, such as the files
+    /// changed since a base commit.
+    pub fn restrict_to(&mut self, files: impl IntoIterator<Item = String>) {
+        let files: HashSet<String> = files.into_iter().collect();
+        let mut dirs = HashSet::new();
+        for file in &files {
+            let mut dir = file.as_str();
+            while let Some((parent, _)) = dir.rsplit_once('/') {
+                dirs.insert(parent.to_string());
+                dir = parent;
+            }
+        }
+        self.only = Some((files, dirs));
+    }
+
+    /// Additionally excludes one path relative to the root, as a `/path`
+    /// line would.
+    pub fn exclude(&mut self, path: &str) {
+        self.rules
+            .extend(IgnoreRules::parse(&format!("/{path}")).rules);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.only.is_none()
+    }
+
+    /// Whether `path` (relative to the repository root, `/`-separated) is
+    /// excluded. Anything inside an excluded directory is excluded as well.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        if let Some((files, dirs)) = &self.only {
+            let kept = if is_dir {
+                dirs.contains(path)
+            } else {
+                files.contains(path)
+            };
+            if !kept {
+                return true;
+            }
+        }
+        let parts: Vec<&str> = path.split('/').collect();
+        for depth in 1..parts.len() {
+            if self.matches(&parts[..depth].join("/"), true) {
+                return true;
+            }
+        }
+        self.matches(path, is_dir)
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            if rule.regex.is_match(path) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    regex
+}
+
+/// Reads `.izignore` from `dir`; a missing file means nothing is excluded.
+pub fn read_izignore(dir: &std::path::Path) -> Result<IgnoreRules> {
+    let path = dir.join(".izignore");
+    if !path.exists() {
+        return Ok(IgnoreRules::default());
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(IgnoreRules::parse(&content))
+}
+
+pub fn global_config_path() -> Option<std::path::PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("iz").join("config.json"))
+}
+
+pub fn read_config_with_global(
+    config_path: &std::path::Path,
+    global_path: Option<&std::path::Path>,
+) -> Result<IzConfig, IzError> {
+    let local = read_config_from_path(config_path)?;
+    Ok(read_global_config(global_path)?.merge(local))
+}
+
+/// Reads the global config (always JSON); a missing file means no defaults.
+pub fn read_global_config(global_path: Option<&std::path::Path>) -> Result<IzConfig, IzError> {
+    let global = match global_path {
+        Some(path) if path.exists() => {
+            let content = std::fs::read_to_string(path).map_err(|source| IzError::ConfigRead {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            serde_json::from_str(&content).map_err(|source| IzError::ConfigParse {
+                path: path.to_path_buf(),
+                source: source.into(),
+            })?
+        }
+        _ => IzConfig::default(),
+    };
+    Ok(global)
+}
+
+/// Picks the config file: the `--config` flag, then `IZ_CONFIG`, then
+/// `./izconfig.json`, `./izconfig.yaml` or `./izconfig.yml`.
+pub fn resolve_config_path(cli_config: Option<&std::path::Path>) -> Result<std::path::PathBuf> {
+    if let Some(path) = cli_config {
+        return Ok(path.to_path_buf());
+    }
+
+    if let Some(path) = std::env::var_os("IZ_CONFIG").filter(|path| !path.is_empty()) {
+        return Ok(std::path::PathBuf::from(path));
+    }
+
+    Ok(find_config_file(&std::env::current_dir()?))
+}
+
+pub fn read_config(config_path: &std::path::Path) -> Result<IzConfig, IzError> {
+    read_config_with_global(config_path, global_config_path().as_deref())
+}
+
+use anyhow::Context;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use std::fs;
+
+    #[test]
+    fn test_parse_key_val_success() {
+        let result = parse_key_val("name=Ali").unwrap();
+        assert_eq!(result, ("name".to_string(), "Ali".to_string()));
+
+        let result = parse_key_val("port=8080").unwrap();
+        assert_eq!(result, ("port".to_string(), "8080".to_string()));
+
+        let result = parse_key_val("key=value=with=equals").unwrap();
+        assert_eq!(result, ("key".to_string(), "value=with=equals".to_string()));
+    }
+
+    #[test]
+    fn test_parse_key_val_failure() {
+        let result = parse_key_val("invalid_format");
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid KEY=value format"));
+    }
+
+    #[test]
+    fn test_substitute_variables_success() {
+        let mut params = HashMap::new();
+        params.insert("name".to_string(), "Ali".to_string());
+        params.insert("port".to_string(), "8080".to_string());
+
+        let result = substitute_variables("echo 'Merhaba #{name}!'", &params).unwrap();
+        assert_eq!(result, "echo 'Merhaba Ali!'");
+
+        let result = substitute_variables("server --port #{port}", &params).unwrap();
+        assert_eq!(result, "server --port 8080");
+
+        let result = substitute_variables("greet #{name} on port #{port}", &params).unwrap();
+        assert_eq!(result, "greet Ali on port 8080");
+    }
+
+    #[test]
+    fn test_substitute_variables_no_variables() {
+        let params = HashMap::new();
+        let result = substitute_variables("echo 'Hello World'", &params).unwrap();
+        assert_eq!(result, "echo 'Hello World'");
+    }
+
+    #[test]
+    fn test_substitute_variables_missing_param() {
+        let params = HashMap::new();
+        let result = substitute_variables("echo 'Hello #{name}'", &params);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Required parameter not found: name"));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "a/x", "b/y"), "");
+
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        assert_eq!(
+            unified_diff(old, new, "a/old", "b/new"),
+            "--- a/old\n+++ b/new\n\
+             @@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+             @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n"
+        );
+
+        // Changes close together share one hunk; an empty side counts from 0
+        assert_eq!(
+            unified_diff("", "only\n", "a", "b"),
+            "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+only\n"
+        );
+        let diff = unified_diff("a\nb\nc\nd\n", "a\nB\nc\nD\n", "a", "b");
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.contains("-b\n+B\n c\n-d\n+D\n"), "{diff}");
+    }
+
+    #[test]
+    fn test_template_placeholders() {
+        let template =
+            "cargo #{cmd} --target #{target} -p #{pkg} --out #{target}/#{deploy.hosts.0}";
+        assert_eq!(
+            template_placeholders(template),
+            vec!["cmd", "target", "pkg", "deploy.hosts.0"]
+        );
+        assert!(template_placeholders("echo 'no params' #{}").is_empty());
+
+        let commands: HashMap<String, CommandEntry> = serde_json::from_str(
+            r##"{
+                "build": {
+                    "template": "cargo build --profile #{profile} #{flags}",
+                    "params": ["target"],
+                    "pre": "echo #{target} #{stamp}"
+                },
+                "release": {"alias": "build", "params": {"profile": "release"}}
+            }"##,
+        )
+        .unwrap();
+        let resolved = resolve_command(&commands, "release").unwrap();
+        let params = command_params(&resolved);
+        let names: Vec<&str> = params.iter().map(|param| param.name.as_str()).collect();
+        assert_eq!(names, vec!["target", "profile", "flags", "stamp"]);
+        assert_eq!(params[1].default.as_deref(), Some("release"));
+        assert_eq!(params[0].default, None);
+    }
+
+    #[test]
+    fn test_read_config_from_path_success() {
+        let temp_dir = std::env::temp_dir().join("iz-test-config");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let config_path = temp_dir.join("izconfig.json");
+
+        let config_content = r#"
+        {
+            "commands": {
+                "run": "dotnet run",
+                "test": "dotnet test"
+            }
+        }"#;
+
+        fs::write(&config_path, config_content).unwrap();
+
+        let config = read_config_from_path(&config_path).unwrap();
+        assert_eq!(config.commands.get("run").unwrap().template(), "dotnet run");
+        assert_eq!(
+            config.commands.get("test").unwrap().template(),
+            "dotnet test"
+        );
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_read_config_from_path_file_not_found() {
+        let temp_dir = std::env::temp_dir().join("iz-test-nonexistent");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let config_path = temp_dir.join("nonexistent.json");
+
+        let result = read_config_from_path(&config_path);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("izconfig.json not found"));
+    }
+
+    #[test]
+    fn test_missing_config_example_matches_project() {
+        let example_for = |marker: Option<&str>| {
+            let dir =
+                std::env::temp_dir().join(format!("iz-test-example-{}", rand::random::<u32>()));
+            fs::create_dir_all(&dir).unwrap();
+            if let Some(marker) = marker {
+                fs::write(dir.join(marker), "").unwrap();
+            }
+            let error = read_config_from_path(&dir.join("izconfig.json")).unwrap_err();
+            let _ = fs::remove_dir_all(&dir);
+            error.to_string()
+        };
+
+        let cargo = example_for(Some("Cargo.toml"));
+        assert!(cargo.contains(r#""test": "cargo test""#), "{cargo}");
+        assert!(!cargo.contains("dotnet"));
+
+        let node = example_for(Some("package.json"));
+        assert!(node.contains(r#""build": "npm run build""#), "{node}");
+        assert!(node.contains(r#""test": "npm test""#));
+
+        let fallback = example_for(None);
+        assert!(fallback.contains(r#""run": "dotnet run""#), "{fallback}");
+    }
+
+    #[test]
+    fn test_read_config_from_path_invalid_json() {
+        let temp_dir = std::env::temp_dir().join("iz-test-invalid");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let config_path = temp_dir.join("izconfig.json");
+
+        fs::write(&config_path, "invalid json content").unwrap();
+
+        let result = read_config_from_path(&config_path);
+        assert!(result.is_err());
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_iz_config_serde() {
+        let mut commands = HashMap::new();
+        commands.insert("run".to_string(), "dotnet run".into());
+        commands.insert("build".to_string(), "cargo build".into());
+
+        let config = IzConfig {
+            commands,
+            temp_dir: None,
+            keep: None,
+            allowed_commands: None,
+            precreate_dirs: None,
+            success_codes: None,
+            cleanup_strategy: None,
+            container: None,
+            system_temp: None,
+            auto_clean: Some(AutoClean {
+                older_than: "7d".to_string(),
+            }),
+            max_temp_bytes: Some(1 << 30),
+            clean_confirm_default: Some(true),
+        };
+
+        // Serialize
+        let json = serde_json::to_string(&config).unwrap();
+
+        // Deserialize
+        let deserialized: IzConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(config, deserialized);
+    }
+
+    #[test]
+    fn test_resolve_temp_base() {
+        let cwd = std::path::Path::new("/work/repo");
+        let mut config = IzConfig::default();
+
+        assert_eq!(
+            resolve_temp_base(None, None, None, &config, cwd),
+            cwd.join(".iztemp")
+        );
+
+        config.system_temp = Some(true);
+        assert_eq!(
+            resolve_temp_base(None, None, None, &config, cwd),
+            std::env::temp_dir().join("iz")
+        );
+
+        config.temp_dir = Some("/config/temp".to_string());
+        assert_eq!(
+            resolve_temp_base(None, None, None, &config, cwd),
+            std::path::PathBuf::from("/config/temp")
+        );
+        assert_eq!(
+            resolve_temp_base(None, Some("/env/temp".to_string()), None, &config, cwd),
+            std::path::PathBuf::from("/env/temp")
+        );
+        assert_eq!(
+            resolve_temp_base(
+                Some("/cli/temp"),
+                Some("/env/temp".to_string()),
+                None,
+                &config,
+                cwd
+            ),
+            std::path::PathBuf::from("/cli/temp")
+        );
+
+        config.temp_dir = None;
+        assert_eq!(
+            resolve_temp_base(None, Some("/env/temp".to_string()), None, &config, cwd),
+            std::path::PathBuf::from("/env/temp")
+        );
+
+        config.temp_dir = Some("/config/temp".to_string());
+        assert_eq!(
+            resolve_temp_base(None, None, Some("/command/temp"), &config, cwd),
+            std::path::PathBuf::from("/command/temp")
+        );
+        assert_eq!(
+            resolve_temp_base(
+                None,
+                Some("/env/temp".to_string()),
+                Some("/command/temp"),
+                &config,
+                cwd
+            ),
+            std::path::PathBuf::from("/env/temp")
+        );
+    }
+
+    #[test]
+    fn test_iz_config_with_temp_dir_and_keep() {
+        let temp_dir = std::env::temp_dir().join("iz-test-config-extended");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let config_path = temp_dir.join("izconfig.json");
+
+        let config_content = r#"
+        {
+            "commands": {
+                "run": "dotnet run",
+                "test": "dotnet test"
+            },
+            "temp_dir": "/tmp/iz-custom",
+            "keep": true
+        }"#;
+
+        fs::write(&config_path, config_content).unwrap();
+
+        let config = read_config_from_path(&config_path).unwrap();
+        assert_eq!(config.commands.get("run").unwrap().template(), "dotnet run");
+        assert_eq!(config.temp_dir.as_ref().unwrap(), "/tmp/iz-custom");
+        assert!(config.keep.unwrap());
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_iz_config_without_optional_fields() {
+        let temp_dir = std::env::temp_dir().join("iz-test-config-minimal");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let config_path = temp_dir.join("izconfig.json");
+
+        let config_content = r#"
+        {
+            "commands": {
+                "run": "dotnet run"
+            }
+        }"#;
+
+        fs::write(&config_path, config_content).unwrap();
+
+        let config = read_config_from_path(&config_path).unwrap();
+        assert_eq!(config.commands.get("run").unwrap().template(), "dotnet run");
+        assert!(config.temp_dir.is_none());
+        assert!(config.keep.is_none());
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_read_config_from_reader() {
+        let source = std::path::Path::new("-");
+        let inputs = [
+            (
+                ConfigFormat::Json,
+                r#"{"commands": {"run": "cargo run"}, "keep": true}"#,
+            ),
+            (
+                ConfigFormat::Yaml,
+                "commands:\n  run: cargo run\nkeep: true\n",
+            ),
+            (
+                ConfigFormat::Toml,
+                "keep = true\n\n[commands]\nrun = \"cargo run\"\n",
+            ),
+        ];
+        for (format, content) in inputs {
+            let config = read_config_from_reader(content.as_bytes(), format, source).unwrap();
+            assert_eq!(
+                config.commands.get("run").unwrap().template(),
+                "cargo run",
+                "{format:?}"
+            );
+            assert_eq!(config.keep, Some(true), "{format:?}");
+        }
+
+        // The forced format wins over what the content looks like
+        assert!(matches!(
+            read_config_from_reader(inputs[2].1.as_bytes(), ConfigFormat::Json, source),
+            Err(IzError::ConfigParse { path, .. }) if path == source
+        ));
+
+        assert_eq!(
+            ConfigFormat::from_path(std::path::Path::new("iz.toml")),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(std::path::Path::new("iz.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(std::path::Path::new("iz.conf")),
+            ConfigFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_read_config_with_global_local_overrides() {
+        let temp_dir = std::env::temp_dir().join("iz-test-config-global");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let global_path = temp_dir.join("config.json");
+        let config_path = temp_dir.join("izconfig.json");
+
+        fs::write(
+            &global_path,
+            r#"
+        {
+            "commands": {
+                "lint": "cargo clippy",
+                "run": "cargo run"
+            },
+            "temp_dir": "/tmp/iz-global",
+            "keep": true
+        }"#,
+        )
+        .unwrap();
+        fs::write(
+            &config_path,
+            r#"
+        {
+            "commands": {
+                "run": "dotnet run"
+            },
+            "keep": false
+        }"#,
+        )
+        .unwrap();
+
+        let config = read_config_with_global(&config_path, Some(&global_path)).unwrap();
+        assert_eq!(config.commands.get("run").unwrap().template(), "dotnet run");
+        assert_eq!(
+            config.commands.get("lint").unwrap().template(),
+            "cargo clippy"
+        );
+        assert_eq!(config.temp_dir.as_ref().unwrap(), "/tmp/iz-global");
+        assert!(!config.keep.unwrap());
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_read_config_with_missing_global() {
+        let temp_dir = std::env::temp_dir().join("iz-test-config-no-global");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let config_path = temp_dir.join("izconfig.json");
+
+        fs::write(&config_path, r#"{ "commands": { "run": "dotnet run" } }"#).unwrap();
+
+        let config =
+            read_config_with_global(&config_path, Some(&temp_dir.join("missing.json"))).unwrap();
+        assert_eq!(config.commands.len(), 1);
+        assert!(config.keep.is_none());
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_output_tail_keeps_last_lines() {
+        let mut tail = OutputTail::new(2);
+        tail.push("one".to_string());
+        tail.push("two".to_string());
+        tail.push("three".to_string());
+
+        let lines: Vec<&String> = tail.lines().collect();
+        assert_eq!(lines, vec!["two", "three"]);
+        assert_eq!(tail.contents(), "two\nthree\n");
+    }
+
+    #[test]
+    fn test_output_tail_zero_limit() {
+        let mut tail = OutputTail::new(0);
+        tail.push("ignored".to_string());
+        assert_eq!(tail.lines().count(), 0);
+    }
+
+    #[test]
+    fn test_command_entry_object_form() {
+        let config: IzConfig = serde_json::from_str(
+            r#"
+        {
+            "commands": {
+                "run": "dotnet run",
+                "serve": {
+                    "template": "python -m http.server #{port}",
+                    "params": ["port", "host"]
+                }
+            }
+        }"#,
+        )
+        .unwrap();
+
+        let run = config.commands.get("run").unwrap();
+        assert_eq!(run.template(), "dotnet run");
+        assert!(run.declared_params().is_empty());
+
+        let serve = config.commands.get("serve").unwrap();
+        assert_eq!(serve.template(), "python -m http.server #{port}");
+        assert_eq!(serve.declared_params(), ["port", "host"]);
+        assert_eq!(serve.generator(), None);
+    }
+
+    #[test]
+    fn test_generated_command_entry() {
+        let config: IzConfig = serde_json::from_str(
+            r#"
+        {
+            "commands": {
+                "gen": {
+                    "generator": "./scripts/gen.sh",
+                    "params": ["target"]
+                }
+            }
+        }"#,
+        )
+        .unwrap();
+
+        let gen = config.commands.get("gen").unwrap();
+        assert_eq!(gen.generator(), Some("./scripts/gen.sh"));
+        assert_eq!(gen.declared_params(), ["target"]);
+        assert_eq!(gen.template(), "");
+        assert!(gen.pre_hook().is_none());
+    }
+
+    #[test]
+    fn test_resolve_command_alias() {
+        let config: IzConfig = serde_json::from_str(
+            r#"
+        {
+            "commands": {
+                "test": "cargo test --mode #{mode} --jobs #{jobs}",
+                "t": {"alias": "test", "params": {"mode": "fast", "jobs": "2"}},
+                "tt": {"alias": "t", "params": {"jobs": "8"}},
+                "loop-a": {"alias": "loop-b"},
+                "loop-b": {"alias": "loop-a"},
+                "dangling": {"alias": "missing"}
+            }
+        }"#,
+        )
+        .unwrap();
+
+        let resolved = resolve_command(&config.commands, "tt").unwrap();
+        assert_eq!(resolved.name, "test");
+        assert_eq!(
+            resolved.entry.template(),
+            "cargo test --mode #{mode} --jobs #{jobs}"
+        );
+        assert_eq!(resolved.params["mode"], "fast");
+        assert_eq!(resolved.params["jobs"], "8");
+
+        let resolved = resolve_command(&config.commands, "test").unwrap();
+        assert_eq!(resolved.name, "test");
+        assert!(resolved.params.is_empty());
+
+        let error = resolve_command(&config.commands, "loop-a").unwrap_err();
+        assert_eq!(error.to_string(), "Alias cycle: loop-a -> loop-b -> loop-a");
+
+        let error = resolve_command(&config.commands, "dangling").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Alias 'dangling' points to unknown command 'missing'"
+        );
+
+        assert!(resolve_command(&config.commands, "nope").is_err());
+    }
+
+    #[test]
+    fn test_check_declared_params() {
+        let declared = vec!["name".to_string(), "port".to_string(), "host".to_string()];
+        let mut params = HashMap::new();
+        params.insert("name".to_string(), "Ali".to_string());
+
+        let result = check_declared_params(&declared, &params);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Missing declared parameters: port, host"));
+
+        params.insert("port".to_string(), "8080".to_string());
+        params.insert("host".to_string(), "localhost".to_string());
+        assert!(check_declared_params(&declared, &params).is_ok());
+    }
+
+    #[test]
+    fn test_check_allowed_command() {
+        let allowed = vec!["cargo".to_string(), "dotnet".to_string()];
+
+        assert!(check_allowed_command("cargo", Some(&allowed)).is_ok());
+        assert!(check_allowed_command("anything", None).is_ok());
+
+        let result = check_allowed_command("rm", Some(&allowed));
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Command 'rm' is not in allowed_commands"));
+    }
+
+    #[test]
+    fn test_create_dir_with_retries_skips_existing() {
+        let temp_dir = std::env::temp_dir().join("iz-test-unique-dir");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(temp_dir.join("iz-taken")).unwrap();
+
+        let mut names = vec!["iz-free", "iz-taken"];
+        let path =
+            create_dir_with_retries(&temp_dir, 3, || names.pop().unwrap().to_string()).unwrap();
+        assert_eq!(path, temp_dir.join("iz-free"));
+        assert!(path.is_dir());
+
+        let result = create_dir_with_retries(&temp_dir, 3, || "iz-taken".to_string());
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("after 3 attempts"));
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_command_entry_hooks() {
+        let config: IzConfig = serde_json::from_str(
+            r#"
+        {
+            "commands": {
+                "run": "dotnet run",
+                "test": {
+                    "template": "dotnet test",
+                    "pre": "dotnet restore",
+                    "post": "echo done #{name}",
+                    "ignore_hook_failures": true
+                }
+            }
+        }"#,
+        )
+        .unwrap();
+
+        let run = config.commands.get("run").unwrap();
+        assert!(run.pre_hook().is_none());
+        assert!(run.post_hook().is_none());
+        assert!(!run.ignore_hook_failures());
+
+        let test = config.commands.get("test").unwrap();
+        assert_eq!(test.pre_hook(), Some("dotnet restore"));
+        assert_eq!(test.post_hook(), Some("echo done #{name}"));
+        assert!(test.ignore_hook_failures());
+    }
+
+    #[test]
+    fn test_phase_error_keeps_message() {
+        let result: Result<()> = Err(anyhow::anyhow!("Commit not found"));
+        let error = result
+            .phase(Phase::Checkout)
+            .context("Failed to checkout commit")
+            .phase(Phase::Execute)
+            .unwrap_err();
+
+        assert_eq!(error_phase(&error), Some(Phase::Checkout));
+        assert_eq!(
+            format!("{error:#}"),
+            "Failed to checkout commit: Commit not found"
+        );
+        assert_eq!(error_phase(&anyhow::anyhow!("untagged")), None);
+    }
+
+    #[test]
+    fn test_read_commit_file() {
+        let temp_dir = std::env::temp_dir().join("iz-test-commit-file");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let commit_path = temp_dir.join("commit.txt");
+
+        fs::write(&commit_path, "  abc1234\n").unwrap();
+        assert_eq!(read_commit_file(&commit_path).unwrap(), "abc1234");
+
+        fs::write(&commit_path, "\n").unwrap();
+        let result = read_commit_file(&commit_path);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Commit file is empty"));
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_run_report_formats() {
+        let report = RunReport {
+            runs: vec![
+                RunReportEntry {
+                    commit: "abc1234".to_string(),
+                    subject: "Fix a | b".to_string(),
+                    command: "test".to_string(),
+                    success: true,
+                    exit_code: Some(0),
+                    duration_ms: 1500,
+                    ..Default::default()
+                },
+                RunReportEntry {
+                    commit: "def5678".to_string(),
+                    subject: "Break build".to_string(),
+                    command: "test".to_string(),
+                    success: false,
+                    exit_code: None,
+                    duration_ms: 20,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let json = serde_json::to_string(&report).unwrap();
+        let deserialized: RunReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(report, deserialized);
+
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("| abc1234 | Fix a \\| b | test | ✅ passed | 0 | 1.50s |"));
+        assert!(markdown.contains("| def5678 | Break build | test | ❌ failed | - | 0.02s |"));
+    }
+
+    #[test]
+    fn test_parse_stash_ref() {
+        assert_eq!(parse_stash_ref("stash"), Some(0));
+        assert_eq!(parse_stash_ref("stash@{0}"), Some(0));
+        assert_eq!(parse_stash_ref("stash@{12}"), Some(12));
+        assert_eq!(parse_stash_ref("stash@{-1}"), None);
+        assert_eq!(parse_stash_ref("stash@{yesterday}"), None);
+        assert_eq!(parse_stash_ref("stash@{0}~1"), None);
+        assert_eq!(parse_stash_ref("HEAD"), None);
+    }
+
+    #[test]
+    fn test_iz_error_variants() {
+        let commands: HashMap<String, CommandEntry> =
+            [("run".to_string(), "dotnet run".into())].into();
+        assert!(matches!(
+            resolve_command(&commands, "deploy"),
+            Err(IzError::CommandNotFound(name)) if name == "deploy"
+        ));
+
+        let params = HashMap::new();
+        assert!(matches!(
+            substitute_variables("echo #{name}", &params),
+            Err(IzError::MissingParam(name)) if name == "name"
+        ));
+        assert!(matches!(
+            check_declared_params(&["port".to_string()], &params),
+            Err(IzError::MissingDeclaredParams(names)) if names == ["port"]
+        ));
+
+        let dir = std::env::temp_dir().join(format!("iz-test-error-{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("izconfig.json");
+        assert!(matches!(
+            read_config_from_path(&config_path),
+            Err(IzError::ConfigNotFound { path, .. }) if path == config_path
+        ));
+        fs::write(&config_path, "{ not json").unwrap();
+        assert!(matches!(
+            read_config_from_path(&config_path),
+            Err(IzError::ConfigParse { .. })
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(
+            IzError::CommandFailed { code: Some(2) }.to_string(),
+            "Command failed with exit code 2"
+        );
+    }
+
+    #[test]
+    fn test_match_command_prefix() {
+        let commands: HashMap<String, CommandEntry> = [
+            ("test", "cargo test"),
+            ("teardown", "docker compose down"),
+            ("build", "cargo build"),
+            ("b", "cargo build --release"),
+        ]
+        .into_iter()
+        .map(|(name, template)| (name.to_string(), template.into()))
+        .collect();
+
+        assert_eq!(match_command_prefix(&commands, "tes").unwrap(), "test");
+        assert_eq!(match_command_prefix(&commands, "bu").unwrap(), "build");
+        // An exact name isn't treated as a prefix of "build"
+        assert_eq!(match_command_prefix(&commands, "b").unwrap(), "b");
+        assert_eq!(
+            match_command_prefix(&commands, "te")
+                .unwrap_err()
+                .to_string(),
+            "Command prefix 'te' is ambiguous: teardown, test"
+        );
+        assert!(match_command_prefix(&commands, "x")
+            .unwrap_err()
+            .to_string()
+            .contains("no command starts with 'x'"));
+    }
+
+    #[test]
+    fn test_ascii_decorations() {
+        assert_eq!(
+            ascii_decorations("⚠️  Operation completed with warnings"),
+            "[warn] Operation completed with warnings"
+        );
+        assert_eq!(
+            ascii_decorations("🚀 Executing command..."),
+            "[run] Executing command..."
+        );
+        assert_eq!(
+            ascii_decorations("\n🛑 Received SIGINT"),
+            "\n[stop] Received SIGINT"
+        );
+        assert_eq!(
+            ascii_decorations("HEAD → main @ abc1234"),
+            "HEAD -> main @ abc1234"
+        );
+        assert_eq!(ascii_decorations("  • .iztemp/iz-1"), "  * .iztemp/iz-1");
+        // Text that isn't a known decoration is left alone
+        assert_eq!(ascii_decorations("héllo ✅"), "héllo [ok]");
+
+        for (symbol, _) in ASCII_TAGS {
+            assert!(ascii_decorations(symbol).is_ascii(), "{symbol}");
+        }
+    }
+
+    #[test]
+    fn test_first_output_change() {
+        let run = |exit_code: i32, stdout: &str| RunReportEntry {
+            exit_code: Some(exit_code),
+            stdout: stdout.as_bytes().to_vec(),
+            ..Default::default()
+        };
+
+        assert_eq!(first_output_change(&[]), None);
+        assert_eq!(first_output_change(&[run(0, "a"), run(0, "a")]), None);
+        assert_eq!(
+            first_output_change(&[run(0, "a"), run(0, "a"), run(0, "b"), run(1, "c")]),
+            Some(OutputChange {
+                index: 2,
+                exit_code_changed: false,
+                stdout_changed: true,
+            })
+        );
+        assert_eq!(
+            first_output_change(&[run(0, "a"), run(1, "a")]),
+            Some(OutputChange {
+                index: 1,
+                exit_code_changed: true,
+                stdout_changed: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_spawn_error() {
+        let not_found = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(
+            spawn_error(not_found(), "cargoo").to_string(),
+            "Command not found: cargoo. Is it installed and on PATH?"
+        );
+        assert!(spawn_error(not_found(), "./build.sh")
+            .to_string()
+            .contains("Does the file exist in the checkout?"));
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(spawn_error(denied, "./build.sh")
+            .to_string()
+            .starts_with("Permission denied executing ./build.sh"));
+    }
+
+    #[test]
+    fn test_run_spec() {
+        let spec = RunSpec::from_json(
+            r#"{
+                "commit": "HEAD~1",
+                "command": "serve",
+                "params": {"port": 3000, "host": "localhost", "debug": true, "deploy": {"hosts": ["a"]}},
+                "env": {"RUST_LOG": "debug"},
+                "keep": true,
+                "timeout": "30s"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(spec.version, RUN_SPEC_VERSION);
+        assert_eq!(spec.commit.as_deref(), Some("HEAD~1"));
+        assert_eq!(spec.shell, None);
+
+        assert_eq!(
+            spec.plain_params(),
+            vec![
+                ("debug".to_string(), "true".to_string()),
+                ("host".to_string(), "localhost".to_string()),
+                ("port".to_string(), "3000".to_string()),
+            ]
+        );
+        let structured = spec.json_params();
+        assert_eq!(structured.len(), 1);
+        assert_eq!(structured[0].0, "deploy");
+
+        // Serializing and reading back gives the same spec
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(RunSpec::from_json(&json).unwrap(), spec);
+
+        let error = RunSpec::from_json(r#"{"version": 2}"#).unwrap_err();
+        assert!(error.to_string().contains("Unsupported run spec version 2"));
+        assert!(RunSpec::from_json(r#"{"comand": "typo"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        use std::time::Duration;
+
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(
+            parse_duration("7d").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("99999999999999999h")
+            .unwrap_err()
+            .to_string()
+            .contains("too large"));
+    }
+
+    #[test]
+    fn test_parse_keep() {
+        use std::time::Duration;
+
+        assert_eq!(parse_keep("true").unwrap(), KeepSetting::Flag(true));
+        assert_eq!(parse_keep("false").unwrap(), KeepSetting::Flag(false));
+        assert_eq!(
+            parse_keep("1h").unwrap(),
+            KeepSetting::For(Duration::from_secs(3600))
+        );
+        assert!(parse_keep("yes").is_err());
+
+        assert!(KeepSetting::For(Duration::from_secs(1)).keeps());
+        assert!(!KeepSetting::Flag(false).keeps());
+        assert_eq!(KeepSetting::Flag(true).duration(), None);
+    }
+
+    #[test]
+    fn test_convert_line_endings() {
+        assert_eq!(
+            convert_line_endings(b"a\r\nb\nc", false),
+            Some(b"a\nb\nc".to_vec())
+        );
+        assert_eq!(
+            convert_line_endings(b"a\r\nb\nc", true),
+            Some(b"a\r\nb\r\nc".to_vec())
+        );
+        // A lone CR is not a line ending
+        assert_eq!(convert_line_endings(b"a\rb\n", false), None);
+        assert_eq!(convert_line_endings(b"a\nb\n", false), None);
+        assert_eq!(convert_line_endings(b"\0\n", true), None);
+    }
+
+    #[test]
+    fn test_parse_commit_list() {
+        let content = "# bisect candidates\nabc123\n\n  HEAD~2  # flaky\n#skip\nv1.0\n";
+        assert_eq!(
+            parse_commit_list(content),
+            vec![
+                (2, "abc123".to_string()),
+                (4, "HEAD~2".to_string()),
+                (6, "v1.0".to_string()),
+            ]
+        );
+        assert!(parse_commit_list("# only comments\n\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let content = "# comment\n\nFOO=bar\nexport QUOTED=\"hello world\"\nSINGLE='x=y'\nEMPTY=\n";
+        let vars = parse_env_file(content).unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                ("FOO".to_string(), "bar".to_string()),
+                ("QUOTED".to_string(), "hello world".to_string()),
+                ("SINGLE".to_string(), "x=y".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+
+        let result = parse_env_file("FOO=bar\nnot a pair\n");
+        assert!(result.unwrap_err().to_string().contains("Line 2"));
+    }
+
+    #[test]
+    fn test_capped_buffer_truncates() {
+        let mut buffer = CappedBuffer::new(Some(5));
+        assert_eq!(buffer.push(b"abc"), b"abc");
+        assert_eq!(buffer.push(b"defg"), b"de");
+        assert_eq!(buffer.push(b"h"), b"");
+        assert!(buffer.is_truncated());
+        assert_eq!(
+            String::from_utf8(buffer.into_bytes()).unwrap(),
+            "abcde\n... (output truncated at 5 bytes)\n"
+        );
+
+        let mut unlimited = CappedBuffer::new(None);
+        unlimited.push(b"everything");
+        assert!(!unlimited.is_truncated());
+        assert_eq!(unlimited.into_bytes(), b"everything");
+    }
+
+    #[test]
+    fn test_substitute_variables_with_json() {
+        let mut params = HashMap::new();
+        params.insert("name".to_string(), "Ali".to_string());
+        let mut json_params = HashMap::new();
+        json_params.insert(
+            "config".to_string(),
+            serde_json::json!({"db": {"host": "x", "port": 5432}, "hosts": ["a", "b"], "debug": true}),
+        );
+
+        let result = substitute_variables_with_json(
+            "#{name} #{config.db.host}:#{config.db.port} #{config.hosts.1} #{config.debug}",
+            &params,
+            &json_params,
+        )
+        .unwrap();
+        assert_eq!(result, "Ali x:5432 b true");
+
+        let result = substitute_variables_with_json("#{config.db}", &params, &json_params);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Parameter 'config.db' is not a scalar value"));
+
+        let result = substitute_variables_with_json("#{config.db.user}", &params, &json_params);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Required parameter not found: config.db.user"));
+    }
+
+    #[test]
+    fn test_parse_key_json() {
+        let (key, value) = parse_key_json(r#"config={"db":{"host":"x"}}"#).unwrap();
+        assert_eq!(key, "config");
+        assert_eq!(value["db"]["host"], "x");
+
+        assert!(parse_key_json("config={not json").is_err());
+    }
+
+    #[test]
+    fn test_is_success_code() {
+        assert!(is_success_code(Some(0), None));
+        assert!(!is_success_code(Some(2), None));
+        assert!(is_success_code(Some(2), Some(&[0, 2])));
+        assert!(!is_success_code(Some(0), Some(&[2])));
+        assert!(!is_success_code(None, Some(&[0])));
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let temp_dir = std::env::temp_dir().join("iz-test-validate-config");
+        fs::create_dir_all(&temp_dir).unwrap();
+        let config_path = temp_dir.join("izconfig.json");
+
+        fs::write(
+            &config_path,
+            r#"
+        {
+            "commands": {
+                "empty": "  ",
+                "clean": "cargo clean",
+                "gen": {"generator": "./gen.sh", "pre": "make", "types": {}},
+                "mixed": {"template": "make", "generator": "./gen.sh"},
+                "serve": {
+                    "template": "serve --port #{port} --host #{host}",
+                    "params": ["port", "unused"],
+                    "retries": 3
+                }
+            },
+            "tmp_dir": ".iztemp"
+        }
+        "#,
+        )
+        .unwrap();
+
+        let warnings = validate_config(&config_path).unwrap();
+        let messages: Vec<(Severity, Option<&str>, &str)> = warnings
+            .iter()
+            .map(|w| (w.severity, w.command.as_deref(), w.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (Severity::Warning, None, "Unknown field 'tmp_dir'"),
+                (
+                    Severity::Warning,
+                    Some("clean"),
+                    "'clean' is a reserved iz subcommand name"
+                ),
+                (Severity::Error, Some("empty"), "Command template is empty"),
+                (
+                    Severity::Error,
+                    Some("gen"),
+                    "Field 'pre' is not supported on a generated command"
+                ),
+                (
+                    Severity::Error,
+                    Some("gen"),
+                    "Field 'types' is not supported on a generated command"
+                ),
+                (
+                    Severity::Error,
+                    Some("mixed"),
+                    "Field 'generator' is not supported on a template command"
+                ),
+                (Severity::Warning, Some("serve"), "Unknown field 'retries'"),
+                (
+                    Severity::Warning,
+                    Some("serve"),
+                    "Placeholder '#{host}' is not declared in params"
+                ),
+                (
+                    Severity::Warning,
+                    Some("serve"),
+                    "Declared param 'unused' is never used"
+                ),
+            ]
+        );
+
+        fs::write(&config_path, r#"{"commands": {"run": "cargo run"}}"#).unwrap();
+        assert!(validate_config(&config_path).unwrap().is_empty());
+
+        fs::write(&config_path, "{ not json").unwrap();
+        assert!(validate_config(&config_path).is_err());
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-pub struct IzConfig {
-    pub commands: HashMap<String, String>,
-    #[serde(default)]
-    pub temp_dir: Option<String>,
-    #[serde(default)]
-    pub keep: Option<bool>,
-}
+    #[test]
+    fn test_build_argv() {
+        assert_eq!(
+            build_argv("  cargo test  --workspace -- --nocapture ").unwrap(),
+            vec!["cargo", "test", "--workspace", "--", "--nocapture"]
+        );
+        assert!(build_argv("   ")
+            .unwrap_err()
+            .to_string()
+            .contains("Empty command"));
+    }
 
-pub fn parse_key_val(
-    s: &str,
-) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let pos = s
-        .find('=')
-        .ok_or_else(|| format!("Invalid KEY=value format: {s}"))?;
-    Ok((s[..pos].to_string(), s[pos + 1..].to_string()))
-}
+    #[cfg(unix)]
+    #[test]
+    fn test_set_tree_readonly() {
+        use std::os::unix::fs::PermissionsExt;
 
-pub fn substitute_variables(template: &str, params: &HashMap<String, String>) -> Result<String> {
-    let re = Regex::new(r"#\{(\w+)\}").unwrap();
-    let mut result = template.to_string();
+        let root = std::env::temp_dir().join(format!("iz-test-readonly-{}", rand::random::<u32>()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.rs"), "fn main() {}").unwrap();
+        let mode =
+            |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
 
-    for caps in re.captures_iter(template) {
-        let var_name = &caps[1];
-        let full_match = &caps[0];
+        set_tree_readonly(&root, true).unwrap();
+        assert_eq!(mode(&root.join("src").join("main.rs")) & 0o222, 0);
+        assert_eq!(mode(&root.join("src")) & 0o222, 0);
+        assert_ne!(mode(&root) & 0o200, 0, "the root stays writable");
 
-        if let Some(value) = params.get(var_name) {
-            result = result.replace(full_match, value);
-        } else {
-            return Err(anyhow::anyhow!(
-                "Required parameter not found: {}",
-                var_name
+        // Only directories recorded as read-only are restored
+        restore_writable(&root).unwrap();
+        assert_eq!(mode(&root.join("src")) & 0o222, 0);
+
+        let meta = RunMeta {
+            readonly: true,
+            ..RunMeta::new("abc", None)
+        };
+        write_run_meta(&root, &meta).unwrap();
+        restore_writable(&root).unwrap();
+        assert_ne!(mode(&root.join("src").join("main.rs")) & 0o200, 0);
+        assert_ne!(mode(&root.join("src")) & 0o200, 0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_events_throttle_and_shape() {
+        use events::{CheckoutPhase, Event, Throttle};
+        use std::time::{Duration, Instant};
+
+        let mut throttle = Throttle::new(Duration::from_millis(100));
+        let start = Instant::now();
+        assert!(throttle.ready_at(start));
+        assert!(!throttle.ready_at(start + Duration::from_millis(50)));
+        assert!(throttle.ready_at(start + Duration::from_millis(100)));
+        assert!(!throttle.ready_at(start + Duration::from_millis(150)));
+
+        let event = Event::CheckoutProgress {
+            phase: CheckoutPhase::Files,
+            total: 3,
+            completed: 1,
+            path: Some("src/main.rs".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "event": "checkout_progress",
+                "phase": "files",
+                "total": 3,
+                "completed": 1,
+                "path": "src/main.rs"
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_append_args() {
+        assert_eq!(shell_quote("--nocapture"), "--nocapture");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(
+            append_args(
+                "cargo test",
+                &[
+                    "--".to_string(),
+                    "--nocapture".to_string(),
+                    "#{x} $HOME".to_string()
+                ]
+            ),
+            "cargo test -- --nocapture '#{x} $HOME'"
+        );
+        assert_eq!(append_args("cargo test", &[]), "cargo test");
+    }
+
+    #[test]
+    fn test_program_name_placeholder() {
+        let mut params = HashMap::new();
+        params.insert("runner".to_string(), "cargo".to_string());
+        params.insert("flags".to_string(), "--release --quiet".to_string());
+        let substitute = |template: &str| substitute_variables(template, &params);
+
+        let template = "#{runner} test #{flags}";
+        let argv = build_argv(&substitute(template).unwrap()).unwrap();
+        assert_eq!(argv, vec!["cargo", "test", "--release", "--quiet"]);
+        assert!(check_program_substituted(template, substitute).is_ok());
+        assert!(check_program_substituted("cargo #{flags}", substitute).is_ok());
+
+        params.insert("runner".to_string(), " ".to_string());
+        let substitute = |template: &str| substitute_variables(template, &params);
+        let error = check_program_substituted(template, substitute).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The program name '#{runner}' is empty after substitution"
+        );
+
+        // A missing param is reported by substitution itself
+        params.remove("runner");
+        let substitute = |template: &str| substitute_variables(template, &params);
+        assert!(check_program_substituted(template, substitute).is_err());
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let rules = IgnoreRules::parse(
+            "# comment\nnode_modules/\n/docs\n*.log\n!keep.log\nassets/**/*.png\n",
+        );
+
+        assert!(rules.is_ignored("node_modules", true));
+        assert!(rules.is_ignored("web/node_modules/pkg/index.js", false));
+        assert!(!rules.is_ignored("node_modules", false));
+        assert!(rules.is_ignored("docs/guide.md", false));
+        assert!(!rules.is_ignored("src/docs/guide.md", false));
+        assert!(rules.is_ignored("build/output.log", false));
+        assert!(!rules.is_ignored("keep.log", false));
+        assert!(rules.is_ignored("assets/icons/big/logo.png", false));
+        assert!(rules.is_ignored("assets/logo.png", false));
+        assert!(!rules.is_ignored("src/main.rs", false));
+
+        assert!(IgnoreRules::parse("\n# only comments\n").is_empty());
+    }
+
+    #[test]
+    fn test_substitute_file_paths() {
+        let root =
+            std::env::temp_dir().join(format!("iz-test-file-paths-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(root.join("config")).unwrap();
+        std::fs::write(root.join("config/app.toml"), "").unwrap();
+
+        assert_eq!(
+            substitute_file_paths("app --config #{file:config/app.toml} #{name}", &root).unwrap(),
+            format!(
+                "app --config {} #{{name}}",
+                root.join("config/app.toml").display()
+            )
+        );
+        assert!(matches!(
+            substitute_file_paths("cat #{file:config/missing.toml}", &root),
+            Err(IzError::MissingCheckoutFile(path)) if path == "config/missing.toml"
+        ));
+        for outside in ["#{file:../secret}", "#{file:/etc/passwd}"] {
+            assert!(matches!(
+                substitute_file_paths(outside, &root),
+                Err(IzError::InvalidFilePath(_))
             ));
         }
+
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
-    Ok(result)
-}
+    #[test]
+    fn test_ignore_rules_restrict_to() {
+        let mut rules = IgnoreRules::parse("*.log\n");
+        rules.restrict_to(["src/app/main.rs".to_string(), "debug.log".to_string()]);
 
-pub fn read_config_from_path(config_path: &std::path::Path) -> Result<IzConfig> {
-    if !config_path.exists() {
-        return Err(anyhow::anyhow!(
-            "izconfig.json not found. Example content:\n{}",
-            serde_json::to_string_pretty(&IzConfig {
-                commands: {
-                    let mut map = HashMap::new();
-                    map.insert("run".to_string(), "dotnet run".to_string());
-                    map.insert("build".to_string(), "dotnet build".to_string());
-                    map.insert("test".to_string(), "dotnet test".to_string());
-                    map
-                },
-                temp_dir: Some(".iztemp".to_string()),
-                keep: Some(false),
-            })?
-        ));
+        assert!(!rules.is_empty());
+        assert!(!rules.is_ignored("src", true));
+        assert!(!rules.is_ignored("src/app", true));
+        assert!(!rules.is_ignored("src/app/main.rs", false));
+        assert!(rules.is_ignored("src/app/lib.rs", false));
+        assert!(rules.is_ignored("docs", true));
+        assert!(rules.is_ignored("README.md", false));
+        // .izignore still applies to the changed files
+        assert!(rules.is_ignored("debug.log", false));
+
+        let mut nothing = IgnoreRules::default();
+        nothing.restrict_to(Vec::new());
+        assert!(nothing.is_ignored("src/main.rs", false));
     }
 
-    let content = std::fs::read_to_string(config_path)
-        .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+    #[test]
+    fn test_rename_to_trash() {
+        let base = std::env::temp_dir().join("iz-test-rename-to-trash");
+        let _ = fs::remove_dir_all(&base);
+        let dir = base.join("iz-123-abc");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("nested/file.txt"), "content").unwrap();
 
-    let config: IzConfig = serde_json::from_str(&content)
-        .with_context(|| format!("Failed to parse config file: {}", config_path.display()))?;
+        let destination = rename_to_trash(&dir).unwrap();
+        assert_eq!(destination, base.join(".trash").join("iz-123-abc"));
+        assert!(!dir.exists());
+        assert_eq!(
+            fs::read_to_string(destination.join("nested/file.txt")).unwrap(),
+            "content"
+        );
 
-    Ok(config)
-}
+        let config: IzConfig =
+            serde_json::from_str(r#"{"commands": {}, "cleanup_strategy": "rename"}"#).unwrap();
+        assert_eq!(config.cleanup_strategy, Some(CleanupStrategy::Rename));
 
-pub fn read_config() -> Result<IzConfig> {
-    let config_path = std::env::current_dir()?.join("izconfig.json");
-    read_config_from_path(&config_path)
-}
+        // Cleanup
+        let _ = fs::remove_dir_all(&base);
+    }
 
-use anyhow::Context;
+    #[test]
+    fn test_check_param_types() {
+        let config: IzConfig = serde_json::from_str(
+            r#"
+        {
+            "commands": {
+                "serve": {
+                    "template": "serve --port #{port} --ratio #{ratio} --verbose #{verbose} #{name}",
+                    "params": ["port", "ratio", "verbose", "name"],
+                    "types": {"port": "int", "ratio": "float", "verbose": "bool", "name": "string"}
+                }
+            }
+        }
+        "#,
+        )
+        .unwrap();
+        let types = config.commands["serve"].param_types().unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
+        let valid = |pairs: &[(&str, &str)]| {
+            let params: HashMap<String, String> = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            check_param_types(types, &params)
+        };
 
-    use std::fs;
+        assert!(valid(&[
+            ("port", "8080"),
+            ("ratio", "0.5"),
+            ("verbose", "true"),
+            ("name", "anything")
+        ])
+        .is_ok());
+        assert!(valid(&[("port", "-1"), ("ratio", "2"), ("verbose", "false")]).is_ok());
+
+        for (pairs, expected) in [
+            (
+                ("port", "abc"),
+                "Parameter 'port' must be of type int, got 'abc'",
+            ),
+            (
+                ("port", "80.5"),
+                "Parameter 'port' must be of type int, got '80.5'",
+            ),
+            (
+                ("ratio", "fast"),
+                "Parameter 'ratio' must be of type float, got 'fast'",
+            ),
+            (
+                ("verbose", "yes"),
+                "Parameter 'verbose' must be of type bool, got 'yes'",
+            ),
+        ] {
+            let error = valid(&[pairs]).unwrap_err().to_string();
+            assert_eq!(error, expected);
+        }
+    }
 
     #[test]
-    fn test_parse_key_val_success() {
-        let result = parse_key_val("name=Ali").unwrap();
-        assert_eq!(result, ("name".to_string(), "Ali".to_string()));
+    fn test_temp_dir_error_messages() {
+        let path = std::path::Path::new("/readonly/iz-1");
+        let denied = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
 
-        let result = parse_key_val("port=8080").unwrap();
-        assert_eq!(result, ("port".to_string(), "8080".to_string()));
+        assert_eq!(
+            create_dir_error(denied(), path).to_string(),
+            "Permission denied creating temp directory at /readonly/iz-1; choose a writable location with --temp-dir or IZTEMP"
+        );
+        assert_eq!(
+            remove_dir_error(denied(), path).to_string(),
+            "Permission denied removing temp directory at /readonly/iz-1; fix its permissions or remove it manually"
+        );
 
-        let result = parse_key_val("key=value=with=equals").unwrap();
-        assert_eq!(result, ("key".to_string(), "value=with=equals".to_string()));
+        let other = create_dir_error(std::io::Error::from(std::io::ErrorKind::NotFound), path);
+        assert_eq!(
+            other.to_string(),
+            "Failed to create temporary directory: /readonly/iz-1"
+        );
     }
 
     #[test]
-    fn test_parse_key_val_failure() {
-        let result = parse_key_val("invalid_format");
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("Invalid KEY=value format"));
+    fn test_parse_run_id() {
+        assert_eq!(parse_run_id("ci-42_a.1").unwrap(), "ci-42_a.1");
+        assert!(parse_run_id("").is_err());
+        assert!(parse_run_id("../escape").is_err());
+        assert!(parse_run_id("a/b").is_err());
+        assert!(parse_run_id("..").is_err());
     }
 
     #[test]
-    fn test_substitute_variables_success() {
-        let mut params = HashMap::new();
-        params.insert("name".to_string(), "Ali".to_string());
-        params.insert("port".to_string(), "8080".to_string());
+    fn test_container_argv() {
+        let argv = container_argv(
+            "docker",
+            "alpine:3",
+            std::path::Path::new(".iztemp/iz-1"),
+            std::path::Path::new("/repo/.iztemp/iz-1"),
+            None,
+            &[("IZ_RUN_ID".to_string(), "iz-1".to_string())],
+            &[
+                "sh".to_string(),
+                "-e".to_string(),
+                ".iztemp/iz-1/.iz-script-1.sh".to_string(),
+            ],
+        );
 
-        let result = substitute_variables("echo 'Merhaba #{name}!'", &params).unwrap();
-        assert_eq!(result, "echo 'Merhaba Ali!'");
+        assert_eq!(
+            argv,
+            [
+                "docker",
+                "run",
+                "--rm",
+                "-v",
+                "/repo/.iztemp/iz-1:/work",
+                "-w",
+                "/work",
+                "-e",
+                "IZ_RUN_ID",
+                "alpine:3",
+                "sh",
+                "-e",
+                "/work/.iz-script-1.sh",
+            ]
+        );
+    }
 
-        let result = substitute_variables("server --port #{port}", &params).unwrap();
-        assert_eq!(result, "server --port 8080");
+    #[test]
+    fn test_bench_stats() {
+        assert_eq!(BenchStats::from_durations(&[]), None);
 
-        let result = substitute_variables("greet #{name} on port #{port}", &params).unwrap();
-        assert_eq!(result, "greet Ali on port 8080");
+        let stats = BenchStats::from_durations(&[10.0, 20.0, 30.0]).unwrap();
+        assert_eq!(stats.runs, 3);
+        assert_eq!(stats.min_ms, 10.0);
+        assert_eq!(stats.mean_ms, 20.0);
+        assert_eq!(stats.max_ms, 30.0);
+        assert_eq!(stats.stddev_ms, 10.0);
+
+        let single = BenchStats::from_durations(&[5.0]).unwrap();
+        assert_eq!(single.stddev_ms, 0.0);
     }
 
     #[test]
-    fn test_substitute_variables_no_variables() {
-        let params = HashMap::new();
-        let result = substitute_variables("echo 'Hello World'", &params).unwrap();
-        assert_eq!(result, "echo 'Hello World'");
+    fn test_mask_secrets() {
+        let secrets = vec!["abc".to_string(), "abcdef".to_string(), String::new()];
+        assert_eq!(
+            mask_secrets("curl -H 'token: abcdef' --user abc", &secrets),
+            "curl -H 'token: ****' --user ****"
+        );
+        assert_eq!(mask_secrets("nothing here", &[]), "nothing here");
     }
 
     #[test]
-    fn test_substitute_variables_missing_param() {
-        let params = HashMap::new();
-        let result = substitute_variables("echo 'Hello #{name}'", &params);
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("Required parameter not found: name"));
+    fn test_yaml_config() {
+        let temp_dir = std::env::temp_dir().join("iz-test-config-yaml");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&temp_dir).unwrap();
+
+        let full_path = temp_dir.join("izconfig.yaml");
+        fs::write(
+            &full_path,
+            "commands:\n  run: dotnet run\n  serve:\n    template: python -m http.server #{port}\n    params: [port]\ntemp_dir: /tmp/iz-custom\nkeep: true\n",
+        )
+        .unwrap();
+        let config = read_config_from_path(&full_path).unwrap();
+        assert_eq!(config.commands.get("run").unwrap().template(), "dotnet run");
+        assert_eq!(
+            config.commands.get("serve").unwrap().declared_params(),
+            ["port"]
+        );
+        assert_eq!(config.temp_dir.as_deref(), Some("/tmp/iz-custom"));
+        assert_eq!(config.keep, Some(true));
+
+        let minimal_path = temp_dir.join("minimal.yml");
+        fs::write(&minimal_path, "commands:\n  run: dotnet run\n").unwrap();
+        let config = read_config_from_path(&minimal_path).unwrap();
+        assert_eq!(config.commands.len(), 1);
+        assert!(config.temp_dir.is_none());
+        assert!(config.keep.is_none());
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_read_config_from_path_success() {
-        let temp_dir = std::env::temp_dir().join("iz-test-config");
+    fn test_find_config_file_prefers_json() {
+        let temp_dir = std::env::temp_dir().join("iz-test-config-discovery");
+        let _ = fs::remove_dir_all(&temp_dir);
         fs::create_dir_all(&temp_dir).unwrap();
-        let config_path = temp_dir.join("izconfig.json");
 
-        let config_content = r#"
-        {
-            "commands": {
-                "run": "dotnet run",
-                "test": "dotnet test"
-            }
-        }"#;
+        assert_eq!(find_config_file(&temp_dir), temp_dir.join("izconfig.json"));
 
-        fs::write(&config_path, config_content).unwrap();
+        fs::write(temp_dir.join("izconfig.yml"), "commands: {}\n").unwrap();
+        assert_eq!(find_config_file(&temp_dir), temp_dir.join("izconfig.yml"));
+        assert!(shadowed_config_files(&temp_dir.join("izconfig.yml")).is_empty());
 
-        let config = read_config_from_path(&config_path).unwrap();
-        assert_eq!(config.commands.get("run").unwrap(), "dotnet run");
-        assert_eq!(config.commands.get("test").unwrap(), "dotnet test");
+        fs::write(temp_dir.join("izconfig.json"), "{}").unwrap();
+        let chosen = find_config_file(&temp_dir);
+        assert_eq!(chosen, temp_dir.join("izconfig.json"));
+        assert_eq!(
+            shadowed_config_files(&chosen),
+            [temp_dir.join("izconfig.yml")]
+        );
 
         // Cleanup
         let _ = fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_read_config_from_path_file_not_found() {
-        let temp_dir = std::env::temp_dir().join("iz-test-nonexistent");
-        fs::create_dir_all(&temp_dir).unwrap();
-        let config_path = temp_dir.join("nonexistent.json");
+    fn test_is_watch_relevant() {
+        let root = std::path::Path::new("/repo");
+        let git_dir = root.join(".git");
+        let ignored = vec![root.join(".iztemp")];
 
-        let result = read_config_from_path(&config_path);
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .to_string()
-            .contains("izconfig.json not found"));
+        assert!(is_watch_relevant(
+            &root.join("src/main.rs"),
+            &git_dir,
+            &ignored
+        ));
+        assert!(is_watch_relevant(&git_dir.join("HEAD"), &git_dir, &ignored));
+        assert!(is_watch_relevant(
+            &git_dir.join("refs/heads/main"),
+            &git_dir,
+            &ignored
+        ));
+        assert!(!is_watch_relevant(
+            &git_dir.join("index"),
+            &git_dir,
+            &ignored
+        ));
+        assert!(!is_watch_relevant(
+            &git_dir.join("objects/ab/cdef"),
+            &git_dir,
+            &ignored
+        ));
+        assert!(!is_watch_relevant(
+            &root.join(".iztemp/iz-1/file"),
+            &git_dir,
+            &ignored
+        ));
     }
 
     #[test]
-    fn test_read_config_from_path_invalid_json() {
-        let temp_dir = std::env::temp_dir().join("iz-test-invalid");
+    fn test_run_meta() {
+        let temp_dir = std::env::temp_dir().join("iz-test-run-meta");
+        let _ = fs::remove_dir_all(&temp_dir);
         fs::create_dir_all(&temp_dir).unwrap();
-        let config_path = temp_dir.join("izconfig.json");
 
-        fs::write(&config_path, "invalid json content").unwrap();
+        assert_eq!(read_run_meta(&temp_dir), None);
 
-        let result = read_config_from_path(&config_path);
-        assert!(result.is_err());
+        let meta = RunMeta::new("abc1234def", Some("test"));
+        write_run_meta(&temp_dir, &meta).unwrap();
+        let read = read_run_meta(&temp_dir).unwrap();
+        assert_eq!(read, meta);
+
+        assert!(read.matches(None, None));
+        assert!(read.matches(Some("test"), Some("abc12")));
+        assert!(!read.matches(Some("build"), None));
+        assert!(!read.matches(None, Some("def")));
+
+        let checkout_only = RunMeta::new("abc1234def", None);
+        assert!(!checkout_only.matches(Some("test"), None));
+
+        assert!(!meta.is_expired(u64::MAX));
+        let expiring = meta
+            .clone()
+            .expiring_after(Some(std::time::Duration::from_secs(60)));
+        assert_eq!(expiring.expires_at, Some(meta.started_at + 60));
+        assert!(!expiring.is_expired(meta.started_at + 59));
+        assert!(expiring.is_expired(meta.started_at + 60));
+        write_run_meta(&temp_dir, &expiring).unwrap();
+        assert_eq!(read_run_meta(&temp_dir).unwrap(), expiring);
 
         // Cleanup
         let _ = fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_iz_config_serde() {
-        let mut commands = HashMap::new();
-        commands.insert("run".to_string(), "dotnet run".to_string());
-        commands.insert("build".to_string(), "cargo build".to_string());
+    fn test_format_age() {
+        assert_eq!(format_age(0), "0s");
+        assert_eq!(format_age(59), "59s");
+        assert_eq!(format_age(60), "1m");
+        assert_eq!(format_age(7200), "2h");
+        assert_eq!(format_age(86400 * 3 + 5), "3d");
+    }
 
-        let config = IzConfig {
-            commands,
-            temp_dir: None,
-            keep: None,
-        };
+    #[test]
+    fn test_tree_stats() {
+        let root = std::env::temp_dir().join(format!("iz-test-stats-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join("README.md"), "hello").unwrap();
+        std::fs::write(root.join("src/lib.rs"), vec![b'x'; 2048]).unwrap();
+        std::fs::write(root.join("src/nested/a.txt"), "abc").unwrap();
+        std::fs::write(root.join("src/nested/b.txt"), "abc").unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/main").unwrap();
+        std::fs::write(root.join(RUN_META_FILE), "{}").unwrap();
 
-        // Serialize
-        let json = serde_json::to_string(&config).unwrap();
+        let stats = tree_stats(&root, 3).unwrap();
+        assert_eq!(stats.files, 4);
+        assert_eq!(stats.total_bytes, 2048 + 5 + 3 + 3);
+        let largest: Vec<(&str, u64)> = stats
+            .largest
+            .iter()
+            .map(|file| (file.path.as_str(), file.bytes))
+            .collect();
+        assert_eq!(
+            largest,
+            vec![
+                ("src/lib.rs", 2048),
+                ("README.md", 5),
+                ("src/nested/a.txt", 3)
+            ]
+        );
 
-        // Deserialize
-        let deserialized: IzConfig = serde_json::from_str(&json).unwrap();
-        assert_eq!(config, deserialized);
+        assert_eq!(dir_size(&root).unwrap(), 2048 + 5 + 3 + 3 + 20 + 2);
+
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
     }
 
     #[test]
-    fn test_iz_config_with_temp_dir_and_keep() {
-        let temp_dir = std::env::temp_dir().join("iz-test-config-extended");
-        fs::create_dir_all(&temp_dir).unwrap();
-        let config_path = temp_dir.join("izconfig.json");
+    fn test_apply_command_overrides() {
+        assert_eq!(
+            parse_command_override("command.unit.test.timeout=30s").unwrap(),
+            CommandOverride {
+                command: "unit.test".to_string(),
+                field: "timeout".to_string(),
+                value: "30s".to_string(),
+            }
+        );
+        assert!(parse_command_override("command.test=1").is_err());
+        assert!(parse_command_override("test.timeout=1").is_err());
+        assert!(parse_command_override("command.test.timeout").is_err());
 
-        let config_content = r#"
-        {
-            "commands": {
-                "run": "dotnet run",
-                "test": "dotnet test"
-            },
-            "temp_dir": "/tmp/iz-custom",
-            "keep": true
-        }"#;
+        let mut commands: HashMap<String, CommandEntry> = serde_json::from_str(
+            r#"{"test": "cargo test", "alias": {"alias": "test"}, "build": {"template": "cargo build", "pre": "echo pre"}, "lint": {"argv": ["cargo", "clippy"]}}"#,
+        )
+        .unwrap();
+        let set = |spec: &str| parse_command_override(spec).unwrap();
 
-        fs::write(&config_path, config_content).unwrap();
+        apply_command_overrides(
+            &mut commands,
+            &[
+                set("command.test.timeout=30"),
+                set("command.test.shell=true"),
+                set("command.build.params=[\"target\"]"),
+                set("command.build.template=42"),
+                set("command.build.workdir=crates/core"),
+                set("command.lint.timeout=2m"),
+                set("command.lint.workdir=crates/core"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(commands["test"].template(), "cargo test");
+        assert_eq!(commands["test"].timeout(), Some("30"));
+        assert!(commands["test"].shell());
+        assert_eq!(commands["build"].declared_params(), ["target"]);
+        assert_eq!(commands["build"].template(), "42");
+        assert_eq!(commands["build"].pre_hook(), Some("echo pre"));
+        assert_eq!(commands["build"].workdir(), Some("crates/core"));
+        assert_eq!(
+            commands["lint"].argv(),
+            Some(&["cargo".to_string(), "clippy".to_string()][..])
+        );
+        assert_eq!(commands["lint"].timeout(), Some("2m"));
+        assert_eq!(commands["lint"].workdir(), Some("crates/core"));
 
-        let config = read_config_from_path(&config_path).unwrap();
-        assert_eq!(config.commands.get("run").unwrap(), "dotnet run");
-        assert_eq!(config.temp_dir.as_ref().unwrap(), "/tmp/iz-custom");
-        assert!(config.keep.unwrap());
+        assert!(matches!(
+            apply_command_overrides(&mut commands, &[set("command.test.retries=3")]),
+            Err(IzError::UnknownCommandField { field, .. }) if field == "retries"
+        ));
+        assert!(matches!(
+            apply_command_overrides(&mut commands, &[set("command.test.workdir=/tmp")]),
+            Err(IzError::InvalidOverride { field, .. }) if field == "workdir"
+        ));
+        assert!(matches!(
+            apply_command_overrides(&mut commands, &[set("command.test.timeout=soon")]),
+            Err(IzError::InvalidOverride { field, .. }) if field == "timeout"
+        ));
+        assert!(matches!(
+            apply_command_overrides(&mut commands, &[set("command.test.shell=maybe")]),
+            Err(IzError::InvalidOverride { .. })
+        ));
+        assert!(matches!(
+            apply_command_overrides(&mut commands, &[set("command.lint.shell=true")]),
+            Err(IzError::InvalidOverride { field, .. }) if field == "shell"
+        ));
+        assert!(matches!(
+            apply_command_overrides(&mut commands, &[set("command.alias.pre=echo")]),
+            Err(IzError::InvalidOverride { .. })
+        ));
+        assert!(matches!(
+            apply_command_overrides(&mut commands, &[set("command.deploy.timeout=1s")]),
+            Err(IzError::CommandNotFound(name)) if name == "deploy"
+        ));
+    }
 
-        // Cleanup
-        let _ = fs::remove_dir_all(&temp_dir);
+    #[test]
+    fn test_unexpected_stderr_lines() {
+        let stderr = "warning: unused variable\n\nerror: boom\n  \nnote: see docs\n";
+        assert_eq!(
+            unexpected_stderr_lines(stderr, &[]),
+            ["warning: unused variable", "error: boom", "note: see docs"]
+        );
+        let allowlist = [
+            Regex::new("^warning:").unwrap(),
+            Regex::new("see docs").unwrap(),
+        ];
+        assert_eq!(unexpected_stderr_lines(stderr, &allowlist), ["error: boom"]);
+        assert!(unexpected_stderr_lines("", &[]).is_empty());
     }
 
     #[test]
-    fn test_iz_config_without_optional_fields() {
-        let temp_dir = std::env::temp_dir().join("iz-test-config-minimal");
-        fs::create_dir_all(&temp_dir).unwrap();
-        let config_path = temp_dir.join("izconfig.json");
+    fn test_group_command_names() {
+        assert_eq!(command_group("db:migrate"), Some("db"));
+        assert_eq!(command_group("db:migrate:up"), Some("db"));
+        assert_eq!(command_group("test"), None);
+        assert_eq!(command_group(":odd"), None);
 
-        let config_content = r#"
-        {
-            "commands": {
-                "run": "dotnet run"
+        let groups = group_command_names(["test", "db:reset", "app:run", "db:migrate", "build"]);
+        assert_eq!(
+            groups,
+            vec![
+                (Some("app"), vec!["app:run"]),
+                (Some("db"), vec!["db:migrate", "db:reset"]),
+                (None, vec!["build", "test"]),
+            ]
+        );
+        assert!(group_command_names([]).is_empty());
+    }
+
+    /// Records every argv it is asked to run and answers with canned exit codes
+    /// instead of spawning processes.
+    struct MockRunner {
+        calls: std::cell::RefCell<Vec<Vec<String>>>,
+        exit_codes: HashMap<String, i32>,
+    }
+
+    impl MockRunner {
+        fn new(exit_codes: &[(&str, i32)]) -> Self {
+            MockRunner {
+                calls: Default::default(),
+                exit_codes: exit_codes
+                    .iter()
+                    .map(|(program, code)| (program.to_string(), *code))
+                    .collect(),
             }
-        }"#;
+        }
+    }
 
-        fs::write(&config_path, config_content).unwrap();
+    impl CommandRunner for MockRunner {
+        fn run(
+            &self,
+            argv: &[String],
+            _cwd: &std::path::Path,
+            env: &[(String, String)],
+        ) -> Result<CommandOutput> {
+            self.calls.borrow_mut().push(argv.to_vec());
+            if argv[0] == "missing" {
+                return Err(anyhow::anyhow!("No such file or directory"));
+            }
+            let code = self.exit_codes.get(&argv[0]).copied().unwrap_or(0);
+            #[cfg(unix)]
+            let status = std::os::unix::process::ExitStatusExt::from_raw(code << 8);
+            #[cfg(windows)]
+            let status = std::os::windows::process::ExitStatusExt::from_raw(code as u32);
+            Ok(CommandOutput {
+                status,
+                stdout: format!("{} {}", argv.join(" "), env.len()).into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+    }
 
-        let config = read_config_from_path(&config_path).unwrap();
-        assert_eq!(config.commands.get("run").unwrap(), "dotnet run");
-        assert!(config.temp_dir.is_none());
-        assert!(config.keep.is_none());
+    #[test]
+    fn test_run_hooked_with_mock_runner() {
+        let argv = |command: &str| build_argv(command).unwrap();
+        let (pre, main, post) = (argv("pre-hook"), argv("cargo test"), argv("post-hook"));
+        let env = [("IZ_COMMIT".to_string(), "abc".to_string())];
+        let cwd = std::path::Path::new(".");
+        fn hooked<'a>(
+            pre: &'a [String],
+            argv: &'a [String],
+            post: &'a [String],
+            hooks_fail_run: bool,
+        ) -> HookedCommand<'a> {
+            HookedCommand {
+                pre: Some(pre),
+                argv,
+                post: Some(post),
+                hooks_fail_run,
+            }
+        }
 
-        // Cleanup
-        let _ = fs::remove_dir_all(&temp_dir);
+        let runner = MockRunner::new(&[]);
+        let mut events = Vec::new();
+        let outcome = run_hooked(
+            &runner,
+            &hooked(&pre, &main, &post, true),
+            cwd,
+            &env,
+            |event| {
+                events.push(match event {
+                    StepEvent::Started(step) => format!("start {step}"),
+                    StepEvent::Finished(step, output) => {
+                        format!("end {step} {}", output.stdout_text())
+                    }
+                })
+            },
+        )
+        .unwrap();
+        assert!(outcome.pre_ok && outcome.post_ok);
+        assert_eq!(
+            outcome.output.unwrap().unwrap().stdout_text(),
+            "cargo test 1"
+        );
+        assert_eq!(
+            *runner.calls.borrow(),
+            [pre.clone(), main.clone(), post.clone()]
+        );
+        assert_eq!(
+            events,
+            [
+                "start pre",
+                "end pre pre-hook 1",
+                "start main",
+                "end main cargo test 1",
+                "start post",
+                "end post post-hook 1"
+            ]
+        );
+
+        // A failing pre hook skips the command, but the post hook still runs
+        let runner = MockRunner::new(&[("pre-hook", 1), ("post-hook", 2)]);
+        let outcome = run_hooked(
+            &runner,
+            &hooked(&pre, &main, &post, true),
+            cwd,
+            &env,
+            |_| {},
+        )
+        .unwrap();
+        assert!(!outcome.pre_ok && !outcome.post_ok);
+        assert!(outcome.output.is_none());
+        assert_eq!(*runner.calls.borrow(), [pre.clone(), post.clone()]);
+
+        // ...unless hook failures are ignored
+        let outcome = run_hooked(
+            &runner,
+            &hooked(&pre, &main, &post, false),
+            cwd,
+            &env,
+            |_| {},
+        )
+        .unwrap();
+        assert!(outcome.output.is_some());
+
+        // A command that can't be started is reported after the post hook ran
+        let runner = MockRunner::new(&[]);
+        let missing = argv("missing");
+        let outcome = run_hooked(
+            &runner,
+            &hooked(&pre, &missing, &post, true),
+            cwd,
+            &env,
+            |_| {},
+        )
+        .unwrap();
+        assert!(outcome.output.unwrap().is_err());
+        assert_eq!(runner.calls.borrow().last(), Some(&post));
+
+        // A pre hook that can't be started skips the command, and the post
+        // hook still runs
+        let runner = MockRunner::new(&[]);
+        let outcome = run_hooked(
+            &runner,
+            &hooked(&missing, &main, &post, true),
+            cwd,
+            &env,
+            |_| {},
+        )
+        .unwrap();
+        assert!(!outcome.pre_ok && outcome.post_ok);
+        assert_eq!(
+            outcome.pre_error.unwrap().to_string(),
+            "Failed to execute pre hook"
+        );
+        assert!(outcome.output.is_none());
+        assert_eq!(*runner.calls.borrow(), [missing.clone(), post.clone()]);
+
+        // A post hook that can't be started fails the whole step
+        let error = run_hooked(
+            &runner,
+            &hooked(&pre, &main, &missing, true),
+            cwd,
+            &env,
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Failed to execute post hook");
+    }
+
+    #[test]
+    fn test_argv_command() {
+        let commands: HashMap<String, CommandEntry> = serde_json::from_str(
+            r##"{"run": {"argv": ["cargo", "run", "--", "#{message}"], "params": ["message"]}}"##,
+        )
+        .unwrap();
+        let entry = &commands["run"];
+        assert!(matches!(entry, CommandEntry::Argv(_)));
+        assert_eq!(entry.template(), "");
+        assert_eq!(entry.declared_params(), ["message"]);
+        assert!(!entry.shell());
+
+        let params = HashMap::from([("message".to_string(), "hello  world".to_string())]);
+        let argv = substitute_argv(entry.argv().unwrap(), |arg| {
+            substitute_variables(arg, &params)
+        })
+        .unwrap();
+        assert_eq!(argv, ["cargo", "run", "--", "hello  world"]);
+        assert_eq!(join_argv(&argv), "cargo run -- 'hello  world'");
+
+        let resolved = resolve_command(&commands, "run").unwrap();
+        let names: Vec<String> = command_params(&resolved)
+            .into_iter()
+            .map(|param| param.name)
+            .collect();
+        assert_eq!(names, ["message"]);
+
+        let empty_program = ["#{runner}".to_string(), "test".to_string()];
+        let params = HashMap::from([("runner".to_string(), String::new())]);
+        assert!(substitute_argv(&empty_program, |arg| substitute_variables(arg, &params)).is_err());
+        assert!(substitute_argv(&[], |arg| substitute_variables(arg, &params)).is_err());
     }
 }
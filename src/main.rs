@@ -1,18 +1,162 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use base64::Engine;
+use clap::{Parser, ValueEnum};
 use git2::Repository;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
+use std::io::{BufRead, IsTerminal, Read, Write};
 use std::path::PathBuf;
-use std::process::Command;
-use std::sync::Mutex;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 #[cfg(unix)]
 use tokio::signal;
 
-use iz::{parse_key_val, read_config, substitute_variables};
+use iz::events;
+use iz::notifier;
+use iz::{
+    append_args, apply_command_overrides, ascii_decorations, build_argv, check_allowed_command,
+    check_declared_params, check_param_types, check_program_substituted, command_group,
+    command_params, container_argv, convert_line_endings, create_dir_error,
+    create_dir_with_retries, dir_size, error_phase, first_output_change, format_age, format_bytes,
+    global_config_path, group_command_names, is_success_code, is_watch_relevant, join_argv,
+    mask_secrets, match_command_prefix, parse_command_override, parse_commit_list, parse_duration,
+    parse_keep, parse_key_json, parse_key_val, parse_run_id, parse_stash_ref, ram_temp_base,
+    read_commit_file, read_config_from_path_as, read_config_from_reader, read_env_file,
+    read_global_config, read_izignore, read_run_meta, remove_dir_error, rename_to_trash,
+    repair_latest_link, resolve_command, resolve_config_path, resolve_temp_base, restore_writable,
+    run_dir_age, run_hooked, shadowed_config_files, spawn_error, substitute_argv,
+    substitute_file_paths, substitute_variables_with_json, system_temp_base, tree_stats,
+    truncation_marker, unexpected_stderr_lines, unified_diff, update_latest_link,
+    validate_config_as, validate_config_content, workdir_path, write_run_meta, AfterResult,
+    AutoClean, BatchSummary, BenchStats, CappedBuffer, CleanupStrategy, CommandOutput,
+    CommandOverride, CommandRunner, ConfigFormat, HookedCommand, HookedOutcome, IgnoreRules,
+    IzError, KeepSetting, OutputChange, OutputTail, Phase, PhaseContext, RunMeta, RunReport,
+    RunReportEntry, RunResult, RunSpec, Severity, Step, StepEvent, TreeStats, AUTO_CLEAN_INTERVAL,
+    AUTO_CLEAN_STAMP_FILE, LATEST_LINK, LATEST_TAG_SPEC, RUN_META_FILE, TRASH_DIR_NAME,
+    TREE_STATS_LARGEST,
+};
 
-static CLEANUP_STATE: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+/// Temporary directories removed if iz is interrupted; more than one while
+/// commits run in parallel.
+static CLEANUP_STATE: Lazy<Mutex<Vec<(PathBuf, CleanupStrategy)>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+/// `--binary-output`: stdout carries only the command's bytes, so status lines go to stderr.
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+/// `--no-emoji` or `IZ_NO_EMOJI`: print ASCII tags instead of emoji.
+static NO_EMOJI: AtomicBool = AtomicBool::new(false);
+/// Working directory of the repository commits are read from when it isn't
+/// the current directory (`--submodule`).
+static REPO_DIR: OnceCell<PathBuf> = OnceCell::new();
+/// `--config -`: the config read from stdin, once, for every later read.
+static STDIN_CONFIG: OnceCell<String> = OnceCell::new();
+/// `--config-format`: parse the config as this instead of guessing from its extension.
+static CONFIG_FORMAT: OnceCell<ConfigFormat> = OnceCell::new();
+
+const TEMP_DIR_ATTEMPTS: usize = 5;
+/// Paths listed before asking to overwrite files with `--into --force`.
+const OVERWRITE_PREVIEW_LIMIT: usize = 10;
+/// Quiet period that ends a burst of file events in `--watch` mode.
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
+const TIMEOUT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Like `println!`, with emoji swapped for ASCII tags under `--no-emoji`.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        println!("{}", decorate(&format!($($arg)*)))
+    };
+}
+
+/// Like `eprintln!`, with emoji swapped for ASCII tags under `--no-emoji`.
+macro_rules! say_err {
+    ($($arg:tt)*) => {
+        eprintln!("{}", decorate(&format!($($arg)*)))
+    };
+}
+
+/// Prints a human-readable status line; silenced when `--output json` is
+/// active and sent to stderr under `--binary-output`.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !JSON_OUTPUT.load(Ordering::Relaxed) {
+            if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+                say_err!($($arg)*);
+            } else {
+                say!($($arg)*);
+            }
+        }
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Human,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OpenMode {
+    Shell,
+    Files,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum SymlinkMode {
+    // Keep symlinks (copied on Windows when they can't be created)
+    Preserve,
+    // Replace symlinks with a copy of their target
+    Copy,
+    // Leave symlinks out of the checkout
+    Skip,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum CheckoutStrategy {
+    // Overwrite whatever is in the target directory
+    Force,
+    // Stop and list the paths that would be overwritten
+    Safe,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum TimeoutSignal {
+    // SIGTERM first, then SIGKILL once the grace period is over
+    Term,
+    // Kill immediately
+    Kill,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum LineEndings {
+    // Whatever .gitattributes and core.autocrlf produce
+    Auto,
+    Lf,
+    Crlf,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ContainerRuntime {
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    fn program(self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ReportFormat {
+    Json,
+    Md,
+}
 
 #[derive(Parser)]
 #[command(
@@ -21,267 +165,4594 @@ static CLEANUP_STATE: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(Non
     version = "0.1.0"
 )]
 struct Cli {
-    /// Git commit ID or 'clean' command
-    commit_id_or_command: String,
+    /// Git commit ID, or the 'bench', 'clean', 'diff', 'validate' or 'version' command
+    commit_id_or_command: Option<String>,
 
-    /// Command to execute (not needed for 'clean')
+    /// Command to execute (not needed for 'clean'; config path for 'validate'; commit for 'bench' and 'diff')
     command: Option<String>,
 
-    /// Keep temporary directory after execution
+    /// Command to benchmark with 'bench', or the second commit for 'diff'
+    bench_command: Option<String>,
+
+    /// Command to compare with 'diff'
+    diff_command: Option<String>,
+
+    /// Extra arguments appended to the command as-is, given after --
+    #[arg(last = true)]
+    extra_args: Vec<String>,
+
+    /// The commit, command, params, env and options as one JSON object, overriding the flags
+    #[arg(long, value_name = "JSON")]
+    spec: Option<String>,
+
+    /// Read the --spec JSON object from a file
+    #[arg(long, value_name = "PATH", conflicts_with = "spec")]
+    spec_file: Option<PathBuf>,
+
+    /// Run as if iz was started in this directory (like git -C)
+    #[arg(long = "chdir", short = 'C', value_name = "DIR")]
+    chdir: Option<PathBuf>,
+
+    /// Config file to use instead of ./izconfig.json (overrides IZ_CONFIG; - reads it from stdin)
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Parse the config as this format instead of guessing from its extension (JSON for stdin)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    config_format: Option<ConfigFormat>,
+
+    /// Read the commit ID from a file instead of the positional argument
+    #[arg(long)]
+    commit_file: Option<PathBuf>,
+
+    /// POST a JSON summary of the run to this URL when it finishes (needs the notify feature)
+    #[arg(long, value_name = "URL", conflicts_with_all = ["stdin_commits", "commits_file", "watch"])]
+    notify: Option<String>,
+
+    /// Run this shell command when the run finishes, with the outcome in IZ_* environment variables
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["stdin_commits", "commits_file", "watch"])]
+    notify_command: Option<String>,
+
+    /// Abort when the repository has uncommitted changes to tracked files
+    #[arg(long)]
+    require_clean: bool,
+
+    /// Run against the most recent tag instead of a positional commit (same as the commit @latest-tag)
+    #[arg(long, conflicts_with_all = ["commit_file", "stdin_commits", "commits_file"])]
+    since_tag: bool,
+
+    /// Take commits from this submodule (name or path) instead of the current repository
+    #[arg(long)]
+    submodule: Option<String>,
+
+    /// Read commit IDs from stdin, one per line, and run the command against each
+    #[arg(long, conflicts_with_all = ["commit_file", "checkout_only", "into"])]
+    stdin_commits: bool,
+
+    /// Read commit IDs from a file (one per line, # comments allowed) and run the command against each
+    #[arg(long, conflicts_with_all = ["commit_file", "stdin_commits", "checkout_only", "into"])]
+    commits_file: Option<PathBuf>,
+
+    /// Keep temporary directory after execution (--keep=false to force cleanup, --keep=1h to remove it on a run after an hour)
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_parser = parse_keep)]
+    keep: Option<KeepSetting>,
+
+    /// Temporary directory path (default: .iztemp; repeatable for clean)
+    #[arg(long)]
+    temp_dir: Vec<String>,
+
+    /// Skip the config's auto_clean of stale temporary directories for this run
+    #[arg(long)]
+    no_auto_clean: bool,
+
+    /// Use <system temp>/iz as the temporary directory instead of ./.iztemp
+    #[arg(long, conflicts_with = "temp_dir")]
+    system_temp: bool,
+
+    /// Check out on a RAM-backed filesystem (/dev/shm) for speed, if one is available
+    #[arg(long, conflicts_with_all = ["temp_dir", "system_temp"])]
+    ram: bool,
+
+    /// Additional parameters (--key=value format)
+    #[arg(long, value_parser = parse_key_val)]
+    param: Vec<(String, String)>,
+
+    /// Parameter whose value is masked as **** in all displayed output (KEY=value)
+    #[arg(long, value_parser = parse_key_val)]
+    secret: Vec<(String, String)>,
+
+    /// Structured parameter (KEY=JSON), referenced as #{key.path.to.value}
+    #[arg(long, value_parser = parse_key_json)]
+    param_json: Vec<(String, serde_json::Value)>,
+
+    /// Environment variable for the executed command (KEY=value, overrides --env-file)
+    #[arg(long, value_parser = parse_key_val)]
+    env: Vec<(String, String)>,
+
+    /// Load environment variables for the executed command from a .env file
+    #[arg(long)]
+    env_file: Option<PathBuf>,
+
+    /// Re-run the command whenever files in the current directory change (Ctrl+C to stop)
+    #[arg(long, conflicts_with_all = ["stdin_commits", "commits_file", "checkout_only"])]
+    watch: bool,
+
+    /// Use iz-<ID> as the temporary directory name instead of a generated one
+    #[arg(long, value_parser = parse_run_id, conflicts_with_all = ["stdin_commits", "commits_file", "into"])]
+    run_id: Option<String>,
+
+    /// Check the commit out directly into this directory (kept by default)
+    #[arg(long)]
+    into: Option<PathBuf>,
+
+    /// Force operation without confirmation (for clean, a non-empty --into directory, or an existing --run-id; IZ_ASSUME_YES also forces clean)
+    #[arg(long)]
+    force: bool,
+
+    /// Answer yes to confirmation prompts, such as overwriting files with --into --force
+    #[arg(long, short)]
+    yes: bool,
+
+    /// List what clean would remove without deleting anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Clean the config and command temp_dirs, IZTEMP, ./.iztemp and <system temp>/iz in one pass
+    #[arg(long)]
+    all_known: bool,
+
+    /// List temporary directories with their commit, command and age instead of cleaning
+    #[arg(long)]
+    list: bool,
+
+    /// Show `iz list` grouped by the command-name prefix before the first ':'
+    #[arg(long)]
+    group: bool,
+
+    /// Print `iz list` names only, each ended by a NUL byte instead of a newline (for xargs -0)
+    #[arg(long = "null", short = '0', conflicts_with = "group")]
+    null_separated: bool,
+
+    /// Exit successfully from clean even when some directories could not be removed
+    #[arg(long)]
+    ignore_errors: bool,
+
+    /// Only clean (or list) directories created for this command
+    #[arg(long)]
+    match_command: Option<String>,
+
+    /// Only clean (or list) directories created for commits starting with this prefix
+    #[arg(long)]
+    match_commit: Option<String>,
+
+    /// Check the commit out into a kept temporary directory without running a command
+    #[arg(long)]
+    checkout_only: bool,
+
+    /// Open the checkout in $SHELL (or the file manager with --open=files) before cleanup
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "shell"
+    )]
+    open: Option<OpenMode>,
+
+    /// Log every directory created and file written by the checkout to stderr (or --trace-fs=FILE)
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-"
+    )]
+    trace_fs: Option<PathBuf>,
+
+    /// Also set up a .git in the checkout with the commit as HEAD (shares objects with this repo)
+    #[arg(long)]
+    with_git: bool,
+
+    /// Whether checkout overwrites existing files in the target directory or reports them as conflicts
+    #[arg(long, value_enum, default_value_t = CheckoutStrategy::Force)]
+    checkout_strategy: CheckoutStrategy,
+
+    /// Line endings for checked-out text files (auto follows .gitattributes and core.autocrlf)
+    #[arg(long, value_enum, default_value_t = LineEndings::Auto)]
+    line_endings: LineEndings,
+
+    /// Make the checked-out files read-only so the command can't edit the source in place (Unix)
+    #[arg(long, conflicts_with = "into")]
+    readonly: bool,
+
+    /// Print the checkout's file count, total size and largest files
+    #[arg(long)]
+    stats: bool,
+
+    /// Check out only the files added or modified since this commit
+    #[arg(long, value_name = "BASE")]
+    changed_since: Option<String>,
+
+    /// How symlinks in the commit are checked out
+    #[arg(long, value_enum, default_value_t = SymlinkMode::Preserve)]
+    symlink_mode: SymlinkMode,
+
+    /// Exit code to treat as success (repeatable, overrides success_codes in the config)
+    #[arg(long = "success-code")]
+    success_codes: Vec<i32>,
+
+    /// Run the command through the shell (multiline templates run as a script file)
+    #[arg(long)]
+    shell: bool,
+
+    /// Config command to run as a verification step after the main command succeeds
+    #[arg(long)]
+    after: Option<String>,
+
+    /// Fail the run when the --after command fails (otherwise it is only reported)
+    #[arg(long, requires = "after")]
+    fail_on_after: bool,
+
+    /// Fail the run when the command writes anything to stderr, even if it exits successfully
+    #[arg(long, conflicts_with = "pty")]
+    fail_on_stderr: bool,
+
+    /// Stderr lines matching this regex don't count for --fail-on-stderr (repeatable)
+    #[arg(long, value_name = "REGEX", requires = "fail_on_stderr", value_parser = Regex::new)]
+    stderr_allowlist: Vec<Regex>,
+
+    /// Exit non-zero when the temporary directory could not be cleaned up
+    #[arg(long)]
+    strict: bool,
+
+    /// Run commands inside this container image, with the checkout as the working directory
+    #[arg(long)]
+    container: Option<String>,
+
+    /// Container runtime for --container (default: docker if available, else podman)
+    #[arg(long, value_enum)]
+    runtime: Option<ContainerRuntime>,
+
+    /// Number of timed runs for 'bench'
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    runs: u32,
+
+    /// Check the commit out again before every 'bench' run instead of reusing one checkout
+    #[arg(long)]
+    fresh_checkout: bool,
+
+    /// List the parameters a command takes instead of running it
+    #[arg(long)]
+    list_params: bool,
+
+    /// Accept a unique prefix of a command name, e.g. 'te' for 'test'
+    #[arg(long)]
+    fuzzy: bool,
+
+    /// Override one field of a command for this run (repeatable), e.g. command.test.timeout=30s
+    #[arg(long = "set", value_name = "command.NAME.FIELD=VALUE", value_parser = parse_command_override)]
+    set: Vec<CommandOverride>,
+
+    /// Print the effective configuration (global, local, environment and CLI overrides) as JSON and exit
     #[arg(long)]
-    keep: bool,
+    print_config: bool,
+
+    /// Print extra diagnostics, such as the exact argv of each executed command
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// Stream command output as it arrives instead of buffering it
+    #[arg(long)]
+    stream: bool,
+
+    /// Number of trailing output lines to retain in streaming mode
+    #[arg(long, default_value_t = 1000)]
+    tail: usize,
+
+    /// Maximum bytes of stdout and of stderr to capture or forward; the rest is dropped
+    #[arg(long)]
+    max_output: Option<usize>,
+
+    /// Stop a command that runs longer than this (e.g. 30s, 5m)
+    #[arg(long, value_parser = parse_duration)]
+    timeout: Option<std::time::Duration>,
+
+    /// How a timed-out command is stopped
+    #[arg(long, value_enum, default_value_t = TimeoutSignal::Term)]
+    timeout_signal: TimeoutSignal,
+
+    /// How long a command may take to exit after SIGTERM before it is killed
+    #[arg(long, value_parser = parse_duration, default_value = "5s")]
+    grace_period: std::time::Duration,
+
+    /// Pass command stdout through as raw bytes (base64-encoded in JSON output)
+    #[arg(long)]
+    binary_output: bool,
+
+    /// Run the command on a pseudo-terminal so it sees a TTY (Unix; stderr is merged into stdout)
+    #[arg(long, conflicts_with = "binary_output")]
+    pty: bool,
+
+    /// Output format for the run result
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    output: OutputFormat,
+
+    /// Write progress events to stderr as JSON lines (for wrapper UIs)
+    #[arg(long)]
+    events: bool,
+
+    /// Print ASCII tags such as [ok] and [warn] instead of emoji (also IZ_NO_EMOJI)
+    #[arg(long)]
+    no_emoji: bool,
+
+    /// Write a summary report of the run to this file
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// How many commits from --stdin-commits or --commits-file run at once (default: number of CPUs)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+
+    /// Report the first commit from --stdin-commits or --commits-file whose output or exit code differs from the one before
+    #[arg(long)]
+    detect_change: bool,
+
+    /// Format of the --report file
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    report_format: ReportFormat,
+}
+
+struct CommitInfo {
+    id: String,
+    short_id: String,
+    summary: String,
+    author: String,
+    head: Option<HeadState>,
+}
+
+/// What `HEAD` pointed at, for revisions like `HEAD` or `HEAD~2`.
+struct HeadState {
+    branch: Option<String>,
+    short_id: String,
+}
+
+impl HeadState {
+    fn head_ref(&self) -> String {
+        self.branch
+            .clone()
+            .unwrap_or_else(|| "detached".to_string())
+    }
+}
+
+impl std::fmt::Display for HeadState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.branch {
+            Some(branch) => write!(f, "HEAD → {branch} @ {}", self.short_id),
+            None => write!(f, "HEAD detached @ {}", self.short_id),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RunOptions {
+    config_path: PathBuf,
+    keep: Option<bool>,
+    /// `--keep=<duration>`: stamped into the run metadata as its expiry
+    keep_for: Option<std::time::Duration>,
+    temp_dir: Option<String>,
+    /// Apply the config's `auto_clean` before creating the temp dir
+    auto_clean: bool,
+    run_id: Option<String>,
+    into: Option<PathBuf>,
+    force: bool,
+    assume_yes: bool,
+    param: Vec<(String, String)>,
+    param_json: Vec<(String, serde_json::Value)>,
+    symlink_mode: SymlinkMode,
+    with_git: bool,
+    checkout_strategy: CheckoutStrategy,
+    line_endings: LineEndings,
+    readonly: bool,
+    stats: bool,
+    changed_since: Option<String>,
+    trace_fs: Option<Arc<FsTrace>>,
+    /// `--set` overrides applied to the config's commands
+    overrides: Vec<CommandOverride>,
+    open: Option<OpenMode>,
+    shell: bool,
+    success_codes: Vec<i32>,
+    after: Option<String>,
+    fail_on_after: bool,
+    fail_on_stderr: bool,
+    stderr_allowlist: Vec<Regex>,
+    strict: bool,
+    container: Option<String>,
+    runtime: Option<ContainerRuntime>,
+    /// Arguments given after `--`, appended after substitution
+    extra_args: Vec<String>,
+    /// Print the JSON result on a single line (one line per commit in batch mode)
+    compact_json: bool,
+    /// Keep the JSON result in the report entry instead of printing it
+    collect_json: bool,
+    exec: ExecOptions,
+}
+
+/// A config command resolved for `--after`.
+struct AfterCommand {
+    name: String,
+    final_command: String,
+    /// Empty when the command runs through the shell
+    argv: Vec<String>,
+    shell: bool,
+}
+
+struct CheckoutOptions {
+    precreate_dirs: bool,
+    symlink_mode: SymlinkMode,
+    with_git: bool,
+    strategy: CheckoutStrategy,
+    line_endings: LineEndings,
+    readonly: bool,
+    /// Collect a [`TreeStats`] summary once the files are written
+    stats: bool,
+    /// Base commit for `--changed-since`
+    changed_since: Option<String>,
+    trace: Option<Arc<FsTrace>>,
+}
+
+/// Where `--trace-fs` logs the checkout's filesystem operations.
+struct FsTrace {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl FsTrace {
+    /// `-` is stderr; any other path is created (or truncated) as the trace file.
+    fn open(target: &std::path::Path) -> Result<FsTrace> {
+        let out: Box<dyn Write + Send> = match target.to_str() {
+            Some("-") => Box::new(std::io::stderr()),
+            _ => Box::new(
+                fs::File::create(target)
+                    .with_context(|| format!("Failed to create trace file {}", target.display()))?,
+            ),
+        };
+        Ok(FsTrace {
+            out: Mutex::new(out),
+        })
+    }
+
+    fn log(&self, operation: &str, path: &std::path::Path) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = writeln!(out, "[fs] {operation} {}", path.display());
+        }
+    }
+}
+
+#[derive(Default)]
+struct CheckoutSummary {
+    skipped_symlinks: Vec<String>,
+    tree_stats: Option<TreeStats>,
+}
+
+/// Image and runtime that commands run in with `--container`.
+#[derive(Clone)]
+struct ContainerTarget {
+    runtime: ContainerRuntime,
+    image: String,
+}
+
+#[derive(Clone)]
+struct ExecOptions {
+    stream: bool,
+    tail: usize,
+    max_output: Option<usize>,
+    binary_output: bool,
+    /// Give the command a pseudo-terminal instead of pipes (Unix only)
+    pty: bool,
+    allowed_commands: Option<Vec<String>>,
+    container: Option<ContainerTarget>,
+    /// Don't echo captured output (buffered mode only)
+    quiet: bool,
+    /// Values masked wherever a command or argv is displayed
+    secrets: Vec<String>,
+    env: Vec<(String, String)>,
+    verbose: bool,
+    timeout: Timeout,
+    /// Keep commands off stdin, which `--stdin-commits` is reading commits from
+    null_stdin: bool,
+    /// The command's `workdir`, relative to the checkout the runner is given
+    workdir: Option<String>,
+}
+
+/// How long a command may run and how it is stopped when it overruns.
+#[derive(Clone)]
+struct Timeout {
+    limit: Option<std::time::Duration>,
+    signal: TimeoutSignal,
+    grace_period: std::time::Duration,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut cli = Cli::parse();
+    NO_EMOJI.store(
+        cli.no_emoji || std::env::var_os("IZ_NO_EMOJI").is_some_and(|value| !value.is_empty()),
+        Ordering::Relaxed,
+    );
+    // Everything below, including relative paths in other options, resolves from here
+    if let Some(dir) = &cli.chdir {
+        std::env::set_current_dir(dir)
+            .with_context(|| format!("Failed to change directory to {}", dir.display()))?;
+    }
+    let spec = match (&cli.spec, &cli.spec_file) {
+        (Some(json), _) => Some(RunSpec::from_json(json).context("Invalid --spec")?),
+        (None, Some(path)) => {
+            let json = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read spec file: {}", path.display()))?;
+            Some(
+                RunSpec::from_json(&json)
+                    .with_context(|| format!("Invalid spec file: {}", path.display()))?,
+            )
+        }
+        (None, None) => None,
+    };
+    if let Some(spec) = spec {
+        apply_spec(&mut cli, spec)?;
+    }
+    if cli.system_temp {
+        cli.temp_dir
+            .push(system_temp_base().to_string_lossy().into_owned());
+    }
+    if cli.ram {
+        match ram_temp_base() {
+            Some(dir) => cli.temp_dir.push(dir.to_string_lossy().into_owned()),
+            None => say_err!(
+                "⚠️  No RAM-backed filesystem (/dev/shm) found; using the normal temp directory"
+            ),
+        }
+    }
+    if cli.notify.is_some() && !notifier::NOTIFY_URL_SUPPORTED {
+        return Err(anyhow::anyhow!(
+            "--notify needs iz built with the `notify` feature (cargo build --features notify)"
+        ));
+    }
+    if cli.pty && !cfg!(unix) {
+        say_err!("⚠️  --pty is only supported on Unix; the command gets piped output");
+        cli.pty = false;
+    }
+    JSON_OUTPUT.store(cli.output == OutputFormat::Json, Ordering::Relaxed);
+    STATUS_TO_STDERR.store(cli.binary_output, Ordering::Relaxed);
+    if cli.events {
+        events::enable();
+    }
+    let config_path = resolve_config_path(cli.config.as_deref())?;
+    if let Some(format) = cli.config_format {
+        let _ = CONFIG_FORMAT.set(format);
+    }
+    if config_path.as_os_str() == "-" {
+        if cli.stdin_commits {
+            return Err(anyhow::anyhow!(
+                "--config - and --stdin-commits both read stdin; put the config or the commits in a file"
+            ));
+        }
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read the config from stdin")?;
+        let _ = STDIN_CONFIG.set(content);
+    }
+    for shadowed in shadowed_config_files(&config_path) {
+        say_err!(
+            "⚠️  Ignoring {} because {} takes precedence",
+            shadowed.display(),
+            config_path.display()
+        );
+    }
+
+    // Check if first argument is "clean" command
+    if cli.commit_id_or_command.as_deref() == Some("clean") {
+        let clean_options = CleanOptions {
+            temp_dirs: cli.temp_dir,
+            all_known: cli.all_known,
+            force: cli.force
+                || std::env::var("IZ_ASSUME_YES").is_ok_and(|value| {
+                    matches!(value.to_lowercase().as_str(), "1" | "true" | "yes")
+                }),
+            dry_run: cli.dry_run,
+            list: cli.list,
+            ignore_errors: cli.ignore_errors,
+            match_command: cli.match_command,
+            match_commit: cli.match_commit,
+        };
+        return clean_command(&config_path, clean_options).await;
+    }
+
+    if cli.commit_id_or_command.as_deref() == Some("validate") {
+        let path = cli.command.map(PathBuf::from).unwrap_or(config_path);
+        return validate_command(&path);
+    }
+
+    if cli.commit_id_or_command.as_deref() == Some("list") {
+        let layout = match (cli.group, cli.null_separated) {
+            (true, _) => ListLayout::Grouped,
+            (_, true) => ListLayout::NullSeparated,
+            _ => ListLayout::Plain,
+        };
+        return list_command(&config_path, cli.command.as_deref(), layout);
+    }
+
+    if cli.commit_id_or_command.as_deref() == Some("version") {
+        return version_command(cli.verbose);
+    }
+
+    if cli.list_params {
+        let command = cli
+            .commit_id_or_command
+            .ok_or_else(|| anyhow::anyhow!("Usage: iz --list-params <command>"))?;
+        return list_params_command(&config_path, &command);
+    }
+
+    if let Some(name) = &cli.submodule {
+        let dir = submodule_dir(name).phase(Phase::Checkout)?;
+        status!("📦 Submodule: {name} ({})", dir.display());
+        REPO_DIR
+            .set(dir)
+            .expect("the repository is only chosen once");
+    }
+
+    let temp_dir = match cli.temp_dir.as_slice() {
+        [] => None,
+        [dir] => Some(dir.clone()),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Multiple --temp-dir values are only supported by the clean command"
+            ))
+        }
+    };
+
+    if cli.print_config {
+        let overrides = iz::IzConfig {
+            temp_dir: temp_dir.or_else(|| std::env::var("IZTEMP").ok()),
+            keep: cli.keep.map(KeepSetting::keeps),
+            success_codes: (!cli.success_codes.is_empty()).then_some(cli.success_codes),
+            container: cli.container,
+            ..iz::IzConfig::default()
+        };
+        return print_config_command(&config_path, &cli.set, overrides);
+    }
+
+    let is_bench = cli.commit_id_or_command.as_deref() == Some("bench");
+    let is_diff = cli.commit_id_or_command.as_deref() == Some("diff");
+    if let (false, false, Some(extra)) = (is_bench, is_diff, &cli.bench_command) {
+        return Err(anyhow::anyhow!("Unexpected argument '{extra}'"));
+    }
+    if let (false, Some(extra)) = (is_diff, &cli.diff_command) {
+        return Err(anyhow::anyhow!("Unexpected argument '{extra}'"));
+    }
+    let diff_usage = || anyhow::anyhow!("Usage: iz diff <commit-a> <commit-b> <command>");
+    let diff_against = if is_diff {
+        Some(cli.bench_command.clone().ok_or_else(diff_usage)?)
+    } else {
+        None
+    };
+
+    // With --commit-file, --stdin-commits or --commits-file the first positional argument is the command
+    let multi_commit = cli.stdin_commits || cli.commits_file.is_some();
+    if cli.detect_change && !multi_commit {
+        return Err(anyhow::anyhow!(
+            "--detect-change needs --stdin-commits or --commits-file"
+        ));
+    }
+    let (commit_id, command) = match &cli.commit_file {
+        _ if is_bench => {
+            let commit_id = cli.command.ok_or_else(|| {
+                anyhow::anyhow!("Usage: iz bench <commit-id> <command> [--runs N]")
+            })?;
+            (Some(commit_id), cli.bench_command)
+        }
+        _ if is_diff => {
+            let commit_id = cli.command.ok_or_else(diff_usage)?;
+            (Some(commit_id), cli.diff_command)
+        }
+        _ if multi_commit => {
+            if cli.command.is_some() {
+                let flag = if cli.stdin_commits {
+                    "--stdin-commits"
+                } else {
+                    "--commits-file"
+                };
+                return Err(anyhow::anyhow!(
+                    "Pass either a positional commit ID or {flag}, not both"
+                ));
+            }
+            (None, cli.commit_id_or_command)
+        }
+        _ if cli.since_tag => {
+            if cli.command.is_some() {
+                return Err(anyhow::anyhow!(
+                    "Pass either a positional commit ID or --since-tag, not both"
+                ));
+            }
+            (Some(LATEST_TAG_SPEC.to_string()), cli.commit_id_or_command)
+        }
+        Some(path) => {
+            if cli.command.is_some() {
+                return Err(anyhow::anyhow!(
+                    "Pass either a positional commit ID or --commit-file, not both"
+                ));
+            }
+            (Some(read_commit_file(path)?), cli.commit_id_or_command)
+        }
+        None => {
+            let commit_id = cli.commit_id_or_command.ok_or_else(|| {
+                anyhow::anyhow!("A commit is required: pass <commit-id> or --commit-file <path>")
+            })?;
+            (Some(commit_id), cli.command)
+        }
+    };
+
+    let commit_id = match commit_id {
+        Some(spec) if spec == LATEST_TAG_SPEC => {
+            let (tag, commit) = latest_tag(&open_repository()?)?;
+            status!("🏷️  Latest tag: {tag} ({commit})");
+            Some(tag)
+        }
+        commit_id => commit_id,
+    };
+
+    // A user-chosen --into directory is never removed by a later run
+    if cli.into.is_some() && cli.keep.and_then(KeepSetting::duration).is_some() {
+        return Err(anyhow::anyhow!(
+            "--keep=<duration> can't be combined with --into; use --keep to keep the directory"
+        ));
+    }
+
+    if cli.require_clean {
+        check_clean_worktree()?;
+    }
+
+    // Opened once so batch runs append to the same trace
+    let trace_fs = cli
+        .trace_fs
+        .as_deref()
+        .map(FsTrace::open)
+        .transpose()?
+        .map(Arc::new);
+
+    if cli.checkout_only {
+        let commit_id = commit_id.expect("--checkout-only conflicts with --stdin-commits");
+        let checkout_options = CheckoutOptions {
+            precreate_dirs: true,
+            symlink_mode: cli.symlink_mode,
+            with_git: cli.with_git,
+            strategy: cli.checkout_strategy,
+            line_endings: cli.line_endings,
+            readonly: cli.readonly,
+            stats: cli.stats,
+            changed_since: cli.changed_since.clone(),
+            trace: trace_fs.clone(),
+        };
+        let config = read_config(&config_path).context("Failed to read izconfig.json")?;
+        let base_temp_dir = determine_temp_dir(&temp_dir, None, &config)?;
+        remove_expired_runs(&base_temp_dir);
+        let checkout_options = CheckoutOptions {
+            precreate_dirs: config.precreate_dirs.unwrap_or(true),
+            ..checkout_options
+        };
+        return checkout_only_command(
+            &base_temp_dir,
+            commit_id,
+            cli.run_id.as_deref(),
+            cli.force,
+            cli.keep.and_then(KeepSetting::duration),
+            checkout_options,
+            cli.open,
+        )
+        .await;
+    }
+
+    let command = match command {
+        Some(command) => command,
+        None if std::io::stdout().is_terminal() && std::io::stdin().is_terminal() => {
+            select_command(&config_path)?
+        }
+        None => {
+            return Err(anyhow::anyhow!(
+                "Command is required. Usage: iz <commit-id> <command> or iz clean"
+            ))
+        }
+    };
+    let command = if cli.fuzzy {
+        let config = read_config(&config_path).context("Failed to read izconfig.json")?;
+        let matched = match_command_prefix(&config.commands, &command)?;
+        if matched != command {
+            status!("🔎 '{command}' matched command '{matched}'");
+        }
+        matched
+    } else {
+        command
+    };
+
+    // Relative --env-file paths resolve against the invocation directory
+    let mut env = match &cli.env_file {
+        Some(path) => read_env_file(path)?,
+        None => Vec::new(),
+    };
+    env.extend(cli.env);
+
+    // Secrets are substituted like any --param but never displayed
+    let secrets: Vec<String> = cli.secret.iter().map(|(_, value)| value.clone()).collect();
+    let mut param = cli.param;
+    param.extend(cli.secret);
+
+    let options = RunOptions {
+        config_path,
+        keep: cli.keep.map(KeepSetting::keeps),
+        keep_for: cli.keep.and_then(KeepSetting::duration),
+        temp_dir,
+        auto_clean: !cli.no_auto_clean,
+        run_id: cli.run_id,
+        into: cli.into,
+        force: cli.force,
+        assume_yes: cli.yes,
+        param,
+        param_json: cli.param_json,
+        symlink_mode: cli.symlink_mode,
+        with_git: cli.with_git,
+        checkout_strategy: cli.checkout_strategy,
+        line_endings: cli.line_endings,
+        readonly: cli.readonly,
+        stats: cli.stats,
+        changed_since: cli.changed_since,
+        trace_fs,
+        overrides: cli.set,
+        open: cli.open,
+        shell: cli.shell,
+        success_codes: cli.success_codes,
+        after: cli.after,
+        fail_on_after: cli.fail_on_after,
+        fail_on_stderr: cli.fail_on_stderr,
+        stderr_allowlist: cli.stderr_allowlist,
+        strict: cli.strict,
+        container: cli.container,
+        runtime: cli.runtime,
+        extra_args: cli.extra_args,
+        compact_json: multi_commit,
+        collect_json: false,
+        exec: ExecOptions {
+            stream: cli.stream,
+            tail: cli.tail,
+            max_output: cli.max_output,
+            binary_output: cli.binary_output,
+            pty: cli.pty,
+            allowed_commands: None,
+            container: None,
+            quiet: false,
+            secrets,
+            env,
+            verbose: cli.verbose,
+            timeout: Timeout {
+                limit: cli.timeout,
+                signal: cli.timeout_signal,
+                grace_period: cli.grace_period,
+            },
+            null_stdin: cli.stdin_commits,
+            workdir: None,
+        },
+    };
+
+    let Some(commit_id) = commit_id else {
+        let report = (cli.report, cli.report_format);
+        let jobs = match cli.jobs {
+            Some(jobs) => jobs as usize,
+            None => std::thread::available_parallelism().map_or(1, usize::from),
+        };
+        if let Some(path) = &cli.commits_file {
+            let commits = read_commits_file(path)?;
+            let commits = commits.into_iter().map(Ok);
+            return commits_command(commits, command, options, report, jobs, cli.detect_change)
+                .await;
+        }
+        let commits = std::io::stdin().lock().lines().map(|line| {
+            line.context("Failed to read commits from stdin")
+                .map(|line| line.trim().to_string())
+        });
+        return commits_command(commits, command, options, report, jobs, cli.detect_change).await;
+    };
+
+    if cli.watch {
+        return watch_command(commit_id, command, options).await;
+    }
+
+    if let Some(other_commit) = diff_against {
+        return diff_commits(commit_id, other_commit, command, options).await;
+    }
+
+    if is_bench {
+        let bench_options = BenchOptions {
+            runs: cli.runs as usize,
+            fresh_checkout: cli.fresh_checkout,
+        };
+        return bench_command(commit_id, command, options, bench_options).await;
+    }
+
+    let (report_entry, result) = execute_run(commit_id, command, options).await;
+    notify_completion(
+        &report_entry,
+        cli.notify.as_deref(),
+        cli.notify_command.as_deref(),
+    );
+
+    if let Some(path) = &cli.report {
+        let report = RunReport {
+            runs: vec![report_entry],
+        };
+        write_report(path, cli.report_format, &report)?;
+    }
+
+    // A failing command's own exit code is passed on, anything else exits 1
+    if let Err(error) = &result {
+        let code = command_exit_code(error).unwrap_or(1);
+        if cli.output == OutputFormat::Json {
+            print_json_error(error);
+            std::process::exit(code);
+        }
+        if code != 1 {
+            eprintln!("Error: {error:?}");
+            std::process::exit(code);
+        }
+    }
+
+    result
+}
+
+/// The exit code of the main command when `error` is its failure; `None` for
+/// other errors, for commands killed by a signal and for a `0` that isn't
+/// among the success codes.
+fn command_exit_code(error: &anyhow::Error) -> Option<i32> {
+    error
+        .chain()
+        .find_map(|cause| match cause.downcast_ref::<IzError>() {
+            Some(IzError::CommandFailed { code }) => code.filter(|&code| code != 0),
+            _ => None,
+        })
+}
+
+/// Runs `command` against one commit and records the outcome for the report.
+async fn execute_run(
+    commit_id: String,
+    command: String,
+    options: RunOptions,
+) -> (RunReportEntry, Result<()>) {
+    let started = std::time::Instant::now();
+    let mut report_entry = RunReportEntry {
+        commit: commit_id.clone(),
+        command: command.clone(),
+        ..Default::default()
+    };
+    let result = match run_exec_options(&command, &options) {
+        Ok(exec) => {
+            let runner = LocalRunner { options: &exec };
+            let options = RunOptions {
+                exec: exec.clone(),
+                ..options
+            };
+            run_command(commit_id, command, options, &runner, &mut report_entry).await
+        }
+        Err(error) => Err(error),
+    };
+    report_entry.duration_ms = started.elapsed().as_millis() as u64;
+
+    (report_entry, result)
+}
+
+/// Completes a run's exec options from the config: the allowlist, the
+/// container, and the command's timeout and secret params.
+fn run_exec_options(command: &str, options: &RunOptions) -> Result<ExecOptions> {
+    let config = load_config(&options.config_path, &options.overrides).phase(Phase::Config)?;
+    let mut exec_options = options.exec.clone();
+    exec_options.allowed_commands = config.allowed_commands.clone();
+    exec_options.container = container_target(options.container.clone(), options.runtime, &config)
+        .phase(Phase::Config)?;
+
+    let resolved = resolve_command(&config.commands, command).phase(Phase::Config)?;
+    apply_command_timeout(&mut exec_options, resolved.entry).phase(Phase::Config)?;
+    exec_options.workdir = resolved.entry.workdir().map(str::to_string);
+    let mut params = resolved.params;
+    params.extend(options.param.iter().cloned());
+    add_secret_params(&mut exec_options, resolved.entry, &params);
+    Ok(exec_options)
+}
+
+/// Sends the `--notify` and `--notify-command` notifications for a finished
+/// run. A failed notification only warns; it doesn't change the run's result.
+fn notify_completion(run: &RunReportEntry, url: Option<&str>, command: Option<&str>) {
+    if let Some(url) = url {
+        if let Err(e) = notifier::post_summary(url, run) {
+            say_err!("⚠️  Failed to notify {url}: {e:#}");
+        }
+    }
+    if let Some(command) = command {
+        if let Err(e) = run_notify_command(command, run) {
+            say_err!("⚠️  Notify command failed: {e:#}");
+        }
+    }
+}
+
+fn run_notify_command(command: &str, run: &RunReportEntry) -> Result<()> {
+    let (argv, script_path) = shell_invocation(command, &std::env::temp_dir())?;
+    let output = Command::new(&argv[0])
+        .args(&argv[1..])
+        .envs(notifier::notify_env(run))
+        .stdin(Stdio::null())
+        .output();
+    if let Some(script_path) = &script_path {
+        let _ = fs::remove_file(script_path);
+    }
+
+    let output = output.with_context(|| format!("Failed to start {}", argv[0]))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `command` against two commits and compares their stdout and exit codes,
+/// failing when they differ.
+async fn diff_commits(
+    commit_a: String,
+    commit_b: String,
+    command: String,
+    mut options: RunOptions,
+) -> Result<()> {
+    options.exec.quiet = true;
+    options.collect_json = true;
+
+    let mut runs = Vec::new();
+    for commit_id in [&commit_a, &commit_b] {
+        let (entry, result) =
+            execute_run(commit_id.clone(), command.clone(), options.clone()).await;
+        // A failing command is still compared; a run that never got to it is not
+        if entry.exit_code.is_none() {
+            result.with_context(|| format!("Failed to run '{command}' against {commit_id}"))?;
+        }
+        runs.push(entry);
+    }
+
+    let (a, b) = (&runs[0], &runs[1]);
+    let diff = unified_diff(
+        &String::from_utf8_lossy(&a.stdout),
+        &String::from_utf8_lossy(&b.stdout),
+        &format!("a/{commit_a}"),
+        &format!("b/{commit_b}"),
+    );
+    let identical = diff.is_empty() && a.exit_code == b.exit_code;
+    let exit_code = |code: Option<i32>| code.map_or("-".to_string(), |code| code.to_string());
+
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let result = serde_json::json!({
+            "commits": [commit_a, commit_b],
+            "command": command,
+            "exit_codes": [a.exit_code, b.exit_code],
+            "runs": [a.json_result, b.json_result],
+            "identical": identical,
+            "diff": diff,
+        });
+        println!("{}", serde_json::to_string(&result)?);
+    } else {
+        if a.exit_code != b.exit_code {
+            println!(
+                "Exit code: {} ({commit_a}) vs {} ({commit_b})",
+                exit_code(a.exit_code),
+                exit_code(b.exit_code)
+            );
+        }
+        print!("{diff}");
+    }
+
+    if !identical {
+        return Err(anyhow::anyhow!(
+            "Output of '{command}' differs between {commit_a} and {commit_b}"
+        ));
+    }
+    status!("✅ Output of '{command}' is identical for {commit_a} and {commit_b}");
+    Ok(())
+}
+
+/// Runs the command, then again after every burst of changes under the current
+/// directory. Every run gets a fresh checkout of whatever the revision points
+/// at by then, so a moved branch or `HEAD` is picked up.
+async fn watch_command(commit_id: String, command: String, options: RunOptions) -> Result<()> {
+    let watch_root = std::env::current_dir().context("Failed to get current directory")?;
+    let repo = open_repository()?;
+    let git_dir = repo.path().to_path_buf();
+
+    let config = load_config(&options.config_path, &options.overrides)?;
+    let command_entry = resolve_command(&config.commands, &command)
+        .ok()
+        .map(|resolved| resolved.entry);
+    let temp_base = determine_temp_dir(&options.temp_dir, command_entry, &config)?;
+    let mut ignored = vec![watch_root.join(temp_base)];
+    ignored.extend(options.into.iter().map(|dir| watch_root.join(dir)));
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = sender.send(event);
+        }
+    })
+    .context("Failed to start file watcher")?;
+    notify::Watcher::watch(&mut watcher, &watch_root, notify::RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", watch_root.display()))?;
+
+    let is_relevant = |event: &notify::Event| {
+        !event.kind.is_access()
+            && event
+                .paths
+                .iter()
+                .any(|path| is_watch_relevant(path, &git_dir, &ignored))
+    };
+
+    let mut last_commit = None;
+    loop {
+        match resolve_commit_info(&commit_id) {
+            Ok(info) => {
+                if last_commit.as_ref().is_some_and(|last| *last != info.id) {
+                    status!("🔀 {commit_id} moved to {}", info.short_id);
+                }
+                last_commit = Some(info.id);
+            }
+            Err(error) => say_err!("❌ {error:#}"),
+        }
+
+        let (_, result) = execute_run(commit_id.clone(), command.clone(), options.clone()).await;
+        if let Err(error) = result {
+            if JSON_OUTPUT.load(Ordering::Relaxed) {
+                print_json_error(&error);
+            } else {
+                say_err!("❌ {error:#}");
+            }
+        }
+
+        status!(
+            "👀 Watching {} for changes (Ctrl+C to stop)...",
+            watch_root.display()
+        );
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    status!("\n👋 Stopped watching");
+                    return Ok(());
+                }
+                event = receiver.recv() => {
+                    let event = event.ok_or_else(|| anyhow::anyhow!("File watcher stopped"))?;
+                    if is_relevant(&event) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Let a burst of events (an editor save, a checkout) settle into one run
+        while let Ok(Some(_)) = tokio::time::timeout(WATCH_DEBOUNCE, receiver.recv()).await {}
+        status!("🔁 Change detected, re-running...");
+    }
+}
+
+fn print_json_error(error: &anyhow::Error) {
+    let error_report = serde_json::json!({
+        "error": format!("{error:#}"),
+        "phase": error_phase(error),
+    });
+    eprintln!("{error_report}");
+}
+
+/// Reads a `--commits-file` and checks that every listed commit resolves, so a
+/// typo fails the batch before anything runs.
+fn read_commits_file(path: &std::path::Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read commits file: {}", path.display()))?;
+    let commits = parse_commit_list(&content);
+    if commits.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Commits file lists no commits: {}",
+            path.display()
+        ));
+    }
+
+    let repo = open_repository()?;
+    let unresolved: Vec<String> = commits
+        .iter()
+        .filter_map(|(line, commit_id)| {
+            let error = find_commit(&repo, commit_id).err()?;
+            Some(format!("  line {line}: {commit_id} ({error})"))
+        })
+        .collect();
+    if !unresolved.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} of {} commits in {} could not be resolved:\n{}",
+            unresolved.len(),
+            commits.len(),
+            path.display(),
+            unresolved.join("\n")
+        ))
+        .phase(Phase::Checkout);
+    }
+
+    Ok(commits
+        .into_iter()
+        .map(|(_, commit_id)| commit_id)
+        .collect())
+}
+
+/// Runs the command against every commit in turn, as they arrive.
+async fn commits_command(
+    commits: impl Iterator<Item = Result<String>>,
+    command: String,
+    options: RunOptions,
+    (report, report_format): (Option<PathBuf>, ReportFormat),
+    jobs: usize,
+    detect_change: bool,
+) -> Result<()> {
+    let started = std::time::Instant::now();
+    let slots = Arc::new(tokio::sync::Semaphore::new(jobs));
+    let runtime = tokio::runtime::Handle::current();
+    let mut pending = Vec::new();
+    let mut read_error = None;
+
+    for commit_id in commits {
+        let commit_id = match commit_id {
+            Ok(commit_id) => commit_id,
+            Err(error) => {
+                read_error = Some(error);
+                break;
+            }
+        };
+        if commit_id.is_empty() {
+            continue;
+        }
+
+        // Runs beyond the limit queue here until a running one finishes
+        let slot = Arc::clone(&slots)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let (command, options, runtime) = (command.clone(), options.clone(), runtime.clone());
+        let run_commit = commit_id.clone();
+        // Commands are waited on synchronously, so each run gets its own blocking thread
+        let handle = tokio::task::spawn_blocking(move || {
+            let _slot = slot;
+            runtime.block_on(execute_run(run_commit, command, options))
+        });
+        pending.push((commit_id, handle));
+    }
+
+    let mut runs = Vec::new();
+    let mut commit_ids = Vec::new();
+    let mut summary = BatchSummary::default();
+    for (commit_id, handle) in pending {
+        let (report_entry, result) = handle.await.context("Run for a commit panicked")?;
+
+        summary.record(&report_entry);
+        match &result {
+            Ok(()) => status!("📊 {commit_id}: ✅ passed"),
+            Err(error) => {
+                if JSON_OUTPUT.load(Ordering::Relaxed) {
+                    print_json_error(error);
+                } else {
+                    say_err!("📊 {commit_id}: ❌ {error:#}");
+                }
+            }
+        }
+
+        runs.push(report_entry);
+        commit_ids.push(commit_id);
+    }
+    if let Some(error) = read_error {
+        return Err(error);
+    }
+
+    if detect_change {
+        print_output_change(&commit_ids, first_output_change(&runs).as_ref());
+    }
+
+    if let Some(path) = &report {
+        write_report(path, report_format, &RunReport { runs })?;
+    }
+
+    summary.duration_ms = started.elapsed().as_millis() as u64;
+    print_batch_summary(&summary);
+    if !summary.all_passed() {
+        return Err(anyhow::anyhow!(
+            "{} of {} commits failed",
+            summary.total - summary.passed,
+            summary.total
+        ));
+    }
+
+    status!("🎉 All {} commits passed", summary.total);
+    Ok(())
+}
+
+/// Prints the batch totals as a table, or as a `summary` object with `--output json`.
+fn print_batch_summary(summary: &BatchSummary) {
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        println!("{}", serde_json::json!({ "summary": summary }));
+        return;
+    }
+
+    let headers = ["TOTAL", "PASSED", "FAILED", "SKIPPED", "DURATION"];
+    let values = [
+        summary.total.to_string(),
+        summary.passed.to_string(),
+        summary.failed.to_string(),
+        summary.skipped.to_string(),
+        format!("{:.2}s", summary.duration_ms as f64 / 1000.0),
+    ];
+    let widths: Vec<usize> = headers
+        .iter()
+        .zip(&values)
+        .map(|(header, value)| header.len().max(value.len()))
+        .collect();
+    for row in [headers.map(str::to_string), values] {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("  {}", cells.join("  ").trim_end());
+    }
+}
+
+/// Prints where the output first changed, in the spirit of `git bisect`.
+fn print_output_change(commit_ids: &[String], change: Option<&OutputChange>) {
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let first_change = change.map(|change| {
+            serde_json::json!({
+                "commit": commit_ids[change.index],
+                "previous": commit_ids[change.index - 1],
+                "exit_code_changed": change.exit_code_changed,
+                "stdout_changed": change.stdout_changed,
+            })
+        });
+        println!("{}", serde_json::json!({ "first_change": first_change }));
+        return;
+    }
+
+    let Some(change) = change else {
+        say!(
+            "🔎 No change in output or exit code across {} commits",
+            commit_ids.len()
+        );
+        return;
+    };
+    let what = match (change.exit_code_changed, change.stdout_changed) {
+        (true, true) => "exit code and output",
+        (true, false) => "exit code",
+        _ => "output",
+    };
+    say!(
+        "🔎 {} is the first commit whose {what} changed",
+        commit_ids[change.index]
+    );
+    println!("   previous commit: {}", commit_ids[change.index - 1]);
+}
+
+/// Runs `command` against one commit through `runner`. `options.exec` must
+/// already be completed by [`run_exec_options`].
+async fn run_command(
+    commit_id: String,
+    command: String,
+    options: RunOptions,
+    runner: &dyn CommandRunner,
+    report_entry: &mut RunReportEntry,
+) -> Result<()> {
+    let RunOptions {
+        config_path,
+        keep,
+        keep_for,
+        temp_dir,
+        auto_clean,
+        run_id,
+        into,
+        force,
+        assume_yes,
+        param,
+        param_json,
+        symlink_mode,
+        with_git,
+        checkout_strategy,
+        line_endings,
+        readonly,
+        stats,
+        changed_since,
+        trace_fs,
+        overrides,
+        open,
+        shell,
+        success_codes,
+        after,
+        fail_on_after,
+        fail_on_stderr,
+        stderr_allowlist,
+        strict,
+        container: _,
+        runtime: _,
+        extra_args,
+        compact_json,
+        collect_json,
+        exec: mut exec_options,
+    } = options;
+
+    status!("🔄 Starting iz CLI...");
+
+    let config = load_config(&config_path, &overrides).phase(Phase::Config)?;
+    let resolved = resolve_command(&config.commands, &command).phase(Phase::Config)?;
+    let command_entry = resolved.entry;
+
+    // --param values override the params an alias presets
+    let mut params = resolved.params;
+    params.extend(param);
+    let json_params: HashMap<String, serde_json::Value> = param_json.into_iter().collect();
+
+    // A --param-json key satisfies a declared param of the same name
+    let mut provided = params.clone();
+    for key in json_params.keys() {
+        provided.entry(key.clone()).or_default();
+    }
+    check_declared_params(command_entry.declared_params(), &provided).phase(Phase::Config)?;
+    if let Some(types) = command_entry.param_types() {
+        check_param_types(types, &params).phase(Phase::Config)?;
+    }
+    let mask = |text: &str| mask_secrets(text, &exec_options.secrets);
+
+    let substitute =
+        |template: &str| substitute_variables_with_json(template, &params, &json_params);
+    let shell = shell || command_entry.shell();
+    let (command_line, mut argv) = prepare_command(
+        command_entry,
+        resolved.name,
+        shell,
+        &params,
+        &substitute,
+        &exec_options,
+    )
+    .phase(Phase::Config)?;
+    // Arguments after `--` are passed through verbatim, never substituted
+    let substituted_args = argv.len();
+    argv.extend(extra_args.iter().cloned());
+    let final_command = append_args(&command_line, &extra_args);
+    let pre_hook = command_entry
+        .pre_hook()
+        .map(substitute)
+        .transpose()
+        .phase(Phase::Config)?;
+    let post_hook = command_entry
+        .post_hook()
+        .map(substitute)
+        .transpose()
+        .phase(Phase::Config)?;
+    let hooks_fail_run = !command_entry.ignore_hook_failures();
+
+    let after = match after {
+        Some(name) => {
+            if !config.commands.contains_key(&name) {
+                return Err(anyhow::anyhow!(
+                    "After command '{}' not found in izconfig.json",
+                    name
+                ))
+                .phase(Phase::Config);
+            }
+            let resolved = resolve_command(&config.commands, &name).phase(Phase::Config)?;
+            let entry = resolved.entry;
+            let shell = shell || entry.shell();
+            let (final_command, argv) = prepare_command(
+                entry,
+                resolved.name,
+                shell,
+                &params,
+                &substitute,
+                &exec_options,
+            )
+            .phase(Phase::Config)?;
+            Some(AfterCommand {
+                name,
+                final_command,
+                argv,
+                shell,
+            })
+        }
+        None => None,
+    };
+
+    let commit_info = resolve_commit_info(&commit_id).phase(Phase::Checkout)?;
+    report_entry.commit = commit_info.id.clone();
+    report_entry.subject = commit_info.summary.clone();
+
+    status!("🎯 Commit: {commit_id} ({})", commit_info.short_id);
+    status!("💬 Subject: {}", commit_info.summary);
+    status!("👤 Author: {}", commit_info.author);
+    if let Some(head) = &commit_info.head {
+        status!("🧭 {head}");
+    }
+    status!("📝 Command: {}", mask(&final_command));
+
+    // A user-chosen --into directory is kept unless --keep=false is explicit
+    let (should_keep, temp_path) = match &into {
+        Some(dir) => {
+            let dir = prepare_into_dir(dir, force).phase(Phase::Checkout)?;
+            // The safe strategy refuses to overwrite anything by itself
+            if checkout_strategy == CheckoutStrategy::Force {
+                confirm_overwrite(&commit_id, &dir, assume_yes).phase(Phase::Checkout)?;
+            }
+            (keep.unwrap_or(true), dir)
+        }
+        None => {
+            let base_temp_dir = determine_temp_dir(&temp_dir, Some(command_entry), &config)
+                .phase(Phase::Checkout)?;
+            remove_expired_runs(&base_temp_dir);
+            if auto_clean {
+                auto_clean_base_dir(&base_temp_dir, &config);
+            }
+            enforce_max_temp_bytes(&base_temp_dir, &config, &commit_id).phase(Phase::Checkout)?;
+            (
+                keep.unwrap_or(config.keep.unwrap_or(false)),
+                create_unique_temp_dir(
+                    &base_temp_dir,
+                    run_id.as_deref(),
+                    force,
+                    &RunMeta {
+                        readonly,
+                        ..RunMeta::new(&commit_info.id, Some(&command)).expiring_after(keep_for)
+                    },
+                )
+                .phase(Phase::Checkout)?,
+            )
+        }
+    };
+
+    // Moving a user-chosen --into directory somewhere else would be surprising
+    let cleanup_strategy = match into {
+        Some(_) => CleanupStrategy::Remove,
+        None => config.cleanup_strategy.unwrap_or_default(),
+    };
+
+    if !should_keep {
+        register_cleanup(&temp_path, cleanup_strategy);
+    }
+    let mut cleanup_guard = CleanupGuard {
+        temp_path: temp_path.clone(),
+        strategy: cleanup_strategy,
+        armed: !should_keep,
+    };
+
+    status!("📁 Temporary directory: {}", temp_path.display());
+
+    let signal_handle = if !should_keep {
+        Some(tokio::spawn(async {
+            let _ = setup_signal_handler().await;
+        }))
+    } else {
+        None
+    };
+
+    let checkout_options = CheckoutOptions {
+        precreate_dirs: config.precreate_dirs.unwrap_or(true),
+        symlink_mode,
+        with_git,
+        strategy: checkout_strategy,
+        line_endings,
+        readonly,
+        stats,
+        changed_since,
+        trace: trace_fs,
+    };
+    let checkout_summary = checkout_commit_to_temp(&commit_id, &temp_path, &checkout_options)
+        .with_context(|| IzError::CheckoutFailed {
+            commit: commit_id.clone(),
+        })
+        .phase(Phase::Checkout)?;
+    report_skipped_symlinks(&checkout_summary);
+    report_tree_stats(&checkout_summary);
+    if should_keep && into.is_none() {
+        link_latest(&temp_path);
+    }
+
+    // Placed first so --env and --env-file values override them
+    let run_id = temp_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let iz_env = [
+        ("IZ_COMMIT", commit_info.id.clone()),
+        ("IZ_TEMP_DIR", temp_path.display().to_string()),
+        ("IZ_COMMAND_NAME", command.clone()),
+        ("IZ_RUN_ID", run_id),
+    ];
+    exec_options
+        .env
+        .splice(0..0, iz_env.map(|(key, value)| (key.to_string(), value)));
+
+    // `#{file:...}` placeholders point into the checkout, so they resolve only now
+    let checkout_root = temp_path
+        .canonicalize()
+        .context("Failed to resolve the checkout path")
+        .phase(Phase::Execute)?;
+    let resolve_files = |text: &str| substitute_file_paths(text, &checkout_root);
+    let final_command = append_args(
+        &resolve_files(&command_line).phase(Phase::Config)?,
+        &extra_args,
+    );
+    for arg in &mut argv[..substituted_args] {
+        *arg = resolve_files(arg).phase(Phase::Config)?;
+    }
+    let resolve_hook = |hook: &str| -> Result<Vec<String>> {
+        build_argv(hook)?
+            .iter()
+            .map(|arg| Ok(resolve_files(arg)?))
+            .collect()
+    };
+
+    let script_path = if shell {
+        let (shell_argv, script_path) =
+            shell_invocation(&final_command, &checkout_root).phase(Phase::Execute)?;
+        argv = shell_argv;
+        script_path
+    } else {
+        None
+    };
+
+    let pre_argv = pre_hook
+        .as_deref()
+        .map(|hook| resolve_hook(hook).context("Invalid pre hook"))
+        .transpose()
+        .phase(Phase::Execute)?;
+    let post_argv = post_hook
+        .as_deref()
+        .map(|hook| resolve_hook(hook).context("Invalid post hook"))
+        .transpose()
+        .phase(Phase::Execute)?;
+
+    // The main command is skipped when a failing pre hook fails the run,
+    // but the post hook always runs.
+    let hooked = HookedCommand {
+        pre: pre_argv.as_deref(),
+        argv: &argv,
+        post: post_argv.as_deref(),
+        hooks_fail_run,
+    };
+    let outcome = run_hooked(
+        runner,
+        &hooked,
+        &temp_path,
+        &exec_options.env,
+        |event| match event {
+            StepEvent::Started(Step::Main) => status!("🚀 Executing command..."),
+            StepEvent::Started(hook) => {
+                let command = match hook {
+                    Step::Pre => pre_hook.as_deref(),
+                    _ => post_hook.as_deref(),
+                };
+                status!(
+                    "🪝 Running {hook} hook: {}",
+                    mask(command.unwrap_or_default())
+                );
+            }
+            StepEvent::Finished(Step::Main, _) => {}
+            StepEvent::Finished(hook, output) => {
+                if !output.status.success() {
+                    say_err!("⚠️  {hook} hook failed with status: {}", output.status);
+                }
+            }
+        },
+    );
+
+    if let Some(script_path) = &script_path {
+        let _ = fs::remove_file(script_path);
+    }
+
+    let HookedOutcome {
+        pre_error,
+        output,
+        post_ok,
+        ..
+    } = outcome.phase(Phase::Execute)?;
+    if let Some(error) = pre_error {
+        if hooks_fail_run {
+            return Err(error).phase(Phase::Execute);
+        }
+        say_err!("⚠️  {error:#}");
+    }
+    let output = match output {
+        Some(output) => output
+            .context("Failed to execute command")
+            .phase(Phase::Execute)?,
+        None => {
+            return Err(anyhow::anyhow!("pre hook failed, command was not executed"))
+                .phase(Phase::Execute)
+        }
+    };
+
+    report_entry.executed = true;
+    report_entry.exit_code = output.status.code();
+    report_entry.stdout = output.stdout.clone();
+    let success_codes = if success_codes.is_empty() {
+        config.success_codes.as_deref()
+    } else {
+        Some(success_codes.as_slice())
+    };
+    let exit_ok = is_success_code(output.status.code(), success_codes);
+    let stderr_text = output.stderr_text();
+    let unexpected_stderr = if fail_on_stderr && exit_ok {
+        unexpected_stderr_lines(&stderr_text, &stderr_allowlist)
+    } else {
+        Vec::new()
+    };
+    let command_ok = exit_ok && unexpected_stderr.is_empty();
+
+    // Verifying a failed command tells nothing new, so --after only follows a success
+    let after_result = match &after {
+        Some(after) if command_ok => Some(
+            run_after_command(after, &temp_path, runner, &exec_options).phase(Phase::Execute)?,
+        ),
+        _ => None,
+    };
+    let after_failed = after_result.as_ref().is_some_and(|after| !after.success);
+
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let result = RunResult {
+            commit: commit_info.id,
+            commit_summary: commit_info.summary,
+            commit_author: commit_info.author,
+            head_ref: commit_info.head.as_ref().map(HeadState::head_ref),
+            command,
+            final_command: mask(&final_command),
+            argv: argv.iter().map(|arg| mask(arg)).collect(),
+            temp_dir: temp_path.display().to_string(),
+            success: command_ok,
+            exit_code: output.status.code(),
+            stdout: if exec_options.binary_output {
+                base64::engine::general_purpose::STANDARD.encode(&output.stdout)
+            } else {
+                output.stdout_text()
+            },
+            stdout_encoding: exec_options.binary_output.then(|| "base64".to_string()),
+            stderr: stderr_text.clone(),
+            skipped_symlinks: checkout_summary.skipped_symlinks,
+            after: after_result,
+            tree_stats: checkout_summary.tree_stats,
+        };
+        if collect_json {
+            report_entry.json_result = Some(serde_json::to_value(&result)?);
+        } else if compact_json {
+            println!("{}", serde_json::to_string(&result)?);
+        } else {
+            println!("{}", serde_json::to_string_pretty(&result)?);
+        }
+    }
+
+    if let Some(first) = unexpected_stderr.first() {
+        return Err(anyhow::anyhow!(
+            "Command wrote {} unexpected line(s) to stderr (--fail-on-stderr), first: {}",
+            unexpected_stderr.len(),
+            mask(first)
+        ))
+        .phase(Phase::Execute);
+    }
+
+    if !command_ok {
+        return Err(IzError::CommandFailed {
+            code: output.status.code(),
+        })
+        .context("Failed to execute command")
+        .phase(Phase::Execute);
+    }
+
+    if hooks_fail_run && !post_ok {
+        return Err(anyhow::anyhow!("post hook failed")).phase(Phase::Execute);
+    }
+
+    if fail_on_after && after_failed {
+        let name = after.map(|after| after.name).unwrap_or_default();
+        return Err(anyhow::anyhow!("after command '{name}' failed")).phase(Phase::Execute);
+    }
+
+    if let Some(mode) = open {
+        open_temp_dir(&temp_path, mode, should_keep)?;
+    }
+
+    cleanup_guard.armed = false;
+    let cleaned = cleanup_temp_directory(&temp_path, should_keep, cleanup_strategy);
+
+    if let Some(handle) = signal_handle {
+        handle.abort();
+    }
+
+    if strict && !cleaned {
+        return Err(anyhow::anyhow!(
+            "Failed to clean up temporary directory: {}",
+            temp_path.display()
+        ))
+        .phase(Phase::Cleanup);
+    }
+
+    report_entry.success = true;
+    let mut warnings = Vec::new();
+    if after_failed {
+        warnings.push("after command failed".to_string());
+    }
+    if !cleaned {
+        warnings.push(format!(
+            "temporary directory left at {}",
+            temp_path.display()
+        ));
+    }
+    if warnings.is_empty() {
+        status!("✅ Operation completed!");
+    } else {
+        status!(
+            "⚠️  Operation completed with warnings ({})",
+            warnings.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// The `--container` image (falling back to the config's), paired with a runtime.
+fn container_target(
+    container: Option<String>,
+    runtime: Option<ContainerRuntime>,
+    config: &iz::IzConfig,
+) -> Result<Option<ContainerTarget>> {
+    container
+        .or_else(|| config.container.clone())
+        .map(|image| {
+            let runtime = match runtime {
+                Some(runtime) => runtime,
+                None => detect_container_runtime()?,
+            };
+            Ok(ContainerTarget { runtime, image })
+        })
+        .transpose()
+}
+
+/// Adds the values of params the config declares as `secrets` to the masked values.
+fn add_secret_params(
+    exec_options: &mut ExecOptions,
+    entry: &iz::CommandEntry,
+    params: &HashMap<String, String>,
+) {
+    let values = entry
+        .secret_params()
+        .iter()
+        .filter_map(|name| params.get(name).cloned());
+    exec_options.secrets.extend(values);
+}
+
+/// The command's template, from the config or printed by its generator.
+fn command_template(
+    entry: &iz::CommandEntry,
+    name: &str,
+    params: &HashMap<String, String>,
+    exec_options: &ExecOptions,
+) -> Result<String> {
+    match entry.generator() {
+        Some(generator) => run_generator(generator, name, params, exec_options)
+            .with_context(|| format!("Failed to generate command '{name}'")),
+        None => Ok(entry.template().to_string()),
+    }
+}
+
+/// The substituted command line to display and the argv to run. A shell
+/// command's argv is left empty: it is built after checkout, since the
+/// script lives in the checkout.
+fn prepare_command(
+    entry: &iz::CommandEntry,
+    name: &str,
+    shell: bool,
+    params: &HashMap<String, String>,
+    substitute: &dyn Fn(&str) -> Result<String, IzError>,
+    exec_options: &ExecOptions,
+) -> Result<(String, Vec<String>)> {
+    if let Some(argv) = entry.argv() {
+        if shell {
+            return Err(anyhow::anyhow!(
+                "Command '{name}' is given as an argv and can't run through the shell"
+            ));
+        }
+        let argv = substitute_argv(argv, substitute)?;
+        return Ok((join_argv(&argv), argv));
+    }
+
+    let template = command_template(entry, name, params, exec_options)?;
+    let final_command = substitute(&template)?;
+    if shell {
+        return Ok((final_command, Vec::new()));
+    }
+    check_program_substituted(&template, substitute)?;
+    let argv = build_argv(&final_command)?;
+    Ok((final_command, argv))
+}
+
+struct BenchOptions {
+    runs: usize,
+    fresh_checkout: bool,
+}
+
+/// Times `runs` executions of a command against one commit. Hooks are not run
+/// and command output is not shown, so only the command itself is measured.
+async fn bench_command(
+    commit_id: String,
+    command: String,
+    options: RunOptions,
+    bench: BenchOptions,
+) -> Result<()> {
+    let config = load_config(&options.config_path, &options.overrides)?;
+    let mut exec_options = options.exec;
+    exec_options.allowed_commands = config.allowed_commands.clone();
+    exec_options.container = container_target(options.container, options.runtime, &config)?;
+    exec_options.quiet = true;
+    exec_options.stream = false;
+    exec_options.binary_output = false;
+    exec_options.pty = false;
+
+    let resolved = resolve_command(&config.commands, &command)?;
+    let command_entry = resolved.entry;
+    apply_command_timeout(&mut exec_options, command_entry)?;
+    exec_options.workdir = command_entry.workdir().map(str::to_string);
+
+    let mut params = resolved.params;
+    params.extend(options.param);
+    let json_params: HashMap<String, serde_json::Value> = options.param_json.into_iter().collect();
+    let mut provided = params.clone();
+    for key in json_params.keys() {
+        provided.entry(key.clone()).or_default();
+    }
+    check_declared_params(command_entry.declared_params(), &provided)?;
+    if let Some(types) = command_entry.param_types() {
+        check_param_types(types, &params)?;
+    }
+    add_secret_params(&mut exec_options, command_entry, &params);
+
+    let substitute =
+        |template: &str| substitute_variables_with_json(template, &params, &json_params);
+    let shell = options.shell || command_entry.shell();
+    let (command_line, mut argv) = prepare_command(
+        command_entry,
+        resolved.name,
+        shell,
+        &params,
+        &substitute,
+        &exec_options,
+    )?;
+    let substituted_args = argv.len();
+    argv.extend(options.extra_args.iter().cloned());
+    let final_command = append_args(&command_line, &options.extra_args);
+    let success_codes = if options.success_codes.is_empty() {
+        config.success_codes.clone()
+    } else {
+        Some(options.success_codes)
+    };
+
+    let commit_info = resolve_commit_info(&commit_id)?;
+    status!(
+        "⏱️  Benchmarking '{command}' on {} ({} runs)",
+        commit_info.short_id,
+        bench.runs
+    );
+    let final_command_display = mask_secrets(&final_command, &exec_options.secrets);
+    status!("📝 Command: {final_command_display}");
+
+    let base_temp_dir = determine_temp_dir(&options.temp_dir, Some(command_entry), &config)?;
+    remove_expired_runs(&base_temp_dir);
+    if options.auto_clean {
+        auto_clean_base_dir(&base_temp_dir, &config);
+    }
+    enforce_max_temp_bytes(&base_temp_dir, &config, &commit_info.id)?;
+    let should_keep = options.keep.unwrap_or(config.keep.unwrap_or(false));
+    let cleanup_strategy = config.cleanup_strategy.unwrap_or_default();
+    let checkout_options = CheckoutOptions {
+        precreate_dirs: config.precreate_dirs.unwrap_or(true),
+        symlink_mode: options.symlink_mode,
+        with_git: options.with_git,
+        strategy: options.checkout_strategy,
+        line_endings: options.line_endings,
+        readonly: options.readonly,
+        stats: false,
+        changed_since: options.changed_since.clone(),
+        trace: options.trace_fs.clone(),
+    };
+    let checkout = || -> Result<CleanupGuard> {
+        let meta = RunMeta {
+            readonly: options.readonly,
+            ..RunMeta::new(&commit_info.id, Some(&command)).expiring_after(options.keep_for)
+        };
+        let temp_path = create_unique_temp_dir(&base_temp_dir, None, false, &meta)?;
+        if !should_keep {
+            register_cleanup(&temp_path, cleanup_strategy);
+        }
+        let guard = CleanupGuard {
+            temp_path,
+            strategy: cleanup_strategy,
+            armed: !should_keep,
+        };
+        checkout_commit_to_temp(&commit_id, &guard.temp_path, &checkout_options).with_context(
+            || IzError::CheckoutFailed {
+                commit: commit_id.clone(),
+            },
+        )?;
+        Ok(guard)
+    };
+
+    let signal_handle = (!should_keep).then(|| {
+        tokio::spawn(async {
+            let _ = setup_signal_handler().await;
+        })
+    });
+
+    let runner = LocalRunner {
+        options: &exec_options,
+    };
+    let mut cleanup_guard = checkout()?;
+    let mut durations_ms = Vec::with_capacity(bench.runs);
+    for run in 1..=bench.runs {
+        if bench.fresh_checkout && run > 1 {
+            cleanup_guard.armed = false;
+            cleanup_temp_directory(&cleanup_guard.temp_path, should_keep, cleanup_strategy);
+            cleanup_guard = checkout()?;
+        }
+        let temp_path = &cleanup_guard.temp_path;
+
+        // A fresh checkout moves the `#{file:...}` paths along with it
+        let checkout_root = temp_path.canonicalize()?;
+        let (argv, script_path) = if shell {
+            let command_line = substitute_file_paths(&command_line, &checkout_root)?;
+            let final_command = append_args(&command_line, &options.extra_args);
+            shell_invocation(&final_command, &checkout_root)?
+        } else {
+            let mut argv = argv.clone();
+            for arg in &mut argv[..substituted_args] {
+                *arg = substitute_file_paths(arg, &checkout_root)?;
+            }
+            (argv, None)
+        };
+        let started = std::time::Instant::now();
+        let output = runner.run(&argv, temp_path, &exec_options.env);
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        if let Some(script_path) = &script_path {
+            let _ = fs::remove_file(script_path);
+        }
+
+        let output = output.context("Failed to execute command")?;
+        if !is_success_code(output.status.code(), success_codes.as_deref()) {
+            eprintln!("{}", output.stderr_text().trim_end());
+            return Err(anyhow::anyhow!(
+                "Run {run} of {} failed with status: {}",
+                bench.runs,
+                output.status
+            ));
+        }
+
+        status!("   run {run}/{}: {elapsed_ms:.1} ms", bench.runs);
+        durations_ms.push(elapsed_ms);
+    }
+
+    cleanup_guard.armed = false;
+    cleanup_temp_directory(&cleanup_guard.temp_path, should_keep, cleanup_strategy);
+    if let Some(handle) = signal_handle {
+        handle.abort();
+    }
+
+    let stats = BenchStats::from_durations(&durations_ms).expect("at least one run");
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let result = serde_json::json!({
+            "commit": commit_info.id,
+            "command": command,
+            "final_command": final_command_display,
+            "durations_ms": durations_ms,
+            "stats": stats,
+        });
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    } else {
+        println!();
+        println!(
+            "{:>12} {:>12} {:>12} {:>12}",
+            "min", "mean", "max", "stddev"
+        );
+        println!(
+            "{:>9.1} ms {:>9.1} ms {:>9.1} ms {:>9.1} ms",
+            stats.min_ms, stats.mean_ms, stats.max_ms, stats.stddev_ms
+        );
+    }
+
+    Ok(())
+}
+
+fn write_report(path: &std::path::Path, format: ReportFormat, report: &RunReport) -> Result<()> {
+    let content = match format {
+        ReportFormat::Json => serde_json::to_string_pretty(report)?,
+        ReportFormat::Md => report.to_markdown(),
+    };
+
+    fs::write(path, content)
+        .with_context(|| format!("Failed to write report: {}", path.display()))?;
+    status!("📊 Report written to {}", path.display());
+    Ok(())
+}
+
+fn select_command(config_path: &std::path::Path) -> Result<String> {
+    let config = read_config(config_path).context("Failed to read izconfig.json")?;
+    let mut names: Vec<&String> = config.commands.keys().collect();
+    names.sort();
+
+    if names.is_empty() {
+        return Err(anyhow::anyhow!("No commands defined in izconfig.json"));
+    }
+
+    let items: Vec<String> = names
+        .iter()
+        .map(|name| format!("{name}  ({})", describe_command(&config.commands[*name])))
+        .collect();
+
+    let selection = dialoguer::Select::new()
+        .with_prompt(decorate("❓ Select a command to run"))
+        .items(&items)
+        .default(0)
+        .interact()
+        .context("Failed to read command selection")?;
+
+    Ok(names[selection].clone())
+}
+
+/// What a command runs, as shown by the command picker and `iz list`.
+fn describe_command(entry: &iz::CommandEntry) -> String {
+    match (entry.alias(), entry.generator()) {
+        (Some(target), _) => format!("alias for {target}"),
+        (None, Some(generator)) => format!("generated by {generator}"),
+        (None, None) => match entry.argv() {
+            Some(argv) => join_argv(argv),
+            None => entry.template().to_string(),
+        },
+    }
+}
+
+/// How `list` lays out the command names.
+#[derive(Clone, Copy, PartialEq)]
+enum ListLayout {
+    Plain,
+    /// Under a heading per group (`--group`)
+    Grouped,
+    /// Bare names ended by NUL bytes (`--null`)
+    NullSeparated,
+}
+
+/// Prints the config's commands, optionally only those of one `group:` or
+/// under a heading per group.
+fn list_command(
+    config_path: &std::path::Path,
+    group: Option<&str>,
+    layout: ListLayout,
+) -> Result<()> {
+    let config = read_config(config_path).context("Failed to read izconfig.json")?;
+    let mut names: Vec<&str> = config
+        .commands
+        .keys()
+        .map(String::as_str)
+        .filter(|name| group.is_none() || command_group(name) == group)
+        .collect();
+    names.sort();
+
+    if let Some(group) = group {
+        if names.is_empty() {
+            let groups = group_command_names(config.commands.keys().map(String::as_str));
+            let known: Vec<&str> = groups.iter().filter_map(|(group, _)| *group).collect();
+            return Err(anyhow::anyhow!(
+                "No commands in group '{group}' (groups: {})",
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            ));
+        }
+    }
+
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let entries: Vec<serde_json::Value> = names
+            .iter()
+            .map(|name| {
+                serde_json::json!({
+                    "name": name,
+                    "group": command_group(name),
+                    "description": describe_command(&config.commands[*name]),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    let print = |name: &str, indent: &str| {
+        println!(
+            "{indent}{name}  ({})",
+            describe_command(&config.commands[name])
+        );
+    };
+    match layout {
+        ListLayout::Plain => {
+            for name in names {
+                print(name, "");
+            }
+        }
+        ListLayout::Grouped => {
+            for (group, names) in group_command_names(names) {
+                println!("{}:", group.unwrap_or("(ungrouped)"));
+                for name in names {
+                    print(name, "  ");
+                }
+            }
+        }
+        ListLayout::NullSeparated => {
+            let mut out = std::io::stdout().lock();
+            for name in names {
+                out.write_all(name.as_bytes())?;
+                out.write_all(b"\0")?;
+            }
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// The format `config_path` is parsed as: `--config-format`, or a guess from its extension.
+fn config_format(config_path: &std::path::Path) -> ConfigFormat {
+    CONFIG_FORMAT
+        .get()
+        .copied()
+        .unwrap_or_else(|| ConfigFormat::from_path(config_path))
+}
+
+/// The config read from stdin when `config_path` is `-`.
+fn stdin_config(config_path: &std::path::Path) -> Option<&'static str> {
+    STDIN_CONFIG
+        .get()
+        .filter(|_| config_path.as_os_str() == "-")
+        .map(String::as_str)
+}
+
+/// Reads the config, from stdin for `--config -`, merged over the global config.
+fn read_config(config_path: &std::path::Path) -> Result<iz::IzConfig, IzError> {
+    let format = config_format(config_path);
+    let local = match stdin_config(config_path) {
+        Some(content) => read_config_from_reader(content.as_bytes(), format, config_path)?,
+        None => read_config_from_path_as(config_path, format)?,
+    };
+    Ok(read_global_config(global_config_path().as_deref())?.merge(local))
+}
+
+/// Reads the config and applies the `--set` overrides to its commands.
+fn load_config(
+    config_path: &std::path::Path,
+    overrides: &[CommandOverride],
+) -> Result<iz::IzConfig> {
+    let mut config = read_config(config_path).context("Failed to read izconfig.json")?;
+    apply_command_overrides(&mut config.commands, overrides)?;
+    Ok(config)
+}
+
+/// Uses the command's `timeout` when `--timeout` wasn't given.
+fn apply_command_timeout(exec_options: &mut ExecOptions, entry: &iz::CommandEntry) -> Result<()> {
+    if exec_options.timeout.limit.is_some() {
+        return Ok(());
+    }
+    if let Some(timeout) = entry.timeout() {
+        let limit = parse_duration(timeout)
+            .map_err(|e| anyhow::anyhow!("Invalid timeout '{timeout}' in command: {e}"))?;
+        exec_options.timeout.limit = Some(limit);
+    }
+    Ok(())
+}
+
+async fn checkout_only_command(
+    base_temp_dir: &std::path::Path,
+    commit_id: String,
+    run_id: Option<&str>,
+    force: bool,
+    keep_for: Option<std::time::Duration>,
+    checkout_options: CheckoutOptions,
+    open: Option<OpenMode>,
+) -> Result<()> {
+    let commit_info = resolve_commit_info(&commit_id)?;
+    let meta = RunMeta {
+        readonly: checkout_options.readonly,
+        ..RunMeta::new(&commit_info.id, None).expiring_after(keep_for)
+    };
+    let temp_path = create_unique_temp_dir(base_temp_dir, run_id, force, &meta)?;
+
+    let checkout_summary = checkout_commit_to_temp(&commit_id, &temp_path, &checkout_options)
+        .with_context(|| IzError::CheckoutFailed {
+            commit: commit_id.clone(),
+        })?;
+    report_skipped_symlinks(&checkout_summary);
+    report_tree_stats(&checkout_summary);
+    link_latest(&temp_path);
+    mark_run_finished(&temp_path);
+
+    say!("📦 Checked out {commit_id} to:");
+    println!("{}", temp_path.display());
+
+    if let Some(mode) = open {
+        open_temp_dir(&temp_path, mode, true)?;
+    }
+    Ok(())
+}
+
+/// Points the `latest` link in the run's base temp dir at `temp_path`; a
+/// failure only produces a warning.
+fn link_latest(temp_path: &std::path::Path) {
+    let Some(base_temp_dir) = temp_path.parent() else {
+        return;
+    };
+    if let Err(e) = update_latest_link(base_temp_dir, temp_path) {
+        say_err!(
+            "⚠️  Could not update {}: {e}",
+            base_temp_dir.join(LATEST_LINK).display()
+        );
+    }
+}
+
+/// Re-points or removes a `latest` link whose run was removed from `base_temp_dir`.
+fn repair_latest(base_temp_dir: &std::path::Path) {
+    if let Err(e) = repair_latest_link(base_temp_dir) {
+        say_err!(
+            "⚠️  Could not repair {}: {e}",
+            base_temp_dir.join(LATEST_LINK).display()
+        );
+    }
+}
+
+/// Lets the user look around the checkout before the cleanup policy is applied.
+fn open_temp_dir(temp_path: &std::path::Path, mode: OpenMode, should_keep: bool) -> Result<()> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        say_err!("⚠️  --open ignored: not running in an interactive terminal");
+        return Ok(());
+    }
+
+    match mode {
+        OpenMode::Shell => {
+            let shell = default_shell();
+            status!(
+                "🐚 Opening {shell} in {} (exit to continue)",
+                temp_path.display()
+            );
+            Command::new(&shell)
+                .current_dir(temp_path)
+                .status()
+                .with_context(|| format!("Failed to start shell: {shell}"))?;
+        }
+        OpenMode::Files => {
+            let opener = if cfg!(target_os = "macos") {
+                "open"
+            } else if cfg!(windows) {
+                "explorer"
+            } else {
+                "xdg-open"
+            };
+            Command::new(opener)
+                .arg(temp_path)
+                .spawn()
+                .with_context(|| format!("Failed to open file manager with {opener}"))?;
+
+            // The file manager doesn't block, so wait before the directory is removed
+            if !should_keep {
+                print!(
+                    "{}",
+                    decorate(&format!(
+                        "⏎ Press Enter to clean up {}...",
+                        temp_path.display()
+                    ))
+                );
+                std::io::stdout().flush()?;
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn default_shell() -> String {
+    if cfg!(windows) {
+        std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string())
+    } else {
+        std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+    }
+}
+
+struct CleanOptions {
+    temp_dirs: Vec<String>,
+    all_known: bool,
+    force: bool,
+    dry_run: bool,
+    list: bool,
+    ignore_errors: bool,
+    match_command: Option<String>,
+    match_commit: Option<String>,
+}
+
+async fn clean_command(config_path: &std::path::Path, options: CleanOptions) -> Result<()> {
+    let CleanOptions {
+        temp_dirs,
+        all_known,
+        force,
+        dry_run,
+        list,
+        ignore_errors,
+        match_command,
+        match_commit,
+    } = options;
+
+    if !list {
+        say!("🧹 Starting cleanup...");
+    }
+
+    let config = read_config(config_path).context("Failed to read izconfig.json")?;
+    let base_temp_dirs = clean_base_dirs(temp_dirs, all_known, &config)?;
+    let filtered = match_command.is_some() || match_commit.is_some();
+
+    let mut items_to_clean = Vec::new();
+    for base_temp_dir in &base_temp_dirs {
+        let mut items = find_iz_dirs(base_temp_dir)?;
+        // Directories without metadata never match a filter
+        if filtered {
+            items.retain(|item| {
+                read_run_meta(item).is_some_and(|meta| {
+                    meta.matches(match_command.as_deref(), match_commit.as_deref())
+                })
+            });
+        }
+        if base_temp_dirs.len() > 1 && !items.is_empty() {
+            say!(
+                "📁 {}: {} temporary directories",
+                base_temp_dir.display(),
+                items.len()
+            );
+        }
+        items_to_clean.extend(items.into_iter().map(|item| (base_temp_dir, item)));
+    }
+
+    if items_to_clean.is_empty() {
+        return Ok(());
+    }
+
+    if list {
+        print_run_table(items_to_clean.iter().map(|(_, item)| item));
+        return Ok(());
+    }
+
+    say!("📋 Found {} temporary directories:", items_to_clean.len());
+    for (_, item) in &items_to_clean {
+        if dry_run {
+            say!("  🔍 Would remove: {}", item.display());
+        } else {
+            say!("  • {}", item.display());
+        }
+    }
+
+    if dry_run {
+        say!(
+            "🔍 Dry run: {} directories would be removed",
+            items_to_clean.len()
+        );
+        return Ok(());
+    }
+
+    if !force && !confirm_clean(config.clean_confirm_default.unwrap_or(false))? {
+        say!("🚫 Cleanup cancelled");
+        return Ok(());
+    }
+
+    let total = items_to_clean.len();
+    let mut cleaned_count = 0;
+    let mut failed = Vec::new();
+    let mut cleaned_per_dir: Vec<(&PathBuf, usize)> = Vec::new();
+
+    for (base_temp_dir, item) in items_to_clean {
+        let removed = restore_writable(&item)
+            .and_then(|()| fs::remove_dir_all(&item).map_err(|e| remove_dir_error(e, &item)));
+        match removed {
+            Ok(()) => {
+                cleaned_count += 1;
+                say!("✅ Cleaned: {}", item.display());
+                match cleaned_per_dir
+                    .iter_mut()
+                    .find(|(dir, _)| *dir == base_temp_dir)
+                {
+                    Some((_, count)) => *count += 1,
+                    None => cleaned_per_dir.push((base_temp_dir, 1)),
+                }
+            }
+            Err(e) => {
+                say_err!("❌ Failed to clean {}: {e:#}", item.display());
+                failed.push(item);
+            }
+        }
+    }
+
+    for (base_temp_dir, _) in &cleaned_per_dir {
+        repair_latest(base_temp_dir);
+    }
+
+    if base_temp_dirs.len() > 1 {
+        for (base_temp_dir, count) in &cleaned_per_dir {
+            say!("  📁 {}: cleaned {count}", base_temp_dir.display());
+        }
+    }
+
+    if failed.is_empty() {
+        say!("🎉 Successfully cleaned {cleaned_count} directories!");
+        return Ok(());
+    }
+
+    say!(
+        "⚠️  Cleaned {cleaned_count} directories, {} failed",
+        failed.len()
+    );
+    if ignore_errors {
+        return Ok(());
+    }
+
+    let failed: Vec<String> = failed
+        .iter()
+        .map(|item| item.display().to_string())
+        .collect();
+    Err(anyhow::anyhow!(
+        "Failed to clean {} of {total} directories: {}",
+        failed.len(),
+        failed.join(", ")
+    ))
+}
+
+/// Prints the commit, command and age recorded in each directory's metadata.
+fn print_run_table<'a>(items: impl Iterator<Item = &'a PathBuf>) {
+    let now = RunMeta::new("", None).started_at;
+    let rows: Vec<[String; 4]> = items
+        .map(|item| match read_run_meta(item) {
+            Some(meta) => [
+                meta.commit.chars().take(7).collect(),
+                meta.command.unwrap_or_else(|| "(checkout)".to_string()),
+                format_age(now.saturating_sub(meta.started_at)),
+                item.display().to_string(),
+            ],
+            None => [
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+                format!("{} (no metadata)", item.display()),
+            ],
+        })
+        .collect();
+
+    let headers = ["COMMIT", "COMMAND", "AGE", "PATH"];
+    let widths: Vec<usize> = (0..3)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain([headers[column].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    for row in std::iter::once(headers.map(str::to_string)).chain(rows) {
+        println!(
+            "  {:<w0$}  {:<w1$}  {:>w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+}
+
+/// Asks before `clean` removes anything. An empty answer takes the config's
+/// `clean_confirm_default`; end of input (no terminal, `< /dev/null`) is a no.
+fn confirm_clean(default_yes: bool) -> Result<bool> {
+    let choices = if default_yes { "[Y/n]" } else { "[y/N]" };
+    print!(
+        "{}",
+        decorate(&format!(
+            "❓ Do you want to clean these directories? {choices}: "
+        ))
+    );
+    std::io::Write::flush(&mut std::io::stdout())?;
+
+    let mut input = String::new();
+    if std::io::stdin().read_line(&mut input)? == 0 {
+        println!();
+        return Ok(false);
+    }
+    let input = input.trim().to_lowercase();
+
+    Ok(match input.as_str() {
+        "" => default_yes,
+        "y" | "yes" => true,
+        _ => false,
+    })
+}
+
+/// Resolves the base directories `clean` should scan, without duplicates.
+fn clean_base_dirs(
+    temp_dirs: Vec<String>,
+    all_known: bool,
+    config: &iz::IzConfig,
+) -> Result<Vec<PathBuf>> {
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    let mut candidates: Vec<PathBuf> = temp_dirs.into_iter().map(PathBuf::from).collect();
+
+    if all_known {
+        candidates.extend(config.temp_dir.as_ref().map(PathBuf::from));
+        let mut command_temp_dirs: Vec<&str> = config
+            .commands
+            .values()
+            .filter_map(|command| command.temp_dir())
+            .collect();
+        command_temp_dirs.sort();
+        candidates.extend(command_temp_dirs.into_iter().map(PathBuf::from));
+        candidates.extend(std::env::var("IZTEMP").ok().map(PathBuf::from));
+        candidates.push(current_dir.join(".iztemp"));
+        candidates.push(system_temp_base());
+        candidates.extend(ram_temp_base());
+    } else if candidates.is_empty() {
+        candidates.push(determine_temp_dir(&None, None, config)?);
+    }
+
+    let mut base_dirs: Vec<PathBuf> = Vec::new();
+    let mut seen = Vec::new();
+    for candidate in candidates {
+        let key = candidate
+            .canonicalize()
+            .unwrap_or_else(|_| current_dir.join(&candidate));
+        if !seen.contains(&key) {
+            seen.push(key);
+            base_dirs.push(candidate);
+        }
+    }
+
+    Ok(base_dirs)
+}
+
+/// Applies the config's `auto_clean`, if any. Failures only produce a warning,
+/// since the run itself doesn't depend on them.
+fn auto_clean_base_dir(base_temp_dir: &std::path::Path, config: &iz::IzConfig) {
+    let Some(settings) = &config.auto_clean else {
+        return;
+    };
+    match auto_clean(base_temp_dir, settings) {
+        Ok(0) => {}
+        Ok(removed) => status!(
+            "🧹 Auto-clean removed {removed} temporary directories older than {}",
+            settings.older_than
+        ),
+        Err(e) => say_err!("⚠️  Auto-clean failed: {e:#}"),
+    }
+}
+
+/// Removes `iz-` directories in `base_temp_dir` older than the threshold, at
+/// most once per [`AUTO_CLEAN_INTERVAL`]. Returns how many were removed.
+fn auto_clean(base_temp_dir: &std::path::Path, settings: &AutoClean) -> Result<usize> {
+    let older_than = parse_duration(&settings.older_than)
+        .map_err(|e| anyhow::anyhow!("Invalid auto_clean.older_than: {e}"))?;
+    if !base_temp_dir.is_dir() {
+        return Ok(0);
+    }
+
+    let stamp = base_temp_dir.join(AUTO_CLEAN_STAMP_FILE);
+    let now = std::time::SystemTime::now();
+    let recently_cleaned = fs::metadata(&stamp)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|cleaned| {
+            now.duration_since(cleaned)
+                .is_ok_and(|since| since < AUTO_CLEAN_INTERVAL)
+        });
+    if recently_cleaned {
+        return Ok(0);
+    }
+    fs::write(&stamp, "").with_context(|| format!("Failed to write {}", stamp.display()))?;
+
+    let mut removed = 0;
+    for item in list_iz_dirs(base_temp_dir)? {
+        let stale = run_dir_age(&item, now).is_some_and(|age| age >= older_than)
+            || read_run_meta(&item).is_some_and(|meta| meta.is_expired(unix_secs(now)));
+        if !stale {
+            continue;
+        }
+        let result = restore_writable(&item)
+            .and_then(|()| fs::remove_dir_all(&item).map_err(|e| remove_dir_error(e, &item)));
+        match result {
+            Ok(()) => removed += 1,
+            Err(e) => say_err!("⚠️  Auto-clean could not remove {}: {e:#}", item.display()),
+        }
+    }
+    if removed > 0 {
+        repair_latest(base_temp_dir);
+    }
+    Ok(removed)
+}
+
+/// Removes kept directories in `base_temp_dir` whose `--keep=<duration>` has
+/// elapsed. Runs before every new checkout; failures only warn.
+fn remove_expired_runs(base_temp_dir: &std::path::Path) {
+    if !base_temp_dir.is_dir() {
+        return;
+    }
+    let items = match list_iz_dirs(base_temp_dir) {
+        Ok(items) => items,
+        Err(e) => {
+            say_err!("⚠️  Could not look for expired temporary directories: {e:#}");
+            return;
+        }
+    };
+    let now = unix_secs(std::time::SystemTime::now());
+    let mut removed = 0;
+    for item in items {
+        if !read_run_meta(&item).is_some_and(|meta| meta.is_expired(now)) || run_in_progress(&item)
+        {
+            continue;
+        }
+        let result = restore_writable(&item)
+            .and_then(|()| fs::remove_dir_all(&item).map_err(|e| remove_dir_error(e, &item)));
+        match result {
+            Ok(()) => removed += 1,
+            Err(e) => say_err!("⚠️  Could not remove expired {}: {e:#}", item.display()),
+        }
+    }
+    if removed > 0 {
+        status!("🧹 Removed {removed} kept temporary directories past their --keep duration");
+        repair_latest(base_temp_dir);
+    }
+}
+
+fn unix_secs(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Applies the config's `max_temp_bytes` before a run creates its directory
+/// in `base_temp_dir`: the oldest runs are removed until the remaining ones
+/// and the new checkout fit under the limit.
+fn enforce_max_temp_bytes(
+    base_temp_dir: &std::path::Path,
+    config: &iz::IzConfig,
+    commit_id: &str,
+) -> Result<()> {
+    let Some(limit) = config.max_temp_bytes else {
+        return Ok(());
+    };
+    let needed = checkout_size(commit_id)?;
+    if needed > limit {
+        return Err(anyhow::anyhow!(
+            "Checking out {commit_id} takes about {}, more than max_temp_bytes allows ({})",
+            format_bytes(needed),
+            format_bytes(limit)
+        ));
+    }
+    if !base_temp_dir.is_dir() {
+        return Ok(());
+    }
+
+    let now = std::time::SystemTime::now();
+    let mut runs = Vec::new();
+    for dir in list_iz_dirs(base_temp_dir)? {
+        if run_in_progress(&dir) {
+            continue;
+        }
+        let size =
+            dir_size(&dir).with_context(|| format!("Failed to measure {}", dir.display()))?;
+        runs.push((run_dir_age(&dir, now), size, dir));
+    }
+    // Oldest first; a directory whose age is unknown goes before all others
+    runs.sort_by_key(|(age, _, _)| std::cmp::Reverse(age.unwrap_or(std::time::Duration::MAX)));
+
+    let mut used: u64 = runs.iter().map(|(_, size, _)| size).sum();
+    let mut pruned = 0;
+    for (_, size, dir) in runs {
+        if used + needed <= limit {
+            break;
+        }
+        restore_writable(&dir)
+            .and_then(|()| fs::remove_dir_all(&dir).map_err(|e| remove_dir_error(e, &dir)))
+            .with_context(|| format!("Failed to prune {} for max_temp_bytes", dir.display()))?;
+        status!(
+            "🧹 Pruned {} ({}) to stay under max_temp_bytes",
+            dir.display(),
+            format_bytes(size)
+        );
+        used -= size;
+        pruned += 1;
+    }
+    if pruned > 0 {
+        repair_latest(base_temp_dir);
+    }
+    Ok(())
+}
+
+/// Whether a run is still using `dir`: one of ours that will clean it up, or
+/// one whose recorded process is still alive.
+fn run_in_progress(dir: &std::path::Path) -> bool {
+    let registered = CLEANUP_STATE
+        .lock()
+        .is_ok_and(|cleanup_state| cleanup_state.iter().any(|(path, _)| path == dir));
+    registered
+        || read_run_meta(dir)
+            .and_then(|meta| meta.pid)
+            .is_some_and(process_alive)
+}
+
+/// Without a way to check (outside Unix), a recorded process counts as alive.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// Clears the process id of a kept run once it is done with the directory,
+/// so `max_temp_bytes` and expiry may remove it later.
+fn mark_run_finished(temp_path: &std::path::Path) {
+    let Some(meta) = read_run_meta(temp_path) else {
+        return;
+    };
+    if let Err(e) = write_run_meta(temp_path, &RunMeta { pid: None, ..meta }) {
+        say_err!("⚠️  Could not update {}: {e:#}", temp_path.display());
+    }
+}
+
+/// Roughly what checking out the commit writes: the size of every blob in
+/// its tree, before `.izignore` or `--changed-since` leave any out.
+fn checkout_size(commit_id: &str) -> Result<u64> {
+    let repo = open_repository()?;
+    let tree = find_commit(&repo, commit_id)?
+        .tree()
+        .context("Failed to get commit tree")?;
+    let odb = repo.odb()?;
+
+    let mut total = 0;
+    tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+        if entry.kind() == Some(git2::ObjectType::Blob) {
+            if let Ok((size, _)) = odb.read_header(entry.id()) {
+                total += size as u64;
+            }
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(total)
+}
+
+/// Lists the `iz-` directories inside one base temp directory, saying so
+/// when the directory is missing or has none.
+fn find_iz_dirs(base_temp_dir: &std::path::Path) -> Result<Vec<PathBuf>> {
+    if !base_temp_dir.exists() {
+        say!(
+            "📁 Temporary directory does not exist: {}",
+            base_temp_dir.display()
+        );
+        return Ok(Vec::new());
+    }
+
+    let items = list_iz_dirs(base_temp_dir)?;
+    if items.is_empty() {
+        say!(
+            "✨ No temporary directories to clean in: {}",
+            base_temp_dir.display()
+        );
+    }
+
+    Ok(items)
+}
+
+/// Lists the `iz-` directories inside one base temp directory, along with
+/// the ones set aside in its trash directory.
+fn list_iz_dirs(base_temp_dir: &std::path::Path) -> Result<Vec<PathBuf>> {
+    // List contents of temp directory
+    let entries = fs::read_dir(base_temp_dir)
+        .with_context(|| format!("Failed to read temp directory: {}", base_temp_dir.display()))?;
+
+    let mut items = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir()
+            && path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .starts_with("iz-")
+        {
+            items.push(path);
+        }
+    }
+
+    // Directories set aside by the `rename` cleanup strategy
+    let trash_dir = base_temp_dir.join(TRASH_DIR_NAME);
+    if trash_dir.is_dir() {
+        for entry in fs::read_dir(&trash_dir)
+            .with_context(|| format!("Failed to read trash directory: {}", trash_dir.display()))?
+        {
+            items.push(entry?.path());
+        }
+    }
+
+    Ok(items)
+}
+
+fn version_command(verbose: bool) -> Result<()> {
+    let version = env!("CARGO_PKG_VERSION");
+    let (major, minor, rev) = git2::Version::get().libgit2_version();
+    let libgit2 = format!("{major}.{minor}.{rev}");
+    let target = env!("IZ_BUILD_TARGET");
+    let rustc = env!("IZ_RUSTC_VERSION");
+
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let info = serde_json::json!({
+            "version": version,
+            "libgit2": libgit2,
+            "target": target,
+            "rustc": rustc,
+        });
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else if verbose {
+        println!("iz {version}");
+        println!("libgit2: {libgit2}");
+        println!("target: {target}");
+        println!("rustc: {rustc}");
+    } else {
+        println!("iz {version}");
+    }
+
+    Ok(())
+}
+
+/// Folds a `--spec` into the parsed flags, as if its values had been passed as flags.
+fn apply_spec(cli: &mut Cli, spec: RunSpec) -> Result<()> {
+    cli.param.extend(spec.plain_params());
+    cli.param_json.extend(spec.json_params());
+    let mut env: Vec<(String, String)> = spec.env.into_iter().collect();
+    env.sort();
+    cli.env.extend(env);
+
+    if cli.stdin_commits || cli.commits_file.is_some() {
+        if spec.commit.is_some() {
+            return Err(anyhow::anyhow!(
+                "A spec with a commit can't be combined with --stdin-commits or --commits-file"
+            ));
+        }
+        if let Some(command) = spec.command {
+            cli.commit_id_or_command = Some(command);
+        }
+    } else {
+        if let Some(commit) = spec.commit {
+            // A lone positional argument then names the command
+            if cli.command.is_none() {
+                cli.command = cli.commit_id_or_command.take();
+            }
+            cli.commit_id_or_command = Some(commit);
+        }
+        if let Some(command) = spec.command {
+            cli.command = Some(command);
+        }
+    }
+
+    if let Some(keep) = spec.keep {
+        cli.keep = Some(KeepSetting::Flag(keep));
+    }
+    if let Some(temp_dir) = spec.temp_dir {
+        cli.temp_dir = vec![temp_dir];
+    }
+    if let Some(shell) = spec.shell {
+        cli.shell = shell;
+    }
+    if let Some(timeout) = spec.timeout {
+        let timeout = parse_duration(&timeout)
+            .map_err(|e| anyhow::anyhow!("Invalid timeout '{timeout}' in spec: {e}"))?;
+        cli.timeout = Some(timeout);
+    }
+    Ok(())
+}
+
+fn list_params_command(config_path: &std::path::Path, command: &str) -> Result<()> {
+    let config = read_config(config_path).context("Failed to read izconfig.json")?;
+    let resolved = resolve_command(&config.commands, command)?;
+    let params = command_params(&resolved);
+
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        let info = serde_json::json!({
+            "command": command,
+            "params": params,
+        });
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    if params.is_empty() {
+        println!("Command '{command}' takes no parameters");
+    } else {
+        println!("Parameters for '{command}':");
+        for param in &params {
+            match &param.default {
+                Some(default) => println!("  {} (default: {default})", param.name),
+                None => println!("  {}", param.name),
+            }
+        }
+    }
+    if resolved.entry.generator().is_some() {
+        println!("Note: the template is generated at run time, so only declared params are listed");
+    }
+
+    Ok(())
+}
+
+fn print_config_command(
+    config_path: &std::path::Path,
+    set: &[CommandOverride],
+    overrides: iz::IzConfig,
+) -> Result<()> {
+    let config = load_config(config_path, set)?.merge(overrides);
+    // Going through a Value sorts the commands, which a HashMap wouldn't
+    let value = serde_json::to_value(&config)?;
+    println!("{}", serde_json::to_string_pretty(&value)?);
+    Ok(())
+}
+
+fn validate_command(path: &std::path::Path) -> Result<()> {
+    let warnings = match stdin_config(path) {
+        Some(content) => validate_config_content(content, config_format(path), path)?,
+        None => validate_config_as(path, config_format(path))?,
+    };
+
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        println!("{}", serde_json::to_string_pretty(&warnings)?);
+    } else {
+        for warning in &warnings {
+            let icon = match warning.severity {
+                Severity::Error => "❌",
+                Severity::Warning => "⚠️ ",
+            };
+            match &warning.command {
+                Some(command) => say!("{icon} {command}: {}", warning.message),
+                None => say!("{icon} {}", warning.message),
+            }
+        }
+    }
+
+    let errors = warnings
+        .iter()
+        .filter(|warning| warning.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(anyhow::anyhow!("{} has {errors} error(s)", path.display()));
+    }
+
+    status!(
+        "✅ {} is valid ({} warning(s))",
+        path.display(),
+        warnings.len()
+    );
+    Ok(())
+}
+
+fn open_repository() -> Result<Repository> {
+    if let Some(dir) = REPO_DIR.get() {
+        return Repository::open(dir)
+            .with_context(|| format!("Failed to open repository at {}", dir.display()));
+    }
+    Repository::open(std::env::current_dir()?)
+        .context("Git repository not found - this directory is not a git repository")
+}
+
+/// Finds a submodule of the current repository by name or path and returns the
+/// directory of its checked-out repository.
+fn submodule_dir(name: &str) -> Result<PathBuf> {
+    let repo = open_repository()?;
+    let submodules = repo.submodules().context("Failed to read submodules")?;
+    let submodule = submodules
+        .iter()
+        .find(|submodule| {
+            submodule.name() == Some(name) || submodule.path() == std::path::Path::new(name)
+        })
+        .ok_or_else(|| {
+            let known: Vec<&str> = submodules.iter().filter_map(|s| s.name()).collect();
+            if known.is_empty() {
+                anyhow::anyhow!("Submodule '{name}' not found: the repository has no submodules")
+            } else {
+                anyhow::anyhow!(
+                    "Submodule '{name}' not found (available: {})",
+                    known.join(", ")
+                )
+            }
+        })?;
+
+    let workdir = repo
+        .workdir()
+        .context("Submodules need a repository with a working directory")?;
+    let dir = workdir.join(submodule.path());
+    if Repository::open(&dir).is_err() {
+        return Err(anyhow::anyhow!(
+            "Submodule '{name}' is not initialized (run: git submodule update --init {})",
+            submodule.path().display()
+        ));
+    }
+    Ok(dir)
+}
+
+fn find_commit<'repo>(repo: &'repo Repository, commit_id: &str) -> Result<git2::Commit<'repo>> {
+    if let Some(index) = parse_stash_ref(commit_id) {
+        return find_stash(repo, commit_id, index);
+    }
+    // Resolved here so every command taking a commit accepts it
+    if commit_id == LATEST_TAG_SPEC {
+        let (tag, _) = latest_tag(repo)?;
+        return find_commit(repo, &format!("refs/tags/{tag}"));
+    }
+
+    let object = repo
+        .revparse_single(commit_id)
+        .map_err(|e| revparse_error(repo, commit_id, e))?;
+
+    // Follow annotated tags (including tags of tags) to the object they point at
+    let mut target = object.clone();
+    while let Some(tag) = target.as_tag() {
+        target = tag
+            .target()
+            .with_context(|| format!("Failed to resolve annotated tag '{commit_id}'"))?;
+    }
+
+    let kind = target
+        .kind()
+        .map_or_else(|| "unknown object".to_string(), |kind| kind.to_string());
+    target.into_commit().map_err(|_| {
+        let via_tag = match object.kind() {
+            Some(git2::ObjectType::Tag) => " (via annotated tag)",
+            _ => "",
+        };
+        anyhow::anyhow!("'{commit_id}' points to a {kind}{via_tag}, expected a commit")
+    })
+}
+
+/// Fails when tracked files have staged or unstaged changes, for
+/// `--require-clean`. Untracked files don't count.
+fn check_clean_worktree() -> Result<()> {
+    let repo = open_repository()?;
+    if repo.is_bare() {
+        return Ok(());
+    }
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    let statuses = repo
+        .statuses(Some(&mut options))
+        .context("Failed to read the working tree status")?;
+    let changed: Vec<String> = statuses
+        .iter()
+        .filter(|entry| entry.status() != git2::Status::CURRENT)
+        .filter_map(|entry| entry.path().map(str::to_string))
+        .collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    const SHOWN: usize = 5;
+    let mut listed = changed[..changed.len().min(SHOWN)].join(", ");
+    if changed.len() > SHOWN {
+        listed.push_str(&format!(" and {} more", changed.len() - SHOWN));
+    }
+    Err(anyhow::anyhow!(
+        "The working tree has uncommitted changes ({listed}); commit or stash them, or drop --require-clean"
+    ))
+}
+
+/// The tag whose commit is newest by commit date, with that commit's short ID.
+/// Tags on the same second are ordered by ancestry, then by name.
+fn latest_tag(repo: &Repository) -> Result<(String, String)> {
+    let names = repo.tag_names(None).context("Failed to list tags")?;
+    let mut latest: Option<(String, git2::Commit)> = None;
+    for name in names.iter().flatten() {
+        // Tags of trees or blobs can't be checked out
+        let Ok(commit) = repo
+            .revparse_single(&format!("refs/tags/{name}"))
+            .and_then(|object| object.peel_to_commit())
+        else {
+            continue;
+        };
+        let newer = match &latest {
+            None => true,
+            Some((latest_name, latest_commit)) => {
+                match commit.time().seconds().cmp(&latest_commit.time().seconds()) {
+                    std::cmp::Ordering::Greater => true,
+                    std::cmp::Ordering::Less => false,
+                    std::cmp::Ordering::Equal if commit.id() != latest_commit.id() => repo
+                        .graph_descendant_of(commit.id(), latest_commit.id())
+                        .unwrap_or(false),
+                    std::cmp::Ordering::Equal => name > latest_name.as_str(),
+                }
+            }
+        };
+        if newer {
+            latest = Some((name.to_string(), commit));
+        }
+    }
+
+    let (name, commit) = latest.ok_or_else(|| {
+        anyhow::anyhow!(
+            "No tags found in the repository; {LATEST_TAG_SPEC} needs a tag on a commit"
+        )
+    })?;
+    let short_id = commit.as_object().short_id()?;
+    Ok((name, short_id.as_str().unwrap_or_default().to_string()))
+}
+
+/// Looks up a stash entry. Its commit holds the stashed working tree, so it is
+/// checked out like any other commit.
+fn find_stash<'repo>(
+    repo: &'repo Repository,
+    spec: &str,
+    index: usize,
+) -> Result<git2::Commit<'repo>> {
+    let reflog = repo
+        .reflog("refs/stash")
+        .context("Failed to read the stash")?;
+    let entry = reflog.get(index).ok_or_else(|| match reflog.len() {
+        0 => anyhow::anyhow!("Stash entry '{spec}' does not exist: the stash is empty"),
+        1 => anyhow::anyhow!("Stash entry '{spec}' does not exist: the stash only has stash@{{0}}"),
+        entries => anyhow::anyhow!(
+            "Stash entry '{spec}' does not exist: the stash has {entries} entries (stash@{{0}} to stash@{{{}}})",
+            entries - 1
+        ),
+    })?;
+    repo.find_commit(entry.id_new())
+        .with_context(|| format!("Failed to read stash entry '{spec}'"))
+}
+
+/// Explains a failed `revparse_single`, singling out `~`/`^` expressions whose
+/// base resolves but which reach past the available history.
+fn revparse_error(repo: &Repository, spec: &str, error: git2::Error) -> anyhow::Error {
+    let not_found = |error: git2::Error| {
+        anyhow::Error::new(error).context("Commit not found - invalid commit ID")
+    };
+
+    let Some(offset) = spec.find(['~', '^']) else {
+        return not_found(error);
+    };
+    let base = &spec[..offset];
+    let Some(base_commit) = repo
+        .revparse_single(base)
+        .ok()
+        .and_then(|object| object.peel_to_commit().ok())
+    else {
+        return not_found(error).context(format!("'{base}' in '{spec}' does not name a commit"));
+    };
+
+    let mut ancestors = 0;
+    let mut current = base_commit;
+    while let Ok(parent) = current.parent(0) {
+        ancestors += 1;
+        current = parent;
+    }
+    let plural = if ancestors == 1 { "" } else { "s" };
+    anyhow::Error::new(error).context(format!(
+        "Cannot resolve '{spec}': not enough history ('{base}' has only {ancestors} ancestor{plural} along its first-parent chain)"
+    ))
+}
+
+fn resolve_commit_info(commit_id: &str) -> Result<CommitInfo> {
+    let repo = open_repository()?;
+    let commit = find_commit(&repo, commit_id)?;
+    let short_id = commit.as_object().short_id()?;
+    let author = commit.author().name().unwrap_or_default().to_string();
+    let head = if commit_id.starts_with("HEAD") {
+        head_state(&repo)?
+    } else {
+        None
+    };
+
+    Ok(CommitInfo {
+        id: commit.id().to_string(),
+        short_id: short_id.as_str().unwrap_or_default().to_string(),
+        summary: commit.summary().unwrap_or_default().to_string(),
+        author,
+        head,
+    })
+}
+
+fn head_state(repo: &Repository) -> Result<Option<HeadState>> {
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(_) => return Ok(None),
+    };
+    let short_id = head.peel_to_commit()?.as_object().short_id()?;
+    let branch = if repo.head_detached()? {
+        None
+    } else {
+        head.shorthand().map(str::to_string)
+    };
+
+    Ok(Some(HeadState {
+        branch,
+        short_id: short_id.as_str().unwrap_or_default().to_string(),
+    }))
+}
+
+fn checkout_commit_to_temp(
+    commit_id: &str,
+    temp_path: &std::path::Path,
+    options: &CheckoutOptions,
+) -> Result<CheckoutSummary> {
+    let repo = open_repository()?;
+    let commit = find_commit(&repo, commit_id)?;
+
+    let tree = commit.tree().context("Failed to get commit tree")?;
+    let mut ignore = read_izignore(&std::env::current_dir()?)?;
+    if let Some(base) = &options.changed_since {
+        let changed = changed_paths(&repo, base, &tree)?;
+        status!("✏️  {} files changed since {base}", changed.len());
+        ignore.restrict_to(changed);
+    }
+    // The run's own metadata lives under that name, so the commit's copy can't
+    if tree.get_name(RUN_META_FILE).is_some() {
+        say_err!("⚠️  The commit's own {RUN_META_FILE} is reserved for run metadata, skipping");
+        ignore.exclude(RUN_META_FILE);
+    }
+
+    // Pre-create directory structure to avoid git2 checkout issues
+    if options.precreate_dirs {
+        create_directory_structure(&tree, temp_path, &ignore, options.trace.as_deref())
+            .context("Failed to create directory structure")?;
+    }
+
+    // git2 compares against the repository's HEAD rather than the target
+    // directory and leaves files matching HEAD alone, so files already present
+    // there are resolved up front: reported when safe, removed when forced
+    let existing = find_checkout_conflicts(&repo, &tree, temp_path, &ignore)?;
+    if !existing.is_empty() {
+        if options.strategy == CheckoutStrategy::Safe {
+            return Err(checkout_conflict_error(&existing));
+        }
+        for path in &existing {
+            let path = temp_path.join(path);
+            let removed = if path.is_dir() && !path.is_symlink() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            removed.with_context(|| format!("Failed to overwrite {}", path.display()))?;
+        }
+    }
+
+    let conflicts = std::cell::RefCell::new(Vec::new());
+    let mut checkout_builder = git2::build::CheckoutBuilder::new();
+    checkout_builder.target_dir(temp_path);
+    checkout_builder.recreate_missing(true);
+    // The checkout lives outside the work tree, so the repository's index is
+    // left alone; this also lets parallel runs check out without its lock
+    checkout_builder.update_index(false);
+    match options.strategy {
+        CheckoutStrategy::Force => {
+            checkout_builder.force();
+        }
+        CheckoutStrategy::Safe => {
+            checkout_builder.safe();
+            checkout_builder.notify_on(git2::CheckoutNotificationType::CONFLICT);
+            checkout_builder.notify(|_, path, _, _, _| {
+                if let Some(path) = path {
+                    conflicts.borrow_mut().push(path.display().to_string());
+                }
+                true
+            });
+        }
+    }
+
+    // Limit the checkout to the paths .izignore doesn't exclude
+    if !ignore.is_empty() {
+        let (paths, ignored) = checkout_paths(&tree, &ignore)?;
+        if options.changed_since.is_none() {
+            status!("🙈 Excluding {ignored} paths listed in .izignore");
+        }
+        if paths.is_empty() {
+            return Ok(CheckoutSummary::default());
+        }
+        for path in paths {
+            checkout_builder.path(path);
+        }
+    }
+
+    let progress_bar = checkout_progress_bar();
+    if progress_bar.is_some() || events::enabled() || options.trace.is_some() {
+        let bar = progress_bar.clone();
+        let trace = options.trace.clone();
+        let mut throttle = events::Throttle::new(events::PROGRESS_INTERVAL);
+        checkout_builder.progress(move |path, completed, total| {
+            if let (Some(trace), Some(path)) = (&trace, path) {
+                trace.log("write", &temp_path.join(path));
+            }
+            if let Some(bar) = &bar {
+                bar.set_length(total as u64);
+                bar.set_position(completed as u64);
+            }
+            // The final update always goes out so consumers see completion
+            if completed == total || throttle.ready() {
+                events::emit(&events::Event::CheckoutProgress {
+                    phase: events::CheckoutPhase::Files,
+                    total,
+                    completed,
+                    path: path.map(|path| path.display().to_string()),
+                });
+            }
+        });
+    }
+
+    let result = repo
+        .checkout_tree(tree.as_object(), Some(&mut checkout_builder))
+        .context("Failed to extract files");
+    drop(checkout_builder);
+
+    if let Some(bar) = progress_bar {
+        bar.finish_and_clear();
+    }
+
+    let conflicts = conflicts.into_inner();
+    if !conflicts.is_empty() {
+        return Err(checkout_conflict_error(&conflicts));
+    }
+    result?;
+
+    let skipped_symlinks = apply_symlink_mode(&tree, temp_path, options.symlink_mode, &ignore)
+        .context("Failed to process symlinks")?;
+
+    if options.line_endings != LineEndings::Auto {
+        let crlf = options.line_endings == LineEndings::Crlf;
+        apply_line_endings(&tree, temp_path, crlf, &ignore)
+            .context("Failed to convert line endings")?;
+    }
+
+    // Applied before .git is attached so git itself can still update its index
+    if options.readonly {
+        #[cfg(unix)]
+        iz::set_tree_readonly(temp_path, true).context("Failed to make the checkout read-only")?;
+        #[cfg(not(unix))]
+        status!("⚠️  --readonly is only supported on Unix; the checkout stays writable");
+    }
+
+    if options.with_git {
+        attach_git_metadata(&repo, &commit, temp_path)
+            .context("Failed to set up .git in the checkout")?;
+    }
+
+    let tree_stats = if options.stats {
+        Some(
+            tree_stats(temp_path, TREE_STATS_LARGEST)
+                .context("Failed to collect checkout stats")?,
+        )
+    } else {
+        None
+    };
+
+    Ok(CheckoutSummary {
+        skipped_symlinks,
+        tree_stats,
+    })
+}
+
+/// Rewrites every checked-out text file to LF or CRLF line endings, overriding
+/// what the repository's attributes produced. Symlinks and binary files are left alone.
+fn apply_line_endings(
+    tree: &git2::Tree,
+    base_path: &std::path::Path,
+    crlf: bool,
+    ignore: &IgnoreRules,
+) -> Result<()> {
+    let (paths, _) = checkout_paths(tree, ignore)?;
+    for path in paths {
+        let path = base_path.join(path);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {}
+            _ => continue,
+        }
+        let content = fs::read(&path)?;
+        if let Some(converted) = convert_line_endings(&content, crlf) {
+            fs::write(&path, converted)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Files added or modified in `tree` since the `base` commit. Deleted files
+/// have nothing to check out and are left out.
+fn changed_paths(repo: &Repository, base: &str, tree: &git2::Tree) -> Result<Vec<String>> {
+    let base_tree = find_commit(repo, base)?
+        .tree()
+        .context("Failed to get base commit tree")?;
+    let diff = repo
+        .diff_tree_to_tree(Some(&base_tree), Some(tree), None)
+        .with_context(|| format!("Failed to diff against {base}"))?;
+
+    Ok(diff
+        .deltas()
+        .filter(|delta| delta.status() != git2::Delta::Deleted)
+        .filter_map(|delta| delta.new_file().path()?.to_str().map(str::to_string))
+        .collect())
+}
+
+/// Collects the non-directory paths of `tree` that are not excluded, along
+/// with the number of excluded entries (an excluded directory counts once).
+fn checkout_paths(tree: &git2::Tree, ignore: &IgnoreRules) -> Result<(Vec<String>, usize)> {
+    let mut paths = Vec::new();
+    let mut ignored = 0;
+
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        let path = format!("{root}{}", entry.name().unwrap_or(""));
+        let is_dir = entry.kind() == Some(git2::ObjectType::Tree);
+
+        if ignore.is_ignored(&path, is_dir) {
+            ignored += 1;
+            return git2::TreeWalkResult::Skip;
+        }
+        if !is_dir {
+            paths.push(path);
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+
+    Ok((paths, ignored))
+}
+
+/// Turns the checkout into a repository whose HEAD is the checked-out commit.
+/// Objects are shared with the source repository through `alternates` rather
+/// than copied, and branches and tags are copied so `git describe` and
+/// `git log` work.
+fn attach_git_metadata(
+    source: &Repository,
+    commit: &git2::Commit,
+    temp_path: &std::path::Path,
+) -> Result<()> {
+    let repo = Repository::init(temp_path)?;
+
+    let objects_dir = source.path().join("objects").canonicalize()?;
+    let info_dir = repo.path().join("objects").join("info");
+    fs::create_dir_all(&info_dir)?;
+    fs::write(
+        info_dir.join("alternates"),
+        format!("{}\n", objects_dir.display()),
+    )?;
+
+    for reference in source.references()? {
+        let reference = reference?;
+        let (Some(name), Some(target)) = (reference.name(), reference.target()) else {
+            continue;
+        };
+        if name.starts_with("refs/heads/") || name.starts_with("refs/tags/") {
+            repo.reference(name, target, true, "iz: copy ref")?;
+        }
+    }
+
+    repo.set_head_detached(commit.id())?;
+
+    let mut index = repo.index()?;
+    index.read_tree(&commit.tree()?)?;
+    index.write()?;
+
+    Ok(())
+}
+
+fn report_skipped_symlinks(summary: &CheckoutSummary) {
+    if !summary.skipped_symlinks.is_empty() {
+        say_err!(
+            "⚠️  Skipped {} symlinks: {}",
+            summary.skipped_symlinks.len(),
+            summary.skipped_symlinks.join(", ")
+        );
+    }
+}
+
+fn report_tree_stats(summary: &CheckoutSummary) {
+    let Some(stats) = &summary.tree_stats else {
+        return;
+    };
+    status!(
+        "📊 {} files, {} total",
+        stats.files,
+        format_bytes(stats.total_bytes)
+    );
+    for file in &stats.largest {
+        status!("   {:>10}  {}", format_bytes(file.bytes), file.path);
+    }
+}
+
+/// Post-processes symlink entries of the checked-out tree according to `mode`
+/// and returns the paths that were left out.
+fn apply_symlink_mode(
+    tree: &git2::Tree,
+    base_path: &std::path::Path,
+    mode: SymlinkMode,
+    ignore: &IgnoreRules,
+) -> Result<Vec<String>> {
+    let mut symlinks = Vec::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        let path = format!("{root}{}", entry.name().unwrap_or(""));
+        let is_dir = entry.kind() == Some(git2::ObjectType::Tree);
+        if ignore.is_ignored(&path, is_dir) {
+            return git2::TreeWalkResult::Skip;
+        }
+        if entry.filemode() == i32::from(git2::FileMode::Link) {
+            symlinks.push(path);
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+
+    let checkout_root = base_path.canonicalize()?;
+    let mut skipped = Vec::new();
+    for relative in symlinks {
+        let link_path = base_path.join(&relative);
+
+        match mode {
+            SymlinkMode::Preserve => {
+                if !preserve_symlink(&link_path)? {
+                    copy_symlink_target(&link_path, &relative, &checkout_root, &mut skipped)?;
+                }
+            }
+            SymlinkMode::Copy => {
+                copy_symlink_target(&link_path, &relative, &checkout_root, &mut skipped)?
+            }
+            SymlinkMode::Skip => {
+                remove_link(&link_path)?;
+                skipped.push(relative);
+            }
+        }
+    }
+
+    Ok(skipped)
+}
+
+/// Reads where a checked-out link points. Without symlink support git2 writes
+/// the target path into a regular file instead.
+fn symlink_target(link_path: &std::path::Path) -> Result<PathBuf> {
+    let metadata = fs::symlink_metadata(link_path)?;
+    if metadata.file_type().is_symlink() {
+        Ok(fs::read_link(link_path)?)
+    } else {
+        Ok(PathBuf::from(fs::read_to_string(link_path)?.trim()))
+    }
+}
+
+#[cfg(unix)]
+fn preserve_symlink(_link_path: &std::path::Path) -> Result<bool> {
+    Ok(true)
+}
+
+#[cfg(windows)]
+fn preserve_symlink(link_path: &std::path::Path) -> Result<bool> {
+    if fs::symlink_metadata(link_path)?.file_type().is_symlink() {
+        return Ok(true);
+    }
+
+    let target = symlink_target(link_path)?;
+    let resolved = link_path.parent().unwrap_or(link_path).join(&target);
+    fs::remove_file(link_path)?;
+
+    let created = if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(&target, link_path)
+    } else {
+        std::os::windows::fs::symlink_file(&target, link_path)
+    };
+
+    if let Err(e) = created {
+        say_err!(
+            "⚠️  Could not create symlink {} ({e}), copying its target instead",
+            link_path.display()
+        );
+        fs::write(link_path, target.to_string_lossy().as_bytes())?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Replaces the link with a copy of its target. A link whose target is
+/// missing, lies outside `checkout_root` or is a directory containing the
+/// link is removed and reported as skipped instead.
+fn copy_symlink_target(
+    link_path: &std::path::Path,
+    relative: &str,
+    checkout_root: &std::path::Path,
+    skipped: &mut Vec<String>,
+) -> Result<()> {
+    let target = symlink_target(link_path)?;
+    let resolved = link_path.parent().unwrap_or(link_path).join(target);
+
+    if !resolved.exists() {
+        say_err!("⚠️  Symlink target missing, skipping: {relative}");
+        remove_link(link_path)?;
+        skipped.push(relative.to_string());
+        return Ok(());
+    }
+
+    let resolved = resolved.canonicalize()?;
+    let problem = if !resolved.starts_with(checkout_root) {
+        Some("points outside the checkout")
+    } else if link_location(link_path)?.starts_with(&resolved) {
+        Some("points at a directory containing it")
+    } else {
+        None
+    };
+    remove_link(link_path)?;
+    if let Some(problem) = problem {
+        say_err!("⚠️  Symlink {relative} {problem}, skipping");
+        skipped.push(relative.to_string());
+        return Ok(());
+    }
+
+    if resolved.is_dir() {
+        copy_dir_recursive(&resolved, link_path, checkout_root, &mut Vec::new())?;
+    } else {
+        fs::copy(&resolved, link_path)?;
+    }
+
+    Ok(())
+}
+
+fn remove_link(link_path: &std::path::Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(link_path)?;
+    if metadata.is_dir() {
+        fs::remove_dir(link_path)?;
+    } else {
+        fs::remove_file(link_path)?;
+    }
+    Ok(())
+}
+
+/// The absolute path of `path` without following it if it is a link itself.
+fn link_location(path: &std::path::Path) -> Result<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    Ok(parent
+        .canonicalize()?
+        .join(path.file_name().unwrap_or_default()))
+}
+
+/// Copies the canonical directory `source` to `destination`, following links
+/// that stay inside `checkout_root`. `ancestors` holds the directories being
+/// copied above this one, so a link back to one of them is skipped instead of
+/// recursing forever.
+fn copy_dir_recursive(
+    source: &std::path::Path,
+    destination: &std::path::Path,
+    checkout_root: &std::path::Path,
+    ancestors: &mut Vec<PathBuf>,
+) -> Result<()> {
+    ancestors.push(source.to_path_buf());
+    fs::create_dir_all(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = destination.join(entry.file_name());
+        let path = if entry.file_type()?.is_symlink() {
+            match entry.path().canonicalize() {
+                Ok(resolved) if resolved.starts_with(checkout_root) => resolved,
+                _ => {
+                    say_err!(
+                        "⚠️  Symlink {} is missing or points outside the checkout, skipping",
+                        entry.path().display()
+                    );
+                    continue;
+                }
+            }
+        } else {
+            entry.path()
+        };
+
+        if !path.is_dir() {
+            fs::copy(&path, &target)?;
+        } else if ancestors.contains(&path) || link_location(&target)?.starts_with(&path) {
+            say_err!("⚠️  Symlink loop at {}, skipping", entry.path().display());
+        } else {
+            copy_dir_recursive(&path, &target, checkout_root, ancestors)?;
+        }
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+/// Swaps emoji for ASCII tags when `--no-emoji` is active.
+fn decorate(text: &str) -> std::borrow::Cow<'_, str> {
+    if NO_EMOJI.load(Ordering::Relaxed) {
+        std::borrow::Cow::Owned(ascii_decorations(text))
+    } else {
+        std::borrow::Cow::Borrowed(text)
+    }
+}
+
+/// A "N/M files" spinner for interactive terminals; `None` for pipes and JSON output.
+fn checkout_progress_bar() -> Option<indicatif::ProgressBar> {
+    if !std::io::stdout().is_terminal() || JSON_OUTPUT.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let bar = indicatif::ProgressBar::new(0);
+    let mut style =
+        indicatif::ProgressStyle::with_template("{spinner} Checking out {pos}/{len} files").ok()?;
+    if NO_EMOJI.load(Ordering::Relaxed) {
+        style = style.tick_chars("-\\|/ ");
+    }
+    bar.set_style(style);
+    bar.enable_steady_tick(std::time::Duration::from_millis(100));
+    Some(bar)
+}
+
+fn create_directory_structure(
+    tree: &git2::Tree,
+    base_path: &std::path::Path,
+    ignore: &IgnoreRules,
+    trace: Option<&FsTrace>,
+) -> Result<()> {
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        if let Some(git2::ObjectType::Tree) = entry.kind() {
+            if ignore.is_ignored(&format!("{root}{}", entry.name().unwrap_or("")), true) {
+                return git2::TreeWalkResult::Skip;
+            }
+            let dir_path = base_path.join(root).join(entry.name().unwrap_or(""));
+            if let Some(trace) = trace {
+                trace.log("mkdir", &dir_path);
+            }
+            if let Err(e) = fs::create_dir_all(&dir_path) {
+                eprintln!(
+                    "Warning: Failed to create directory {}: {}",
+                    dir_path.display(),
+                    e
+                );
+            }
+        }
+        git2::TreeWalkResult::Ok
+    })?;
 
-    /// Temporary directory path (default: .iztemp)
-    #[arg(long)]
-    temp_dir: Option<String>,
+    Ok(())
+}
 
-    /// Additional parameters (--key=value format)
-    #[arg(long, value_parser = parse_key_val)]
-    param: Vec<(String, String)>,
+/// Lists the files in `tree` whose path already exists in `target` with
+/// different content, which a safe checkout must not overwrite.
+fn find_checkout_conflicts(
+    repo: &Repository,
+    tree: &git2::Tree,
+    target: &std::path::Path,
+    ignore: &IgnoreRules,
+) -> Result<Vec<String>> {
+    let mut conflicts = Vec::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        let path = format!("{root}{}", entry.name().unwrap_or(""));
+        let is_tree = entry.kind() == Some(git2::ObjectType::Tree);
+        if ignore.is_ignored(&path, is_tree) {
+            return git2::TreeWalkResult::Skip;
+        }
+        if is_tree {
+            return git2::TreeWalkResult::Ok;
+        }
+        let existing = target.join(&path);
+        if fs::symlink_metadata(&existing).is_err() {
+            return git2::TreeWalkResult::Ok;
+        }
+        let unchanged = match (fs::read(&existing), repo.find_blob(entry.id())) {
+            (Ok(content), Ok(blob)) => content == blob.content(),
+            _ => false,
+        };
+        if !unchanged {
+            conflicts.push(path);
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(conflicts)
+}
 
-    /// Force operation without confirmation (for clean command)
-    #[arg(long)]
-    force: bool,
+fn checkout_conflict_error(paths: &[String]) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Checkout would overwrite {} existing path(s): {} (use --checkout-strategy force to overwrite them)",
+        paths.len(),
+        paths.join(", ")
+    )
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Builds the argv for running `command` through the shell. A multiline command
+/// is written to a script file in `dir` (returned for later removal) so the
+/// shell reports errors with line numbers.
+fn shell_invocation(
+    command: &str,
+    dir: &std::path::Path,
+) -> Result<(Vec<String>, Option<PathBuf>)> {
+    let (shell, flag, extension) = if cfg!(windows) {
+        ("cmd", "/C", "cmd")
+    } else {
+        ("sh", "-c", "sh")
+    };
 
-    // Check if first argument is "clean" command
-    if cli.commit_id_or_command == "clean" {
-        return clean_command(cli.temp_dir, cli.force).await;
+    if !command.contains('\n') {
+        return Ok((
+            vec![shell.to_string(), flag.to_string(), command.to_string()],
+            None,
+        ));
     }
 
-    // Original behavior for commit ID + command
-    let commit_id = cli.commit_id_or_command;
-    let command = cli.command.ok_or_else(|| {
-        anyhow::anyhow!("Command is required. Usage: iz <commit-id> <command> or iz clean")
-    })?;
+    let script_path = dir.join(format!(
+        ".iz-script-{:08x}.{extension}",
+        rand::random::<u32>()
+    ));
+    fs::write(&script_path, command)
+        .with_context(|| format!("Failed to write script: {}", script_path.display()))?;
 
-    run_command(commit_id, command, cli.keep, cli.temp_dir, cli.param).await
-}
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+    }
 
-async fn run_command(
-    commit_id: String,
-    command: String,
-    keep: bool,
-    temp_dir: Option<String>,
-    param: Vec<(String, String)>,
-) -> Result<()> {
-    println!("🔄 Starting iz CLI...");
+    let script = script_path.display().to_string();
+    let argv = if cfg!(windows) {
+        vec![shell.to_string(), flag.to_string(), script]
+    } else {
+        // -e stops at the first failing line
+        vec![shell.to_string(), "-e".to_string(), script]
+    };
 
-    let config = read_config().context("Failed to read izconfig.json")?;
+    Ok((argv, Some(script_path)))
+}
 
-    let command_template = config
-        .commands
-        .get(&command)
-        .ok_or_else(|| anyhow::anyhow!("Command '{}' not found in izconfig.json", command))?;
+/// Runs commands as local processes (or in the `--container`) with a run's exec options.
+struct LocalRunner<'a> {
+    options: &'a ExecOptions,
+}
 
-    let params: HashMap<String, String> = param.into_iter().collect();
-    let final_command = substitute_variables(command_template, &params)?;
+impl CommandRunner for LocalRunner<'_> {
+    fn run(
+        &self,
+        argv: &[String],
+        cwd: &std::path::Path,
+        env: &[(String, String)],
+    ) -> Result<CommandOutput> {
+        execute_command(argv, cwd, env, self.options)
+    }
+}
 
-    println!("🎯 Commit: {commit_id}");
-    println!("📝 Command: {final_command}");
+fn execute_command(
+    argv: &[String],
+    working_dir: &std::path::Path,
+    env: &[(String, String)],
+    options: &ExecOptions,
+) -> Result<CommandOutput> {
+    let (program, _) = argv
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("Empty command"))?;
 
-    let should_keep = keep || config.keep.unwrap_or(false);
-    let base_temp_dir = determine_temp_dir(&temp_dir, &config)?;
-    let temp_path = create_unique_temp_dir(&base_temp_dir)?;
+    check_allowed_command(program, options.allowed_commands.as_deref())?;
 
-    if !should_keep {
-        let mut cleanup_state = CLEANUP_STATE.lock().unwrap();
-        *cleanup_state = Some(temp_path.clone());
+    // The allowlist applies to the command itself, not to the container runtime
+    let argv = match &options.container {
+        Some(target) => {
+            let mount_source = fs::canonicalize(working_dir).with_context(|| {
+                format!("Failed to resolve checkout path: {}", working_dir.display())
+            })?;
+            container_argv(
+                target.runtime.program(),
+                &target.image,
+                working_dir,
+                &mount_source,
+                options.workdir.as_deref(),
+                env,
+                argv,
+            )
+        }
+        None => argv.to_vec(),
+    };
+    let (program, args) = argv.split_first().expect("argv is not empty");
+
+    if options.verbose {
+        let masked: Vec<String> = argv
+            .iter()
+            .map(|arg| mask_secrets(arg, &options.secrets))
+            .collect();
+        say_err!("🔎 argv: {}", serde_json::to_string(&masked)?);
     }
 
-    println!("📁 Temporary directory: {}", temp_path.display());
+    let mut cmd = Command::new(program);
+    cmd.args(args);
 
-    let signal_handle = if !should_keep {
-        Some(tokio::spawn(async {
-            let _ = setup_signal_handler().await;
-        }))
+    cmd.current_dir(workdir_path(working_dir, options.workdir.as_deref())?);
+    cmd.envs(env.iter().map(|(k, v)| (k, v)));
+    if options.null_stdin {
+        cmd.stdin(Stdio::null());
+    }
+
+    if options.pty {
+        run_pty(&mut cmd, options.tail, options.max_output, &options.timeout)
+    } else if options.binary_output {
+        run_binary(&mut cmd, options.max_output, &options.timeout)
+    } else if options.stream {
+        run_streaming(&mut cmd, options.tail, options.max_output, &options.timeout)
     } else {
-        None
-    };
+        run_buffered(
+            &mut cmd,
+            options.max_output,
+            options.quiet,
+            &options.timeout,
+        )
+    }
+}
 
-    checkout_commit_to_temp(&commit_id, &temp_path).context("Failed to checkout commit")?;
+/// Runs a command's generator in the current directory and returns its stdout
+/// as the template. The generator gets the command name as its argument and
+/// each `--param` as an `IZ_PARAM_<KEY>` environment variable.
+fn run_generator(
+    generator: &str,
+    command: &str,
+    params: &HashMap<String, String>,
+    options: &ExecOptions,
+) -> Result<String> {
+    check_allowed_command(generator, options.allowed_commands.as_deref())?;
 
-    println!("🚀 Executing command...");
-    execute_command(&final_command, &temp_path).context("Failed to execute command")?;
+    let output = Command::new(generator)
+        .arg(command)
+        .env("IZ_COMMAND_NAME", command)
+        .envs(
+            params
+                .iter()
+                .map(|(key, value)| (format!("IZ_PARAM_{}", key.to_uppercase()), value)),
+        )
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| spawn_error(e, generator))
+        .with_context(|| format!("Failed to run generator '{generator}'"))?;
 
-    cleanup_temp_directory(&temp_path, should_keep);
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Generator '{generator}' failed with status: {}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
 
-    if let Some(handle) = signal_handle {
-        handle.abort();
+    let template = String::from_utf8(output.stdout)
+        .with_context(|| format!("Generator '{generator}' printed invalid UTF-8"))?;
+    let template = template.trim_end();
+    if template.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "Generator '{generator}' printed no command"
+        ));
     }
 
-    println!("✅ Operation completed!");
-    Ok(())
+    Ok(template.to_string())
 }
 
-async fn clean_command(temp_dir: Option<String>, force: bool) -> Result<()> {
-    println!("🧹 Starting cleanup...");
+/// Picks docker, or podman when docker isn't installed.
+fn detect_container_runtime() -> Result<ContainerRuntime> {
+    [ContainerRuntime::Docker, ContainerRuntime::Podman]
+        .into_iter()
+        .find(|runtime| {
+            Command::new(runtime.program())
+                .arg("--version")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No container runtime found for --container: install docker or podman, or pick one with --runtime"
+            )
+        })
+}
 
-    let config = read_config().context("Failed to read izconfig.json")?;
-    let base_temp_dir = determine_temp_dir(&temp_dir, &config)?;
+fn run_after_command(
+    after: &AfterCommand,
+    working_dir: &std::path::Path,
+    runner: &dyn CommandRunner,
+    options: &ExecOptions,
+) -> Result<AfterResult> {
+    let checkout = working_dir.canonicalize()?;
+    let (command, argv, script_path) = if after.shell {
+        let command = substitute_file_paths(&after.final_command, &checkout)?;
+        let (argv, script_path) = shell_invocation(&command, &checkout)?;
+        (command, argv, script_path)
+    } else {
+        let argv = after
+            .argv
+            .iter()
+            .map(|arg| substitute_file_paths(arg, &checkout))
+            .collect::<Result<Vec<_>, _>>()?;
+        (join_argv(&argv), argv, None)
+    };
+    let final_command = mask_secrets(&command, &options.secrets);
+    status!("🔍 Running after command '{}': {final_command}", after.name);
+    let output = runner.run(&argv, working_dir, &options.env);
+    if let Some(script_path) = &script_path {
+        let _ = fs::remove_file(script_path);
+    }
+    let output =
+        output.with_context(|| format!("Failed to execute after command '{}'", after.name))?;
 
-    if !base_temp_dir.exists() {
-        println!(
-            "📁 Temporary directory does not exist: {}",
-            base_temp_dir.display()
+    let success = output.status.success();
+    if success {
+        status!("✅ After command '{}' passed", after.name);
+    } else {
+        say_err!(
+            "⚠️  After command '{}' failed with status: {}",
+            after.name,
+            output.status
         );
-        return Ok(());
     }
 
-    // List contents of temp directory
-    let entries = fs::read_dir(&base_temp_dir)
-        .with_context(|| format!("Failed to read temp directory: {}", base_temp_dir.display()))?;
+    Ok(AfterResult {
+        command: after.name.clone(),
+        final_command,
+        success,
+        exit_code: output.status.code(),
+        stdout: output.stdout_text(),
+        stderr: output.stderr_text(),
+    })
+}
 
-    let mut items_to_clean = Vec::new();
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir()
-            && path
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .starts_with("iz-")
-        {
-            items_to_clean.push(path);
+fn run_buffered(
+    cmd: &mut Command,
+    max_output: Option<usize>,
+    quiet: bool,
+    timeout: &Timeout,
+) -> Result<CommandOutput> {
+    let (status, stdout, stderr) = capture_output(cmd, max_output, timeout)?;
+    let output = CommandOutput {
+        status,
+        stdout: stdout.into_bytes(),
+        stderr: stderr.into_bytes(),
+    };
+
+    if !quiet && !JSON_OUTPUT.load(Ordering::Relaxed) {
+        if !output.stdout.is_empty() {
+            say!("📄 Output:");
+            println!("{}", String::from_utf8_lossy(&output.stdout));
+        }
+
+        if !output.stderr.is_empty() {
+            say_err!("⚠️  Error output:");
+            eprintln!("{}", String::from_utf8_lossy(&output.stderr));
         }
     }
 
-    if items_to_clean.is_empty() {
-        println!(
-            "✨ No temporary directories to clean in: {}",
-            base_temp_dir.display()
+    Ok(output)
+}
+
+fn run_binary(
+    cmd: &mut Command,
+    max_output: Option<usize>,
+    timeout: &Timeout,
+) -> Result<CommandOutput> {
+    let (status, stdout, stderr) = capture_output(cmd, max_output, timeout)?;
+    // A marker would corrupt the raw bytes, so binary stdout is only reported as truncated
+    if stdout.is_truncated() {
+        say_err!(
+            "⚠️  Binary output truncated at {} bytes",
+            max_output.unwrap_or_default()
         );
-        return Ok(());
     }
+    let output = CommandOutput {
+        status,
+        stdout: stdout.into_raw_bytes(),
+        stderr: stderr.into_bytes(),
+    };
 
-    println!("📋 Found {} temporary directories:", items_to_clean.len());
-    for item in &items_to_clean {
-        println!("  • {}", item.display());
+    if !JSON_OUTPUT.load(Ordering::Relaxed) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&output.stdout)?;
+        stdout.flush()?;
+        std::io::stderr().write_all(&output.stderr)?;
     }
 
-    if !force {
-        print!("❓ Do you want to clean these directories? [y/N]: ");
-        std::io::Write::flush(&mut std::io::stdout())?;
+    Ok(output)
+}
 
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let input = input.trim().to_lowercase();
+/// Runs the command to completion, keeping at most `max_output` bytes of each stream.
+/// Both pipes are drained to the end so the command never blocks on a full pipe.
+fn capture_output(
+    cmd: &mut Command,
+    max_output: Option<usize>,
+    timeout: &Timeout,
+) -> Result<(std::process::ExitStatus, CappedBuffer, CappedBuffer)> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error(e, &cmd.get_program().to_string_lossy()))?;
 
-        if input != "y" && input != "yes" {
-            println!("🚫 Cleanup cancelled");
-            return Ok(());
-        }
-    }
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
 
-    let mut cleaned_count = 0;
-    let mut failed_count = 0;
+    let stdout_handle = std::thread::spawn(move || read_capped(stdout, max_output));
+    let stderr_handle = std::thread::spawn(move || read_capped(stderr, max_output));
 
-    for item in items_to_clean {
-        match fs::remove_dir_all(&item) {
-            Ok(()) => {
-                cleaned_count += 1;
-                println!("✅ Cleaned: {}", item.display());
-            }
-            Err(e) => {
-                failed_count += 1;
-                eprintln!("❌ Failed to clean {}: {}", item.display(), e);
-            }
-        }
+    let status = wait_with_timeout(&mut child, timeout)?;
+
+    let stdout = stdout_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to read command output"))??;
+    let stderr = stderr_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to read command error output"))??;
+
+    Ok((status, stdout, stderr))
+}
+
+/// Waits for the command, stopping it once it overruns the timeout: with
+/// `--timeout-signal term` it gets SIGTERM and the grace period to exit before
+/// being killed. Where SIGTERM isn't available (Windows) it is killed directly.
+/// The output readers of a timed-out command are not joined, since a process
+/// it spawned may still hold the pipes open.
+fn wait_with_timeout(
+    child: &mut std::process::Child,
+    timeout: &Timeout,
+) -> Result<std::process::ExitStatus> {
+    // A limit too far out to represent is no limit at all
+    let deadline = timeout.limit.and_then(|limit| {
+        std::time::Instant::now()
+            .checked_add(limit)
+            .map(|at| (limit, at))
+    });
+    let Some((limit, deadline)) = deadline else {
+        return child.wait().context("Failed to wait for command");
+    };
+
+    if let Some(status) = wait_until(child, deadline)? {
+        return Ok(status);
     }
 
-    if failed_count == 0 {
-        println!("🎉 Successfully cleaned {cleaned_count} directories!");
-    } else {
-        println!("⚠️  Cleaned {cleaned_count} directories, {failed_count} failed");
+    let terminated = timeout.signal == TimeoutSignal::Term
+        && send_sigterm(child)
+        && match std::time::Instant::now().checked_add(timeout.grace_period) {
+            Some(deadline) => wait_until(child, deadline)?.is_some(),
+            None => child.wait().is_ok(),
+        };
+    if !terminated {
+        // Already exited if the grace period just ran out, which isn't an error
+        let _ = child.kill();
+        child.wait().context("Failed to wait for command")?;
     }
 
-    Ok(())
+    let how = match (timeout.signal, terminated) {
+        (TimeoutSignal::Term, true) => "stopped with SIGTERM".to_string(),
+        (TimeoutSignal::Term, false) if cfg!(unix) => format!(
+            "killed after ignoring SIGTERM for {:?}",
+            timeout.grace_period
+        ),
+        _ => "killed".to_string(),
+    };
+    Err(anyhow::anyhow!("Command timed out after {limit:?} ({how})"))
 }
 
-fn checkout_commit_to_temp(commit_id: &str, temp_path: &std::path::Path) -> Result<()> {
-    let repo = Repository::open(std::env::current_dir()?)
-        .context("Git repository not found - this directory is not a git repository")?;
+/// Polls the child until it exits or `deadline` passes.
+fn wait_until(
+    child: &mut std::process::Child,
+    deadline: std::time::Instant,
+) -> Result<Option<std::process::ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for command")? {
+            return Ok(Some(status));
+        }
+        if std::time::Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(TIMEOUT_POLL_INTERVAL.min(deadline - std::time::Instant::now()));
+    }
+}
 
-    let object = repo
-        .revparse_single(commit_id)
-        .context("Commit not found - invalid commit ID")?;
+#[cfg(unix)]
+fn send_sigterm(child: &std::process::Child) -> bool {
+    // SAFETY: kill(2) has no memory-safety preconditions
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) == 0 }
+}
 
-    let commit = object
-        .peel_to_commit()
-        .context("Given reference does not point to a commit")?;
+#[cfg(not(unix))]
+fn send_sigterm(_child: &std::process::Child) -> bool {
+    false
+}
 
-    let tree = commit.tree().context("Failed to get commit tree")?;
+fn read_capped(
+    mut stream: impl std::io::Read,
+    max_output: Option<usize>,
+) -> std::io::Result<CappedBuffer> {
+    let mut buffer = CappedBuffer::new(max_output);
+    let mut chunk = [0u8; 8192];
 
-    // Pre-create directory structure to avoid git2 checkout issues
-    create_directory_structure(&tree, temp_path).context("Failed to create directory structure")?;
+    loop {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        buffer.push(&chunk[..read]);
+    }
 
-    let mut checkout_builder = git2::build::CheckoutBuilder::new();
-    checkout_builder.target_dir(temp_path);
-    checkout_builder.force();
-    checkout_builder.recreate_missing(true);
+    Ok(buffer)
+}
 
-    repo.checkout_tree(tree.as_object(), Some(&mut checkout_builder))
-        .context("Failed to extract files")?;
+fn run_streaming(
+    cmd: &mut Command,
+    tail: usize,
+    max_output: Option<usize>,
+    timeout: &Timeout,
+) -> Result<CommandOutput> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error(e, &cmd.get_program().to_string_lossy()))?;
 
-    Ok(())
-}
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
 
-fn create_directory_structure(tree: &git2::Tree, base_path: &std::path::Path) -> Result<()> {
-    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
-        if let Some(git2::ObjectType::Tree) = entry.kind() {
-            let dir_path = base_path.join(root).join(entry.name().unwrap_or(""));
-            if let Err(e) = fs::create_dir_all(&dir_path) {
-                eprintln!(
-                    "Warning: Failed to create directory {}: {}",
-                    dir_path.display(),
-                    e
-                );
+    // Keep stdout clean for the JSON result by forwarding streamed lines to stderr
+    let json = JSON_OUTPUT.load(Ordering::Relaxed);
+    let stdout_handle = std::thread::spawn(move || {
+        forward_lines(stdout, tail, max_output, |line| {
+            if json {
+                eprintln!("{line}");
+            } else {
+                println!("{line}");
             }
-        }
-        git2::TreeWalkResult::Ok
-    })?;
+        })
+    });
+    let stderr_handle = std::thread::spawn(move || {
+        forward_lines(stderr, tail, max_output, |line| eprintln!("{line}"))
+    });
 
-    Ok(())
+    let status = wait_with_timeout(&mut child, timeout)?;
+
+    let stdout_tail = stdout_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to read command output"))??;
+    let stderr_tail = stderr_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to read command error output"))??;
+
+    Ok(CommandOutput {
+        status,
+        stdout: stdout_tail.contents().into_bytes(),
+        stderr: stderr_tail.contents().into_bytes(),
+    })
 }
 
-fn execute_command(command: &str, working_dir: &std::path::Path) -> Result<()> {
-    let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        return Err(anyhow::anyhow!("Empty command"));
+/// Runs the command with a pseudo-terminal as its stdin, stdout and stderr,
+/// forwarding what it prints like `--stream`. Both streams arrive on the one
+/// terminal, so everything is reported as stdout.
+#[cfg(unix)]
+fn run_pty(
+    cmd: &mut Command,
+    tail: usize,
+    max_output: Option<usize>,
+    timeout: &Timeout,
+) -> Result<CommandOutput> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+
+    let (mut master, mut slave) = (-1, -1);
+    // SAFETY: openpty only writes the two descriptors; the name, termios and
+    // window size arguments may be null
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if opened != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to allocate a pseudo-terminal");
+    }
+    // SAFETY: openpty succeeded, so both descriptors are open and owned by us
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    // Commands spawned concurrently must not inherit the terminal, or reads
+    // would never see it close
+    for fd in [&master, &slave] {
+        // SAFETY: fcntl only updates the flags of a descriptor we own
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error())
+                .context("Failed to allocate a pseudo-terminal");
+        }
     }
 
-    let mut cmd = Command::new(parts[0]);
-    if parts.len() > 1 {
-        cmd.args(&parts[1..]);
+    cmd.stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    // SAFETY: setsid and ioctl are async-signal-safe
+    unsafe {
+        cmd.pre_exec(|| {
+            // A session of its own makes the terminal the command's controlling one
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
     }
+    let spawned = cmd.spawn();
+    // Close our copies of the terminal so reads end once the command exits
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let mut child = spawned.map_err(|e| spawn_error(e, &cmd.get_program().to_string_lossy()))?;
+
+    let json = JSON_OUTPUT.load(Ordering::Relaxed);
+    let terminal = PtyReader(fs::File::from(master));
+    // Raw chunks keep `\r` progress bars and prompts without a newline intact
+    let output_handle = std::thread::spawn(move || {
+        forward_chunks(terminal, tail, max_output, |chunk| {
+            if json {
+                let mut stderr = std::io::stderr().lock();
+                stderr.write_all(chunk)?;
+                stderr.flush()
+            } else {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(chunk)?;
+                stdout.flush()
+            }
+        })
+    });
+
+    let status = wait_with_timeout(&mut child, timeout)?;
 
-    cmd.current_dir(working_dir);
+    let output_tail = output_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to read command output"))??;
 
-    let output = cmd.output().context("Failed to execute command")?;
+    Ok(CommandOutput {
+        status,
+        stdout: output_tail.contents().into_bytes(),
+        stderr: Vec::new(),
+    })
+}
+
+#[cfg(not(unix))]
+fn run_pty(
+    cmd: &mut Command,
+    tail: usize,
+    max_output: Option<usize>,
+    timeout: &Timeout,
+) -> Result<CommandOutput> {
+    run_streaming(cmd, tail, max_output, timeout)
+}
+
+/// Reads the master side of a pseudo-terminal. Linux reports EIO rather than
+/// end of file once every process has closed the terminal.
+#[cfg(unix)]
+struct PtyReader(fs::File);
 
-    if !output.stdout.is_empty() {
-        println!("📄 Output:");
-        println!("{}", String::from_utf8_lossy(&output.stdout));
+#[cfg(unix)]
+impl std::io::Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
     }
+}
 
-    if !output.stderr.is_empty() {
-        eprintln!("⚠️  Error output:");
-        eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+fn forward_lines(
+    stream: impl std::io::Read,
+    tail: usize,
+    max_output: Option<usize>,
+    print: impl Fn(&str),
+) -> std::io::Result<OutputTail> {
+    let mut reader = std::io::BufReader::new(stream);
+    let mut lines = OutputTail::new(tail);
+    let mut buffer = Vec::new();
+    let mut remaining = max_output.unwrap_or(usize::MAX);
+    let mut truncated = false;
+
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer)? == 0 {
+            break;
+        }
+
+        // Past the limit, keep draining the pipe so the command can continue
+        if truncated {
+            continue;
+        }
+
+        let kept = buffer.len().min(remaining);
+        remaining -= kept;
+
+        if kept > 0 {
+            let line = String::from_utf8_lossy(&buffer[..kept]);
+            let line = line.trim_end_matches(['\n', '\r']);
+            print(line);
+            lines.push(line.to_string());
+        }
+
+        if kept < buffer.len() {
+            truncated = true;
+            let marker = truncation_marker(max_output.unwrap_or_default());
+            print(&marker);
+            lines.push(marker);
+        }
     }
 
-    if !output.status.success() {
-        return Err(anyhow::anyhow!(
-            "Command failed with status: {}",
-            output.status
-        ));
+    Ok(lines)
+}
+
+/// Like [`forward_lines`], but passes the stream on in the chunks it arrives
+/// in rather than a line at a time. The tail still collects whole lines.
+#[cfg(unix)]
+fn forward_chunks(
+    mut stream: impl std::io::Read,
+    tail: usize,
+    max_output: Option<usize>,
+    mut write: impl FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<OutputTail> {
+    let mut lines = OutputTail::new(tail);
+    let mut chunk = [0u8; 8192];
+    let mut line = Vec::new();
+    let mut remaining = max_output.unwrap_or(usize::MAX);
+    let mut truncated = false;
+    let push_line = |lines: &mut OutputTail, line: &mut Vec<u8>| {
+        let text = String::from_utf8_lossy(line);
+        lines.push(text.trim_end_matches(['\n', '\r']).to_string());
+        line.clear();
+    };
+
+    loop {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+
+        // Past the limit, keep draining the terminal so the command can continue
+        if truncated {
+            continue;
+        }
+
+        let kept = read.min(remaining);
+        remaining -= kept;
+        write(&chunk[..kept])?;
+        for &byte in &chunk[..kept] {
+            line.push(byte);
+            if byte == b'\n' {
+                push_line(&mut lines, &mut line);
+            }
+        }
+
+        if kept < read {
+            truncated = true;
+            if !line.is_empty() {
+                write(b"\n")?;
+                push_line(&mut lines, &mut line);
+            }
+            let marker = truncation_marker(max_output.unwrap_or_default());
+            write(format!("{marker}\n").as_bytes())?;
+            lines.push(marker);
+        }
     }
 
-    Ok(())
+    if !line.is_empty() {
+        push_line(&mut lines, &mut line);
+    }
+    Ok(lines)
 }
 
 #[cfg(unix)]
@@ -291,12 +4762,12 @@ async fn setup_signal_handler() -> Result<()> {
 
     tokio::select! {
         _ = sigint.recv() => {
-            println!("\n🛑 Received SIGINT (Ctrl+C)");
+            say!("\n🛑 Received SIGINT (Ctrl+C)");
             perform_cleanup();
             std::process::exit(130);
         }
         _ = sigterm.recv() => {
-            println!("\n🛑 Received SIGTERM");
+            say!("\n🛑 Received SIGTERM");
             perform_cleanup();
             std::process::exit(143);
         }
@@ -306,63 +4777,206 @@ async fn setup_signal_handler() -> Result<()> {
 #[cfg(windows)]
 async fn setup_signal_handler() -> Result<()> {
     tokio::signal::ctrl_c().await?;
-    println!("\n🛑 Received Ctrl+C");
+    say!("\n🛑 Received Ctrl+C");
     perform_cleanup();
     std::process::exit(130);
 }
 
 fn perform_cleanup() {
     if let Ok(mut cleanup_state) = CLEANUP_STATE.lock() {
-        if let Some(temp_path) = cleanup_state.take() {
-            if let Err(e) = fs::remove_dir_all(&temp_path) {
-                eprintln!("⚠️  Error during signal cleanup: {e}");
+        for (temp_path, strategy) in cleanup_state.drain(..) {
+            if let Err(e) = remove_temp_dir(&temp_path, strategy) {
+                say_err!("⚠️  Error during signal cleanup: {e}");
             } else {
-                println!("🧹 Temporary directory cleaned up: {}", temp_path.display());
+                status!("🧹 Temporary directory cleaned up: {}", temp_path.display());
             }
         }
     }
 }
 
-fn cleanup_temp_directory(temp_path: &PathBuf, should_keep: bool) {
+/// Marks a temporary directory for removal if iz is interrupted.
+fn register_cleanup(temp_path: &std::path::Path, strategy: CleanupStrategy) {
+    CLEANUP_STATE
+        .lock()
+        .unwrap()
+        .push((temp_path.to_path_buf(), strategy));
+}
+
+/// Removes a temporary directory that is not being kept when `run_command`
+/// or `bench_command` returns early with an error, so a failed checkout or
+/// command doesn't leave an orphaned directory behind.
+struct CleanupGuard {
+    temp_path: PathBuf,
+    strategy: CleanupStrategy,
+    armed: bool,
+}
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            cleanup_temp_directory(&self.temp_path, false, self.strategy);
+        }
+    }
+}
+
+/// Removes (or keeps) the temporary directory. Returns `false` when removal
+/// failed and the directory was left behind.
+fn cleanup_temp_directory(
+    temp_path: &std::path::Path,
+    should_keep: bool,
+    strategy: CleanupStrategy,
+) -> bool {
     if let Ok(mut cleanup_state) = CLEANUP_STATE.lock() {
-        *cleanup_state = None;
+        cleanup_state.retain(|(path, _)| path != temp_path);
     }
 
     if should_keep {
-        println!("💾 Temporary directory preserved: {}", temp_path.display());
-    } else if let Err(e) = fs::remove_dir_all(temp_path) {
-        eprintln!("⚠️  Error cleaning temporary directory: {e}");
+        mark_run_finished(temp_path);
+        status!("💾 Temporary directory preserved: {}", temp_path.display());
+    } else if let Err(e) = remove_temp_dir(temp_path, strategy) {
+        say_err!("⚠️  Error cleaning temporary directory: {e:#}");
+        say_err!("💾 Temporary directory left at: {}", temp_path.display());
+        return false;
     } else {
-        println!("🧹 Temporary directory cleaned");
+        status!("🧹 Temporary directory cleaned");
+    }
+
+    true
+}
+
+fn remove_temp_dir(temp_path: &std::path::Path, strategy: CleanupStrategy) -> Result<()> {
+    restore_writable(temp_path)?;
+    match strategy {
+        CleanupStrategy::Remove => {
+            fs::remove_dir_all(temp_path).map_err(|e| remove_dir_error(e, temp_path))?
+        }
+        CleanupStrategy::Rename => {
+            rename_to_trash(temp_path)?;
+        }
+        CleanupStrategy::Trash => trash::delete(temp_path)
+            .with_context(|| format!("Failed to move {} to trash", temp_path.display()))?,
     }
+    Ok(())
+}
+
+fn determine_temp_dir(
+    cli_temp_dir: &Option<String>,
+    command: Option<&iz::CommandEntry>,
+    config: &iz::IzConfig,
+) -> Result<PathBuf> {
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    Ok(resolve_temp_base(
+        cli_temp_dir.as_deref(),
+        std::env::var("IZTEMP").ok(),
+        command.and_then(|command| command.temp_dir()),
+        config,
+        &current_dir,
+    ))
 }
 
-fn determine_temp_dir(cli_temp_dir: &Option<String>, config: &iz::IzConfig) -> Result<PathBuf> {
-    if let Some(temp_dir) = cli_temp_dir {
-        return Ok(PathBuf::from(temp_dir));
+fn prepare_into_dir(dir: &std::path::Path, force: bool) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+
+    let is_empty = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+        .next()
+        .is_none();
+
+    if !is_empty && !force {
+        return Err(anyhow::anyhow!(
+            "Target directory is not empty: {} (use --force to overwrite)",
+            dir.display()
+        ));
     }
 
-    if let Ok(env_temp_dir) = std::env::var("IZTEMP") {
-        return Ok(PathBuf::from(env_temp_dir));
+    Ok(dir.to_path_buf())
+}
+
+/// Lists how many existing files a checkout into `dir` would overwrite and asks
+/// before going ahead. Without a terminal to ask on, only `--yes` allows it.
+fn confirm_overwrite(commit_id: &str, dir: &std::path::Path, assume_yes: bool) -> Result<()> {
+    let repo = open_repository()?;
+    let tree = find_commit(&repo, commit_id)?
+        .tree()
+        .context("Failed to get commit tree")?;
+    let ignore = read_izignore(&std::env::current_dir()?)?;
+    let overwritten = find_checkout_conflicts(&repo, &tree, dir, &ignore)?;
+    if overwritten.is_empty() {
+        return Ok(());
     }
 
-    if let Some(config_temp_dir) = &config.temp_dir {
-        return Ok(PathBuf::from(config_temp_dir));
+    say_err!(
+        "⚠️  Checking out into {} overwrites {} existing file(s):",
+        dir.display(),
+        overwritten.len()
+    );
+    for path in overwritten.iter().take(OVERWRITE_PREVIEW_LIMIT) {
+        eprintln!("   {path}");
+    }
+    if overwritten.len() > OVERWRITE_PREVIEW_LIMIT {
+        eprintln!(
+            "   ... and {} more",
+            overwritten.len() - OVERWRITE_PREVIEW_LIMIT
+        );
+    }
+    if assume_yes {
+        return Ok(());
     }
 
-    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Refusing to overwrite {} existing file(s) in {} without confirmation (pass --yes)",
+            overwritten.len(),
+            dir.display()
+        ));
+    }
+    eprint!("{}", decorate("❓ Overwrite them? [y/N]: "));
+    std::io::stderr().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let input = input.trim().to_lowercase();
+    if input != "y" && input != "yes" {
+        return Err(anyhow::anyhow!("Checkout into {} cancelled", dir.display()));
+    }
+    Ok(())
+}
 
-    Ok(current_dir.join(".iztemp"))
+/// Creates the run's temporary directory and records `meta` in it.
+fn create_unique_temp_dir(
+    base_temp_dir: &std::path::Path,
+    run_id: Option<&str>,
+    force: bool,
+    meta: &RunMeta,
+) -> Result<PathBuf> {
+    let path = create_temp_dir_path(base_temp_dir, run_id, force)?;
+    write_run_meta(&path, meta)?;
+    Ok(path)
 }
 
-fn create_unique_temp_dir(base_temp_dir: &PathBuf) -> Result<PathBuf> {
+fn create_temp_dir_path(
+    base_temp_dir: &std::path::Path,
+    run_id: Option<&str>,
+    force: bool,
+) -> Result<PathBuf> {
     if !base_temp_dir.exists() {
-        fs::create_dir_all(base_temp_dir).with_context(|| {
-            format!(
-                "Failed to create temp directory: {}",
-                base_temp_dir.display()
-            )
-        })?;
+        fs::create_dir_all(base_temp_dir).map_err(|e| create_dir_error(e, base_temp_dir))?;
+    }
+
+    if let Some(run_id) = run_id {
+        let path = base_temp_dir.join(format!("iz-{run_id}"));
+        if path.exists() {
+            if !force {
+                return Err(anyhow::anyhow!(
+                    "Temporary directory already exists: {} (use --force to replace it)",
+                    path.display()
+                ));
+            }
+            restore_writable(&path)?;
+            fs::remove_dir_all(&path).map_err(|e| remove_dir_error(e, &path))?;
+        }
+        fs::create_dir(&path).map_err(|e| create_dir_error(e, &path))?;
+        return Ok(path);
     }
 
     let timestamp = std::time::SystemTime::now()
@@ -370,17 +4984,8 @@ fn create_unique_temp_dir(base_temp_dir: &PathBuf) -> Result<PathBuf> {
         .unwrap()
         .as_millis();
 
-    let random_id: u32 = rand::random();
-    let unique_name = format!("iz-{timestamp}-{random_id:x}");
-
-    let temp_path = base_temp_dir.join(unique_name);
-
-    fs::create_dir_all(&temp_path).with_context(|| {
-        format!(
-            "Failed to create temporary directory: {}",
-            temp_path.display()
-        )
-    })?;
-
-    Ok(temp_path)
+    create_dir_with_retries(base_temp_dir, TEMP_DIR_ATTEMPTS, || {
+        let random_id: u32 = rand::random();
+        format!("iz-{timestamp}-{random_id:x}")
+    })
 }
@@ -0,0 +1,49 @@
+//! Notifications for a finished run: a JSON POST to `--notify <url>`, which
+//! needs the `notify` feature, and a local `--notify-command`.
+
+use crate::RunReportEntry;
+use anyhow::Result;
+
+/// How long `--notify` waits for the server before giving up.
+pub const NOTIFY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Whether this build can POST to `--notify` URLs.
+pub const NOTIFY_URL_SUPPORTED: bool = cfg!(feature = "notify");
+
+/// Environment variables describing the run, set for `--notify-command`. The
+/// exit code is empty when the command never ran or was killed by a signal.
+pub fn notify_env(run: &RunReportEntry) -> Vec<(String, String)> {
+    [
+        ("IZ_COMMIT", run.commit.clone()),
+        ("IZ_SUBJECT", run.subject.clone()),
+        ("IZ_COMMAND_NAME", run.command.clone()),
+        ("IZ_SUCCESS", run.success.to_string()),
+        (
+            "IZ_EXIT_CODE",
+            run.exit_code
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+        ),
+        ("IZ_DURATION_MS", run.duration_ms.to_string()),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value))
+    .collect()
+}
+
+/// POSTs the run to `url` as the same JSON object a run report holds.
+#[cfg(feature = "notify")]
+pub fn post_summary(url: &str, run: &RunReportEntry) -> Result<()> {
+    ureq::post(url)
+        .timeout(NOTIFY_TIMEOUT)
+        .set("Content-Type", "application/json")
+        .send_string(&serde_json::to_string(run)?)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+pub fn post_summary(_url: &str, _run: &RunReportEntry) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "iz was built without the `notify` feature, so it can't send --notify requests"
+    ))
+}
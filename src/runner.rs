@@ -0,0 +1,97 @@
+//! Command execution behind a trait, so the run flow can be driven by local
+//! processes, a container or remote runtime, or a test double.
+
+use crate::CommandOutput;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub trait CommandRunner {
+    /// Runs `argv` in `cwd` with `env` added to the inherited environment.
+    fn run(&self, argv: &[String], cwd: &Path, env: &[(String, String)]) -> Result<CommandOutput>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Pre,
+    Main,
+    Post,
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Step::Pre => "pre",
+            Step::Main => "main",
+            Step::Post => "post",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Reported by [`run_hooked`] around every command it runs.
+#[derive(Debug)]
+pub enum StepEvent<'a> {
+    Started(Step),
+    Finished(Step, &'a CommandOutput),
+}
+
+/// A command with its `pre` and `post` hooks, as argv vectors.
+pub struct HookedCommand<'a> {
+    pub pre: Option<&'a [String]>,
+    pub argv: &'a [String],
+    pub post: Option<&'a [String]>,
+    /// A failing `pre` hook skips the command (unless `ignore_hook_failures`).
+    pub hooks_fail_run: bool,
+}
+
+#[derive(Debug)]
+pub struct HookedOutcome {
+    pub pre_ok: bool,
+    /// Why the `pre` hook couldn't be started; it then counts as failed.
+    pub pre_error: Option<anyhow::Error>,
+    /// `None` when a failing `pre` hook skipped the command. An error running
+    /// the command is kept here so the `post` hook still runs.
+    pub output: Option<Result<CommandOutput>>,
+    pub post_ok: bool,
+}
+
+/// Runs the `pre` hook, the command and the `post` hook. The `post` hook
+/// always runs, even when the `pre` hook couldn't be started; failing to
+/// start the `post` hook is an error.
+pub fn run_hooked(
+    runner: &dyn CommandRunner,
+    command: &HookedCommand,
+    cwd: &Path,
+    env: &[(String, String)],
+    mut observe: impl FnMut(StepEvent),
+) -> Result<HookedOutcome> {
+    let mut run_step = |step: Step, argv: &[String]| -> Result<CommandOutput> {
+        observe(StepEvent::Started(step));
+        let output = runner.run(argv, cwd, env)?;
+        observe(StepEvent::Finished(step, &output));
+        Ok(output)
+    };
+
+    let (pre_ok, pre_error) = match command.pre.map(|argv| run_step(Step::Pre, argv)) {
+        Some(Ok(output)) => (output.status.success(), None),
+        Some(Err(error)) => (false, Some(error.context("Failed to execute pre hook"))),
+        None => (true, None),
+    };
+
+    let output = (pre_ok || !command.hooks_fail_run).then(|| run_step(Step::Main, command.argv));
+
+    let post_ok = match command.post {
+        Some(argv) => run_step(Step::Post, argv)
+            .context("Failed to execute post hook")?
+            .status
+            .success(),
+        None => true,
+    };
+
+    Ok(HookedOutcome {
+        pre_ok,
+        pre_error,
+        output,
+        post_ok,
+    })
+}
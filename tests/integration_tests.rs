@@ -1,56 +1,93 @@
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Output};
 
-fn create_test_git_repo_with_config(commands: &[(&str, &str)]) -> PathBuf {
-    let temp_dir = env::temp_dir().join(format!("iz-integration-test-{}", rand::random::<u32>()));
-    fs::create_dir_all(&temp_dir).unwrap();
-    let repo_path = &temp_dir;
-
-    Command::new("git")
-        .args(["init"])
-        .current_dir(repo_path)
-        .output()
-        .expect("Git init failed");
+/// A throwaway git repository with an izconfig.json, removed again on drop.
+struct TestRepo {
+    path: PathBuf,
+}
 
-    Command::new("git")
-        .args(["config", "user.email", "test@example.com"])
-        .current_dir(repo_path)
-        .output()
-        .expect("Git config email failed");
+impl TestRepo {
+    /// Creates a repository whose first commit holds `commands` as the config
+    /// plus a `test.txt`.
+    fn new(commands: &[(&str, &str)]) -> Self {
+        let repo = Self::empty();
 
-    Command::new("git")
-        .args(["config", "user.name", "Test User"])
-        .current_dir(repo_path)
-        .output()
-        .expect("Git config name failed");
-    let mut config_content = String::from("{\n  \"commands\": {\n");
-    for (i, (key, value)) in commands.iter().enumerate() {
-        config_content.push_str(&format!("    \"{key}\": \"{value}\""));
-        if i < commands.len() - 1 {
-            config_content.push(',');
+        let mut config_content = String::from("{\n  \"commands\": {\n");
+        for (i, (key, value)) in commands.iter().enumerate() {
+            config_content.push_str(&format!("    \"{key}\": \"{value}\""));
+            if i < commands.len() - 1 {
+                config_content.push(',');
+            }
+            config_content.push('\n');
         }
-        config_content.push('\n');
+        config_content.push_str("  }\n}");
+
+        repo.write_config(&config_content);
+        fs::write(repo.path.join("test.txt"), "Test content").unwrap();
+        repo.commit("Test commit");
+        repo
     }
-    config_content.push_str("  }\n}");
 
-    fs::write(repo_path.join("izconfig.json"), config_content).unwrap();
-    fs::write(repo_path.join("test.txt"), "Test content").unwrap();
+    /// Creates an initialized repository with no commits and no config.
+    fn empty() -> Self {
+        let path = env::temp_dir().join(format!("iz-integration-test-{}", rand::random::<u32>()));
+        fs::create_dir_all(&path).unwrap();
+        let repo = Self { path };
 
-    Command::new("git")
-        .args(["add", "."])
-        .current_dir(repo_path)
-        .output()
-        .expect("Git add failed");
+        repo.git(&["init"]);
+        repo.git(&["config", "user.email", "test@example.com"]);
+        repo.git(&["config", "user.name", "Test User"]);
+        repo
+    }
 
-    Command::new("git")
-        .args(["commit", "-m", "Test commit"])
-        .current_dir(repo_path)
-        .output()
-        .expect("Git commit failed");
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_config(&self, config_content: &str) {
+        fs::write(self.path.join("izconfig.json"), config_content).unwrap();
+    }
+
+    /// Returns an iz command that runs inside the repository.
+    fn iz(&self) -> Command {
+        let mut command = Command::new(get_iz_binary_path());
+        command.current_dir(&self.path);
+        command
+    }
+
+    /// Runs iz with `args` inside the repository.
+    fn run(&self, args: &[&str]) -> Output {
+        self.iz().args(args).output().expect("Failed to run iz CLI")
+    }
 
-    temp_dir
+    /// Runs git with `args` inside the repository, failing the test if git does.
+    fn git(&self, args: &[&str]) -> Output {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.path)
+            .output()
+            .expect("Failed to run git");
+        assert!(
+            output.status.success(),
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    }
+
+    /// Stages everything in the working tree and commits it.
+    fn commit(&self, message: &str) {
+        self.git(&["add", "."]);
+        self.git(&["commit", "-m", message]);
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
 }
 
 fn get_iz_binary_path() -> String {
@@ -81,26 +118,33 @@ fn get_iz_binary_path() -> String {
     panic!("iz CLI binary not found. Run 'cargo build' first.");
 }
 
-#[test]
-fn test_iz_cli_basic_command() {
-    let temp_repo = create_test_git_repo_with_config(&[
-        ("hello", "echo 'Hello from test project!'"),
-        ("pwd", "pwd"),
-    ]);
-
-    let iz_binary = get_iz_binary_path();
-
-    let output = Command::new(&iz_binary)
-        .args(["HEAD", "hello"])
-        .current_dir(&temp_repo)
-        .output()
-        .expect("Failed to run iz CLI");
-
+/// Asserts that iz succeeded, showing its stderr when it didn't.
+fn assert_success(output: &Output) {
     assert!(
         output.status.success(),
         "iz CLI failed: {}",
         String::from_utf8_lossy(&output.stderr)
     );
+}
+
+/// Asserts that iz exited with `code`, showing its output when it didn't.
+fn assert_exit_code(output: &Output, code: i32) {
+    assert_eq!(
+        output.status.code(),
+        Some(code),
+        "unexpected exit code\nstdout: {}\nstderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_iz_cli_basic_command() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'Hello from test project!'"), ("pwd", "pwd")]);
+
+    let output = temp_repo.run(&["HEAD", "hello"]);
+
+    assert_success(&output);
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("Hello from test project!"));
@@ -109,21 +153,11 @@ fn test_iz_cli_basic_command() {
 
 #[test]
 fn test_iz_cli_with_parameters() {
-    let temp_repo = create_test_git_repo_with_config(&[("greet", "echo 'Hello #{name}!'")]);
+    let temp_repo = TestRepo::new(&[("greet", "echo 'Hello #{name}!'")]);
 
-    let iz_binary = get_iz_binary_path();
+    let output = temp_repo.run(&["HEAD", "greet", "--param", "name=Integration"]);
 
-    let output = Command::new(&iz_binary)
-        .args(["HEAD", "greet", "--param", "name=Integration"])
-        .current_dir(&temp_repo)
-        .output()
-        .expect("Failed to run iz CLI");
-
-    assert!(
-        output.status.success(),
-        "iz CLI failed: {}",
-        String::from_utf8_lossy(&output.stderr)
-    );
+    assert_success(&output);
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("Hello Integration!"));
@@ -131,23 +165,9 @@ fn test_iz_cli_with_parameters() {
 
 #[test]
 fn test_iz_cli_missing_config() {
-    let temp_dir =
-        env::temp_dir().join(format!("iz-test-missing-config-{}", rand::random::<u32>()));
-    fs::create_dir_all(&temp_dir).unwrap();
+    let temp_repo = TestRepo::empty();
 
-    Command::new("git")
-        .args(["init"])
-        .current_dir(&temp_dir)
-        .output()
-        .expect("Git init failed");
-
-    let iz_binary = get_iz_binary_path();
-
-    let output = Command::new(&iz_binary)
-        .args(["HEAD", "test"])
-        .current_dir(&temp_dir)
-        .output()
-        .expect("Failed to run iz CLI");
+    let output = temp_repo.run(&["HEAD", "test"]);
 
     assert!(!output.status.success(), "iz CLI should have failed");
 
@@ -157,15 +177,9 @@ fn test_iz_cli_missing_config() {
 
 #[test]
 fn test_iz_cli_missing_command() {
-    let temp_repo = create_test_git_repo_with_config(&[("run", "echo 'run command'")]);
-
-    let iz_binary = get_iz_binary_path();
+    let temp_repo = TestRepo::new(&[("run", "echo 'run command'")]);
 
-    let output = Command::new(&iz_binary)
-        .args(["HEAD", "nonexistent"])
-        .current_dir(&temp_repo)
-        .output()
-        .expect("Failed to run iz CLI");
+    let output = temp_repo.run(&["HEAD", "nonexistent"]);
 
     assert!(!output.status.success(), "iz CLI should have failed");
 
@@ -175,9 +189,7 @@ fn test_iz_cli_missing_command() {
 
 #[test]
 fn test_iz_cli_help() {
-    let iz_binary = get_iz_binary_path();
-
-    let output = Command::new(&iz_binary)
+    let output = Command::new(get_iz_binary_path())
         .args(["--help"])
         .output()
         .expect("Failed to run iz CLI help");
@@ -191,15 +203,9 @@ fn test_iz_cli_help() {
 
 #[test]
 fn test_iz_cli_missing_parameter() {
-    let temp_repo = create_test_git_repo_with_config(&[("greet", "echo 'Hello #{name}!'")]);
-
-    let iz_binary = get_iz_binary_path();
+    let temp_repo = TestRepo::new(&[("greet", "echo 'Hello #{name}!'")]);
 
-    let output = Command::new(&iz_binary)
-        .args(["HEAD", "greet"])
-        .current_dir(&temp_repo)
-        .output()
-        .expect("Failed to run iz CLI");
+    let output = temp_repo.run(&["HEAD", "greet"]);
 
     assert!(!output.status.success(), "iz CLI should have failed");
 
@@ -209,22 +215,17 @@ fn test_iz_cli_missing_parameter() {
 
 #[test]
 fn test_iz_cli_clean_force() {
-    let temp_repo = create_test_git_repo_with_config(&[("test", "echo 'test'")]);
-    let iz_binary = get_iz_binary_path();
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
 
     // Create fake temp directories
-    let temp_base = temp_repo.join(".iztemp");
+    let temp_base = temp_repo.path().join(".iztemp");
     fs::create_dir_all(&temp_base).unwrap();
     fs::create_dir_all(temp_base.join("iz-test1")).unwrap();
     fs::create_dir_all(temp_base.join("iz-test2")).unwrap();
     fs::create_dir_all(temp_base.join("other-folder")).unwrap();
 
     // Run cleanup with force
-    let output = Command::new(&iz_binary)
-        .args(["clean", "--force"])
-        .current_dir(&temp_repo)
-        .output()
-        .expect("Failed to run iz clean");
+    let output = temp_repo.run(&["clean", "--force"]);
 
     assert!(
         output.status.success(),
@@ -245,18 +246,13 @@ fn test_iz_cli_clean_force() {
 
 #[test]
 fn test_iz_cli_clean_no_directories() {
-    let temp_repo = create_test_git_repo_with_config(&[("test", "echo 'test'")]);
-    let iz_binary = get_iz_binary_path();
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
 
     // Create empty temp directory
-    let temp_base = temp_repo.join(".iztemp");
+    let temp_base = temp_repo.path().join(".iztemp");
     fs::create_dir_all(&temp_base).unwrap();
 
-    let output = Command::new(&iz_binary)
-        .args(["clean", "--force"])
-        .current_dir(&temp_repo)
-        .output()
-        .expect("Failed to run iz clean");
+    let output = temp_repo.run(&["clean", "--force"]);
 
     assert!(
         output.status.success(),
@@ -270,20 +266,15 @@ fn test_iz_cli_clean_no_directories() {
 
 #[test]
 fn test_iz_cli_clean_custom_temp_dir() {
-    let temp_repo = create_test_git_repo_with_config(&[("test", "echo 'test'")]);
-    let iz_binary = get_iz_binary_path();
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
 
     // Create custom temp directory with iz- directories
-    let custom_temp = temp_repo.join("custom-temp");
+    let custom_temp = temp_repo.path().join("custom-temp");
     fs::create_dir_all(&custom_temp).unwrap();
     fs::create_dir_all(custom_temp.join("iz-custom1")).unwrap();
     fs::create_dir_all(custom_temp.join("iz-custom2")).unwrap();
 
-    let output = Command::new(&iz_binary)
-        .args(["clean", "--force", "--temp-dir", "custom-temp"])
-        .current_dir(&temp_repo)
-        .output()
-        .expect("Failed to run iz clean");
+    let output = temp_repo.run(&["clean", "--force", "--temp-dir", "custom-temp"]);
 
     assert!(
         output.status.success(),
@@ -298,3 +289,3339 @@ fn test_iz_cli_clean_custom_temp_dir() {
     assert!(!custom_temp.join("iz-custom1").exists());
     assert!(!custom_temp.join("iz-custom2").exists());
 }
+
+#[test]
+fn test_iz_cli_stream_output() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'Hello from stream!'")]);
+
+    let output = temp_repo.run(&["HEAD", "hello", "--stream", "--tail", "5"]);
+
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Hello from stream!"));
+    assert!(stdout.contains("✅ Operation completed!"));
+}
+
+#[test]
+fn test_iz_cli_declared_params_missing() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "greet": { "template": "echo 'Hello #{name}!'", "params": ["name", "greeting"] }
+            }
+        }"#,
+    );
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "greet",
+        "--param",
+        "name=Ali",
+        "--param",
+        "greting=Hi",
+    ]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Missing declared parameters: greeting"));
+}
+
+#[test]
+fn test_iz_cli_checkout_only() {
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
+
+    let output = temp_repo.run(&["HEAD", "--checkout-only"]);
+
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let checkout_path = PathBuf::from(stdout.lines().last().unwrap().trim());
+    assert!(checkout_path.is_dir());
+    assert_eq!(
+        fs::read_to_string(checkout_path.join("test.txt")).unwrap(),
+        "Test content"
+    );
+}
+
+#[test]
+fn test_iz_cli_disallowed_command() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "hello": "echo 'hello'",
+                "list": "ls"
+            },
+            "allowed_commands": ["echo"]
+        }"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "hello"]);
+    assert_success(&output);
+
+    let output = temp_repo.run(&["HEAD", "list"]);
+    assert!(!output.status.success(), "iz CLI should have failed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Command 'ls' is not in allowed_commands"));
+}
+
+#[test]
+fn test_iz_cli_commit_header() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+
+    let output = temp_repo.run(&["HEAD", "hello"]);
+
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("💬 Subject: Test commit"));
+    assert!(stdout.contains("👤 Author: Test User"));
+}
+
+#[test]
+fn test_iz_cli_json_output() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+
+    let output = temp_repo.run(&["HEAD", "hello", "--output", "json"]);
+
+    assert_success(&output);
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["commit_summary"], "Test commit");
+    assert_eq!(result["commit_author"], "Test User");
+    assert_eq!(result["command"], "hello");
+    assert_eq!(result["exit_code"], 0);
+    assert!(result["stdout"].as_str().unwrap().contains("hello"));
+}
+
+#[test]
+fn test_iz_cli_post_hook_runs_after_failure() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "fail": { "template": "false", "post": "echo post-hook-ran" }
+            }
+        }"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "fail"]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("post-hook-ran"));
+}
+
+#[test]
+fn test_iz_cli_failing_pre_hook() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "strict": { "template": "echo main-ran", "pre": "false" },
+                "lenient": {
+                    "template": "echo main-ran",
+                    "pre": "false",
+                    "ignore_hook_failures": true
+                }
+            }
+        }"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "strict"]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Executing command"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pre hook failed"));
+
+    let output = temp_repo.run(&["HEAD", "lenient"]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("main-ran"));
+}
+
+#[test]
+fn test_iz_cli_json_error_phase() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+
+    let output = temp_repo.run(&["does-not-exist", "hello", "--output", "json"]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["phase"], "checkout");
+    assert!(error["error"]
+        .as_str()
+        .unwrap()
+        .contains("Commit not found"));
+
+    let output = temp_repo.run(&["HEAD", "missing", "--output", "json"]);
+
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["phase"], "config");
+}
+
+#[test]
+fn test_iz_cli_precreate_dirs_deep_tree() {
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
+
+    // Synthetic deep tree: 5 top-level directories, each 20 levels deep
+    for branch in 0..5 {
+        let mut dir = temp_repo.path().join(format!("branch{branch}"));
+        for depth in 0..20 {
+            dir = dir.join(format!("level{depth}"));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("file.txt"), format!("{branch}-{depth}")).unwrap();
+        }
+    }
+    temp_repo.commit("Deep tree");
+    let deepest = (0..20).fold(PathBuf::from("branch4"), |dir, depth| {
+        dir.join(format!("level{depth}"))
+    });
+
+    for precreate in [true, false] {
+        temp_repo.write_config(&format!(
+            r#"{{ "commands": {{ "test": "echo 'test'" }}, "precreate_dirs": {precreate} }}"#
+        ));
+
+        let start = std::time::Instant::now();
+        let output = temp_repo.run(&["HEAD", "--checkout-only"]);
+        println!("precreate_dirs={precreate}: {:?}", start.elapsed());
+
+        assert_success(&output);
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let checkout_path = PathBuf::from(stdout.lines().last().unwrap().trim());
+        assert_eq!(
+            fs::read_to_string(checkout_path.join(&deepest).join("file.txt")).unwrap(),
+            "4-19"
+        );
+    }
+}
+
+#[test]
+fn test_iz_cli_missing_command_non_interactive() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+
+    let output = temp_repo
+        .iz()
+        .args(["HEAD"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to run iz CLI");
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Command is required"));
+}
+
+#[test]
+fn test_iz_cli_into_empty_dir() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+    let target = temp_repo.path().join("into-target");
+
+    let output = temp_repo.run(&["HEAD", "hello", "--into", "into-target"]);
+
+    assert_success(&output);
+
+    // Kept by default since the user chose the path
+    assert_eq!(
+        fs::read_to_string(target.join("test.txt")).unwrap(),
+        "Test content"
+    );
+}
+
+#[test]
+fn test_iz_cli_into_non_empty_dir() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+    let target = temp_repo.path().join("into-target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("existing.txt"), "existing").unwrap();
+
+    let output = temp_repo.run(&["HEAD", "hello", "--into", "into-target"]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Target directory is not empty"));
+    assert!(!target.join("test.txt").exists());
+
+    let output = temp_repo.run(&["HEAD", "hello", "--into", "into-target", "--force"]);
+
+    assert_success(&output);
+    assert!(target.join("test.txt").exists());
+    assert!(target.join("existing.txt").exists());
+}
+
+#[test]
+fn test_iz_cli_line_endings() {
+    let temp_repo = TestRepo::new(&[]);
+
+    fs::write(
+        temp_repo.path().join(".gitattributes"),
+        "*.lf text eol=lf\n*.crlf text eol=crlf\n",
+    )
+    .unwrap();
+    fs::write(temp_repo.path().join("unix.lf"), "one\r\ntwo\r\n").unwrap();
+    fs::write(temp_repo.path().join("dos.crlf"), "one\ntwo\n").unwrap();
+    temp_repo.commit("Add line ending attributes");
+
+    let checkout = |extra: &[&str]| {
+        let output = temp_repo
+            .iz()
+            .args(["HEAD", "--checkout-only"])
+            .args(extra)
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        PathBuf::from(stdout.lines().last().unwrap().trim())
+    };
+
+    // By default the attributes decide
+    let path = checkout(&[]);
+    assert_eq!(fs::read(path.join("unix.lf")).unwrap(), b"one\ntwo\n");
+    assert_eq!(fs::read(path.join("dos.crlf")).unwrap(), b"one\r\ntwo\r\n");
+
+    let path = checkout(&["--line-endings", "crlf"]);
+    assert_eq!(fs::read(path.join("unix.lf")).unwrap(), b"one\r\ntwo\r\n");
+    assert_eq!(fs::read(path.join("test.txt")).unwrap(), b"Test content");
+
+    let path = checkout(&["--line-endings", "lf"]);
+    assert_eq!(fs::read(path.join("dos.crlf")).unwrap(), b"one\ntwo\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_readonly() {
+    let temp_repo = TestRepo::new(&[("perms", "ls -l test.txt")]);
+
+    let output = temp_repo.run(&["HEAD", "perms", "--readonly"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_success(&output);
+    assert!(stdout.contains("-r--r--r--"), "stdout: {stdout}");
+
+    // Write permission is restored so the usual cleanup succeeds
+    let leftovers = fs::read_dir(temp_repo.path().join(".iztemp"))
+        .map(|entries| entries.count())
+        .unwrap_or(0);
+    assert_eq!(leftovers, 0);
+
+    let output = temp_repo.run(&["HEAD", "--checkout-only", "--readonly"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let path = PathBuf::from(stdout.lines().last().unwrap().trim());
+    assert!(fs::metadata(path.join("test.txt"))
+        .unwrap()
+        .permissions()
+        .readonly());
+
+    let output = temp_repo.run(&["clean", "--force"]);
+    assert!(
+        output.status.success(),
+        "iz clean failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!path.exists());
+
+    // A committed iz-meta.json doesn't replace the run's own metadata
+    fs::write(temp_repo.path().join("iz-meta.json"), "{}").unwrap();
+    for args in [&["add", "iz-meta.json"][..], &["commit", "-m", "Add meta"]] {
+        temp_repo.git(args);
+    }
+    let output = temp_repo.run(&["HEAD", "--checkout-only", "--readonly"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("reserved for run metadata"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let path = PathBuf::from(stdout.lines().last().unwrap().trim());
+    let meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(path.join("iz-meta.json")).unwrap()).unwrap();
+    assert_eq!(meta["readonly"], true);
+}
+
+#[test]
+fn test_iz_cli_checkout_strategy_conflict() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+    let target = temp_repo.path().join("into-target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(target.join("test.txt"), "local edits").unwrap();
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "hello",
+        "--into",
+        "into-target",
+        "--force",
+        "--checkout-strategy",
+        "safe",
+    ]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Checkout would overwrite"), "{stderr}");
+    assert!(stderr.contains("test.txt"), "{stderr}");
+    assert_eq!(
+        fs::read_to_string(target.join("test.txt")).unwrap(),
+        "local edits"
+    );
+
+    // Overwriting needs confirmation, which a non-interactive run can't give
+    let force_checkout = |extra: &[&str]| {
+        temp_repo
+            .iz()
+            .args([
+                "HEAD",
+                "hello",
+                "--into",
+                "into-target",
+                "--force",
+                "--checkout-strategy",
+                "force",
+            ])
+            .args(extra)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .expect("Failed to run iz CLI")
+    };
+    let output = force_checkout(&[]);
+    assert!(!output.status.success(), "iz CLI should have refused");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("overwrites 1 existing file(s)"), "{stderr}");
+    assert!(
+        stderr.contains("Refusing to overwrite 1 existing file(s)"),
+        "{stderr}"
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("test.txt")).unwrap(),
+        "local edits"
+    );
+
+    let output = force_checkout(&["--yes"]);
+    assert_success(&output);
+    assert_eq!(
+        fs::read_to_string(target.join("test.txt")).unwrap(),
+        "Test content"
+    );
+}
+
+#[test]
+fn test_iz_cli_clean_dry_run() {
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
+
+    let temp_base = temp_repo.path().join(".iztemp");
+    fs::create_dir_all(temp_base.join("iz-dry1")).unwrap();
+    fs::create_dir_all(temp_base.join("iz-dry2")).unwrap();
+
+    let output = temp_repo.run(&["clean", "--dry-run"]);
+
+    assert!(
+        output.status.success(),
+        "iz clean failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Would remove"));
+    assert!(stdout.contains("2 directories would be removed"));
+
+    // Nothing was deleted
+    assert!(temp_base.join("iz-dry1").exists());
+    assert!(temp_base.join("iz-dry2").exists());
+}
+
+#[test]
+fn test_iz_cli_binary_output() {
+    let temp_repo = TestRepo::new(&[("bytes", r"printf \\377\\376binary")]);
+
+    let output = temp_repo.run(&["HEAD", "bytes", "--binary-output"]);
+
+    assert_success(&output);
+
+    // Status lines go to stderr so stdout can be redirected to a file
+    assert_eq!(output.stdout, b"\xff\xfebinary");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Executing command"));
+
+    let output = temp_repo.run(&["HEAD", "bytes", "--binary-output", "--output", "json"]);
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["stdout_encoding"], "base64");
+    assert_eq!(result["stdout"], "//5iaW5hcnk=");
+}
+
+#[test]
+fn test_iz_cli_commit_file() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello from file'")]);
+    fs::write(temp_repo.path().join("commit.txt"), "HEAD\n").unwrap();
+
+    let output = temp_repo.run(&["--commit-file", "commit.txt", "hello"]);
+
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("hello from file"));
+    assert!(stdout.contains("🎯 Commit: HEAD"));
+}
+
+#[test]
+fn test_iz_cli_commit_file_conflict() {
+    let temp_repo = TestRepo::new(&[("hello", "echo 'hello'")]);
+    fs::write(temp_repo.path().join("commit.txt"), "HEAD").unwrap();
+
+    let output = temp_repo.run(&["HEAD", "hello", "--commit-file", "commit.txt"]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not both"));
+}
+
+#[test]
+fn test_iz_cli_report_on_failure() {
+    let temp_repo = TestRepo::new(&[("fail", "false"), ("hello", "echo 'hello'")]);
+
+    let output = temp_repo.run(&["HEAD", "fail", "--report", "report.json"]);
+
+    assert!(!output.status.success(), "iz CLI should have failed");
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(temp_repo.path().join("report.json")).unwrap())
+            .unwrap();
+    let run = &report["runs"][0];
+    assert_eq!(run["subject"], "Test commit");
+    assert_eq!(run["command"], "fail");
+    assert_eq!(run["success"], false);
+    assert_eq!(run["exit_code"], 1);
+    assert_eq!(run["commit"].as_str().unwrap().len(), 40);
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "hello",
+        "--report",
+        "report.md",
+        "--report-format",
+        "md",
+    ]);
+
+    assert!(output.status.success());
+    let markdown = fs::read_to_string(temp_repo.path().join("report.md")).unwrap();
+    assert!(markdown.contains("| Test commit | hello | ✅ passed | 0 |"));
+}
+
+#[cfg(unix)]
+fn create_test_git_repo_with_symlink() -> TestRepo {
+    let temp_repo = TestRepo::new(&[]);
+    std::os::unix::fs::symlink("test.txt", temp_repo.path().join("link.txt")).unwrap();
+
+    temp_repo.commit("Add symlink");
+
+    temp_repo
+}
+
+#[cfg(unix)]
+fn checkout_with_symlink_mode(temp_repo: &TestRepo, mode: &str) -> (PathBuf, String) {
+    let output = temp_repo.run(&["HEAD", "--checkout-only", "--symlink-mode", mode]);
+
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let checkout_path = PathBuf::from(stdout.lines().last().unwrap().trim());
+    (
+        checkout_path,
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_symlink_modes() {
+    let temp_repo = create_test_git_repo_with_symlink();
+
+    let (path, _) = checkout_with_symlink_mode(&temp_repo, "preserve");
+    let link = path.join("link.txt");
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_link(&link).unwrap(), PathBuf::from("test.txt"));
+
+    let (path, _) = checkout_with_symlink_mode(&temp_repo, "copy");
+    let link = path.join("link.txt");
+    assert!(fs::symlink_metadata(&link).unwrap().is_file());
+    assert_eq!(fs::read_to_string(&link).unwrap(), "Test content");
+
+    let (path, stderr) = checkout_with_symlink_mode(&temp_repo, "skip");
+    assert!(fs::symlink_metadata(path.join("link.txt")).is_err());
+    assert!(path.join("test.txt").is_file());
+    assert!(stderr.contains("Skipped 1 symlinks: link.txt"));
+}
+
+#[cfg(unix)]
+fn commit_symlinks(temp_repo: &TestRepo, links: &[(&str, &Path)]) {
+    for (link, target) in links {
+        std::os::unix::fs::symlink(target, temp_repo.path().join(link)).unwrap();
+    }
+    temp_repo.commit("Add symlinks");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_symlink_copy_skips_loops() {
+    let temp_repo = TestRepo::new(&[]);
+    fs::create_dir_all(temp_repo.path().join("sub")).unwrap();
+    fs::write(temp_repo.path().join("sub").join("file.txt"), "in sub").unwrap();
+    commit_symlinks(
+        &temp_repo,
+        &[
+            ("loop", Path::new(".")),
+            ("sub/parent", Path::new("..")),
+            ("inner", Path::new("sub")),
+        ],
+    );
+
+    let (path, stderr) = checkout_with_symlink_mode(&temp_repo, "copy");
+    assert!(fs::symlink_metadata(path.join("loop")).is_err());
+    assert!(fs::symlink_metadata(path.join("sub").join("parent")).is_err());
+    assert!(
+        stderr.contains("Symlink loop points at a directory containing it"),
+        "{stderr}"
+    );
+    // The copied directory keeps its files but not the link back up
+    assert_eq!(
+        fs::read_to_string(path.join("inner").join("file.txt")).unwrap(),
+        "in sub"
+    );
+    assert!(!path.join("inner").join("parent").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_symlink_copy_stays_in_checkout() {
+    let temp_repo = TestRepo::new(&[]);
+    let outside = env::temp_dir().join(format!("iz-outside-{}", rand::random::<u32>()));
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("secret.txt"), "secret").unwrap();
+    commit_symlinks(
+        &temp_repo,
+        &[
+            ("escape.txt", &outside.join("secret.txt")),
+            ("escape-dir", &outside),
+        ],
+    );
+
+    let (path, stderr) = checkout_with_symlink_mode(&temp_repo, "copy");
+    assert!(fs::symlink_metadata(path.join("escape.txt")).is_err());
+    assert!(fs::symlink_metadata(path.join("escape-dir")).is_err());
+    assert!(
+        stderr.contains("Symlink escape.txt points outside the checkout"),
+        "{stderr}"
+    );
+    assert!(path.join("test.txt").is_file());
+
+    let _ = fs::remove_dir_all(&outside);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_env_file_precedence() {
+    let temp_repo = TestRepo::new(&[("show", "printenv IZ_FOO IZ_BAR")]);
+
+    fs::write(
+        temp_repo.path().join(".env.test"),
+        "# test env\nIZ_FOO=from-file\n\nIZ_BAR=\"bar value\"\n",
+    )
+    .unwrap();
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "show",
+        "--env-file",
+        ".env.test",
+        "--env",
+        "IZ_FOO=from-flag",
+    ]);
+
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("from-flag\nbar value"));
+    assert!(!stdout.contains("from-file"));
+}
+
+#[test]
+fn test_iz_cli_env_file_missing() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    let output = temp_repo.run(&["HEAD", "hello", "--env-file", "missing.env"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to read env file: missing.env"));
+}
+
+#[test]
+fn test_iz_cli_clean_multiple_temp_dirs() {
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
+
+    let first = temp_repo.path().join("first-temp");
+    let second = temp_repo.path().join("second-temp");
+    fs::create_dir_all(first.join("iz-a1")).unwrap();
+    fs::create_dir_all(first.join("iz-a2")).unwrap();
+    fs::create_dir_all(second.join("iz-b1")).unwrap();
+
+    let output = temp_repo.run(&[
+        "clean",
+        "--force",
+        "--temp-dir",
+        "first-temp",
+        "--temp-dir",
+        "second-temp",
+        "--temp-dir",
+        "./first-temp",
+    ]);
+
+    assert!(
+        output.status.success(),
+        "iz clean failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("first-temp: 2 temporary directories"));
+    assert!(stdout.contains("second-temp: 1 temporary directories"));
+    assert!(stdout.contains("Successfully cleaned 3 directories"));
+
+    assert!(!first.join("iz-a1").exists());
+    assert!(!first.join("iz-a2").exists());
+    assert!(!second.join("iz-b1").exists());
+}
+
+#[test]
+fn test_iz_cli_max_output() {
+    let temp_repo = TestRepo::new(&[("loud", "seq 1 1000")]);
+
+    for extra in [None, Some("--stream")] {
+        let mut args = vec!["HEAD", "loud", "--max-output", "20"];
+        args.extend(extra);
+
+        let output = temp_repo.run(&args);
+
+        assert_success(&output);
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("9\n10\n... (output truncated at 20 bytes)"));
+        assert!(!stdout.contains("\n11\n"));
+    }
+}
+
+#[test]
+fn test_iz_cli_param_json() {
+    let temp_repo = TestRepo::new(&[("connect", "echo host=#{config.db.host}")]);
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "connect",
+        "--param-json",
+        r#"config={"db":{"host":"db.local"}}"#,
+    ]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("host=db.local"));
+}
+
+#[test]
+fn test_iz_cli_stdin_commits() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    fs::write(temp_repo.path().join("second.txt"), "second").unwrap();
+    temp_repo.commit("Second commit");
+
+    let mut child = temp_repo
+        .iz()
+        .args(["--stdin-commits", "hello", "--output", "json"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run iz CLI");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"HEAD\n\nHEAD~1\nnot-a-commit\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(
+        !output.status.success(),
+        "a bad commit should fail the batch"
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let results: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["commit_summary"], "Second commit");
+    assert_eq!(results[1]["commit_summary"], "Test commit");
+    // The commit that doesn't resolve never gets to run the command
+    assert_eq!(results[2]["summary"]["total"], 3);
+    assert_eq!(results[2]["summary"]["passed"], 2);
+    assert_eq!(results[2]["summary"]["skipped"], 1);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\"phase\":\"checkout\""));
+    assert!(stderr.contains("1 of 3 commits failed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_clean_reports_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
+
+    let temp_base = temp_repo.path().join(".iztemp");
+    let locked = temp_base.join("iz-locked");
+    fs::create_dir_all(locked.join("inner")).unwrap();
+    fs::write(locked.join("inner").join("file.txt"), "x").unwrap();
+    fs::create_dir_all(temp_base.join("iz-ok")).unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+
+    // Permissions don't restrict root, so there's nothing to test there
+    if fs::create_dir(locked.join("probe")).is_ok() {
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+
+    let output = temp_repo.run(&["clean", "--force"]);
+
+    assert!(
+        !output.status.success(),
+        "a failed removal should fail clean"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Failed to clean 1 of 2 directories"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("iz-locked"));
+    assert!(!temp_base.join("iz-ok").exists());
+
+    let output = temp_repo.run(&["clean", "--force", "--ignore-errors"]);
+
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Cleaned 0 directories, 1 failed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_system_temp() {
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
+    // std::env::temp_dir() honors TMPDIR
+    let system_tmp = temp_repo.path().join("system-tmp");
+    fs::create_dir_all(&system_tmp).unwrap();
+
+    let checkout_base = |args: &[&str], iztemp: Option<&str>| {
+        let mut command = temp_repo.iz();
+        command
+            .args(["HEAD", "--checkout-only"])
+            .args(args)
+            .env("TMPDIR", &system_tmp)
+            .env_remove("IZTEMP");
+        if let Some(iztemp) = iztemp {
+            command.env("IZTEMP", iztemp);
+        }
+        let output = command.output().expect("Failed to run iz CLI");
+        assert_success(&output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        PathBuf::from(stdout.lines().last().unwrap().trim())
+            .parent()
+            .unwrap()
+            .to_path_buf()
+    };
+
+    assert_eq!(checkout_base(&[], None), temp_repo.path().join(".iztemp"));
+    assert_eq!(
+        checkout_base(&["--system-temp"], None),
+        system_tmp.join("iz")
+    );
+
+    temp_repo.write_config(r#"{"commands": {"test": "echo test"}, "system_temp": true}"#);
+    assert_eq!(checkout_base(&[], None), system_tmp.join("iz"));
+    assert_eq!(
+        checkout_base(&[], Some("from-env")),
+        PathBuf::from("from-env")
+    );
+    assert_eq!(
+        checkout_base(&["--temp-dir", "from-flag"], Some("from-env")),
+        PathBuf::from("from-flag")
+    );
+
+    temp_repo.write_config(
+        r#"{"commands": {"test": "echo test"}, "system_temp": true, "temp_dir": "from-config"}"#,
+    );
+    assert_eq!(checkout_base(&[], None), PathBuf::from("from-config"));
+}
+
+#[test]
+fn test_iz_cli_checkout_progress_events() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    let progress_events = |args: &[&str]| -> Vec<serde_json::Value> {
+        let output = temp_repo
+            .iz()
+            .args(["HEAD", "hello"])
+            .args(args)
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter(|event| event["event"] == "checkout_progress")
+            .collect()
+    };
+
+    assert!(progress_events(&[]).is_empty());
+
+    let events = progress_events(&["--events"]);
+    assert!(!events.is_empty());
+    let last = events.last().unwrap();
+    assert_eq!(last["phase"], "files");
+    assert_eq!(last["total"], 2);
+    assert_eq!(last["completed"], 2);
+}
+
+#[test]
+fn test_iz_cli_trailing_args() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "direct": "echo ran",
+                "shelled": {"template": "echo ran", "shell": true}
+            }
+        }"#,
+    );
+
+    for command in ["direct", "shelled"] {
+        let output = temp_repo.run(&[
+            "HEAD",
+            command,
+            "--",
+            "--nocapture",
+            "two  words",
+            "#{not_a_param}",
+        ]);
+
+        assert_success(&output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("ran --nocapture two  words #{not_a_param}"),
+            "{command}: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn test_iz_cli_program_name_placeholder() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r##"{"commands": {"run": "#{runner} from-placeholder"}, "allowed_commands": ["echo"]}"##,
+    );
+
+    let run =
+        |runner: &str| temp_repo.run(&["HEAD", "run", "--param", &format!("runner={runner}")]);
+
+    let output = run("echo");
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("from-placeholder"));
+
+    // The substituted program is what allowed_commands checks
+    let output = run("printf");
+    assert!(!output.status.success(), "printf is not allowed");
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Command 'printf' is not in allowed_commands"));
+
+    let output = run("");
+    assert!(!output.status.success(), "an empty program should fail");
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("The program name '#{runner}' is empty after substitution"));
+}
+
+#[test]
+fn test_iz_cli_list_params() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r##"{"commands": {
+            "deploy": "echo #{env} #{region} #{env}",
+            "prod": {"alias": "deploy", "params": {"env": "production"}}
+        }}"##,
+    );
+
+    let list_params = |command: &str| temp_repo.run(&["--list-params", command]);
+
+    let output = list_params("prod");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("env (default: production)"), "{stdout}");
+    assert!(stdout.contains("  region\n"), "{stdout}");
+    assert_eq!(stdout.matches("env").count(), 1);
+
+    let output = list_params("missing");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Command 'missing' not found"));
+}
+
+#[test]
+fn test_iz_cli_command_alias() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "test": "echo mode=#{mode} jobs=#{jobs}",
+                "t": {"alias": "test", "params": {"mode": "fast", "jobs": "1"}},
+                "loop": {"alias": "again"},
+                "again": {"alias": "loop"}
+            }
+        }"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "t", "--param", "jobs=4"]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("mode=fast jobs=4"));
+
+    let output = temp_repo.run(&["HEAD", "loop"]);
+
+    assert!(!output.status.success(), "an alias cycle should fail");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Alias cycle: loop -> again -> loop"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_iz_cli_ram() {
+    if !Path::new("/dev/shm").is_dir() {
+        return;
+    }
+    let temp_repo = TestRepo::new(&[("test", "echo 'test'")]);
+
+    let output = temp_repo.run(&["HEAD", "--checkout-only", "--ram"]);
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let path = PathBuf::from(stdout.lines().last().unwrap().trim());
+    assert_eq!(path.parent().unwrap(), Path::new("/dev/shm/iz"));
+    assert!(path.join("test.txt").exists());
+    assert!(!temp_repo.path().join(".iztemp").exists());
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_iz_cli_chdir() {
+    let temp_repo = TestRepo::new(&[("show", "cat test.txt")]);
+    let elsewhere = env::temp_dir().join(format!("iz-chdir-{}", rand::random::<u32>()));
+    fs::create_dir_all(&elsewhere).unwrap();
+
+    let output = Command::new(get_iz_binary_path())
+        .arg("-C")
+        .arg(temp_repo.path())
+        .args(["HEAD", "show", "--keep"])
+        .current_dir(&elsewhere)
+        .output()
+        .expect("Failed to run iz CLI");
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Test content"));
+    // The relative default temp dir is created in the target repository
+    assert!(temp_repo.path().join(".iztemp").exists());
+    assert!(!elsewhere.join(".iztemp").exists());
+
+    let output = Command::new(get_iz_binary_path())
+        .args(["--chdir", "no-such-dir", "HEAD", "show"])
+        .current_dir(&elsewhere)
+        .output()
+        .expect("Failed to run iz CLI");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Failed to change directory to no-such-dir"));
+}
+
+#[test]
+fn test_iz_cli_submodule() {
+    let library = TestRepo::new(&[]);
+    fs::write(library.path().join("version.txt"), "library v2").unwrap();
+    library.commit("Library v2");
+
+    let temp_repo = TestRepo::new(&[("show", "cat version.txt")]);
+    temp_repo.git(&[
+        "-c",
+        "protocol.file.allow=always",
+        "submodule",
+        "add",
+        library.path().to_str().unwrap(),
+        "libs/core",
+    ]);
+    temp_repo.git(&["commit", "-m", "Add submodule"]);
+
+    let output = temp_repo.run(&["--submodule", "libs/core", "HEAD", "show"]);
+    assert_success(&output);
+    // version.txt only exists in the submodule's history
+    assert!(String::from_utf8_lossy(&output.stdout).contains("library v2"));
+
+    let output = temp_repo.run(&["--submodule", "libs/missing", "HEAD", "show"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Submodule 'libs/missing' not found (available: libs/core)"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_jobs_limit() {
+    let temp_repo = TestRepo::new(&[]);
+    // Every run drops a lock file while it works and logs how many it sees
+    temp_repo.write_config(r##"{"commands": {"busy": {
+            "template": "touch #{locks}/$$; ls #{locks} | wc -l >> #{log}; sleep 0.3; rm #{locks}/$$",
+            "shell": true
+        }}}"##,
+    );
+    fs::write(temp_repo.path().join("commits.txt"), "HEAD\n".repeat(6)).unwrap();
+
+    let max_concurrent = |jobs: &str| {
+        let locks = temp_repo.path().join(format!("locks-{jobs}"));
+        let log = temp_repo.path().join(format!("counts-{jobs}.log"));
+        fs::create_dir_all(&locks).unwrap();
+        let output = temp_repo
+            .iz()
+            .args(["--commits-file", "commits.txt", "busy", "--jobs", jobs])
+            .arg(format!("--param=locks={}", locks.display()))
+            .arg(format!("--param=log={}", log.display()))
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("All 6 commits passed"));
+
+        let counts: Vec<usize> = fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().parse().unwrap())
+            .collect();
+        assert_eq!(counts.len(), 6);
+        counts.into_iter().max().unwrap()
+    };
+
+    assert!(max_concurrent("2") <= 2);
+    assert_eq!(max_concurrent("1"), 1);
+}
+
+#[test]
+fn test_iz_cli_commits_file() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    fs::write(
+        temp_repo.path().join("commits.txt"),
+        "# candidates\nHEAD\n\nnot-a-commit\nHEAD # again\nmissing-ref\n",
+    )
+    .unwrap();
+
+    let output = temp_repo.run(&["--commits-file", "commits.txt", "hello"]);
+
+    assert!(!output.status.success(), "unresolvable commits should fail");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 of 4 commits in commits.txt could not be resolved"));
+    assert!(stderr.contains("line 4: not-a-commit"));
+    assert!(stderr.contains("line 6: missing-ref"));
+    // Validation happens before anything runs
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("hello"));
+
+    fs::write(
+        temp_repo.path().join("commits.txt"),
+        "HEAD\n# comment\nHEAD # again\n",
+    )
+    .unwrap();
+
+    let output = temp_repo.run(&["--commits-file", "commits.txt", "hello"]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("All 2 commits passed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_timeout_signal() {
+    let temp_repo = TestRepo::new(&[]);
+
+    fs::write(
+        temp_repo.path().join("serve.sh"),
+        "trap 'echo cleaned > \"$1\"; exit 0' TERM\nwhile true; do sleep 0.1; done\n",
+    )
+    .unwrap();
+    fs::write(
+        temp_repo.path().join("stubborn.sh"),
+        "trap '' TERM\nwhile true; do sleep 0.1; done\n",
+    )
+    .unwrap();
+    temp_repo.commit("Add servers");
+    temp_repo.write_config(
+        r#"{"commands": {"serve": "sh serve.sh #{out}", "stubborn": "sh stubborn.sh"}}"#,
+    );
+
+    let marker = temp_repo.path().join("term-marker");
+    let out = format!("out={}", marker.display());
+    let output = temp_repo.run(&["HEAD", "serve", "--param", &out, "--timeout", "1s"]);
+    assert!(!output.status.success(), "iz CLI should have timed out");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("timed out after 1s (stopped with SIGTERM)"),
+        "{stderr}"
+    );
+    assert_eq!(fs::read_to_string(&marker).unwrap().trim(), "cleaned");
+
+    let marker = temp_repo.path().join("kill-marker");
+    let out = format!("out={}", marker.display());
+    let output = temp_repo.run(&[
+        "HEAD",
+        "serve",
+        "--param",
+        &out,
+        "--timeout",
+        "1s",
+        "--timeout-signal",
+        "kill",
+    ]);
+    assert!(!output.status.success(), "iz CLI should have timed out");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("timed out after 1s (killed)"));
+    assert!(!marker.exists(), "SIGKILL should not run the trap");
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "stubborn",
+        "--timeout",
+        "500ms",
+        "--grace-period",
+        "300ms",
+    ]);
+    assert!(!output.status.success(), "iz CLI should have timed out");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("killed after ignoring SIGTERM for 300ms")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_success_codes() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{"commands": {"nochange": "grep pattern missing.txt"}, "success_codes": [0, 2]}"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "nochange", "--output", "json"]);
+
+    assert!(
+        output.status.success(),
+        "exit code 2 should be accepted: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["success"], true);
+    assert_eq!(result["exit_code"], 2);
+
+    // The flag overrides the config list
+    let output = temp_repo.run(&["HEAD", "nochange", "--success-code", "0"]);
+
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_iz_cli_validate() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    let output = temp_repo.run(&["validate"]);
+
+    assert!(
+        output.status.success(),
+        "iz validate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("izconfig.json is valid"));
+
+    fs::write(
+        temp_repo.path().join("broken.json"),
+        r#"{"commands": {"empty": "", "clean": "cargo clean"}}"#,
+    )
+    .unwrap();
+
+    let output = temp_repo.run(&["validate", "broken.json"]);
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("empty: Command template is empty"));
+    assert!(stdout.contains("clean: 'clean' is a reserved iz subcommand name"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("broken.json has 1 error(s)"));
+}
+
+#[test]
+fn test_iz_cli_annotated_tag() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    temp_repo.git(&["tag", "-a", "v1.0", "-m", "Release 1.0"]);
+    temp_repo.git(&["tag", "-a", "tree-tag", "-m", "Tagged tree", "HEAD^{tree}"]);
+
+    // Move on so the tag is not simply HEAD
+    fs::write(temp_repo.path().join("test.txt"), "Changed content").unwrap();
+    temp_repo.git(&["commit", "-am", "Change content"]);
+
+    let output = temp_repo.run(&["v1.0", "--checkout-only"]);
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let checkout_path = PathBuf::from(stdout.lines().last().unwrap().trim());
+    assert_eq!(
+        fs::read_to_string(checkout_path.join("test.txt")).unwrap(),
+        "Test content"
+    );
+
+    let output = temp_repo.run(&["tree-tag", "hello"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'tree-tag' points to a tree (via annotated tag), expected a commit"),
+        "unexpected error: {stderr}"
+    );
+}
+
+#[test]
+fn test_iz_cli_open_non_interactive() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    let output = temp_repo.run(&["HEAD", "hello", "--open"]);
+
+    assert_success(&output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--open ignored: not running in an interactive terminal"));
+}
+
+#[test]
+fn test_iz_cli_argv_recorded() {
+    let temp_repo = TestRepo::new(&[("greet", "echo  hello   #{name} --loud")]);
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "greet",
+        "--param",
+        "name=Ali",
+        "--output",
+        "json",
+        "--verbose",
+    ]);
+
+    assert_success(&output);
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        result["argv"],
+        serde_json::json!(["echo", "hello", "Ali", "--loud"])
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(r#"argv: ["echo","hello","Ali","--loud"]"#));
+}
+
+#[test]
+fn test_iz_cli_izignore() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    fs::create_dir_all(temp_repo.path().join("docs/api")).unwrap();
+    fs::write(temp_repo.path().join("docs/api/index.md"), "docs").unwrap();
+    fs::create_dir_all(temp_repo.path().join("src")).unwrap();
+    fs::write(temp_repo.path().join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(temp_repo.path().join("src/debug.log"), "log").unwrap();
+    temp_repo.commit("Add docs and src");
+
+    fs::write(temp_repo.path().join(".izignore"), "docs/\n*.log\n").unwrap();
+
+    let output = temp_repo.run(&["HEAD", "--checkout-only"]);
+
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let checkout_path = PathBuf::from(stdout.lines().last().unwrap().trim());
+    assert!(!checkout_path.join("docs").exists());
+    assert!(!checkout_path.join("src/debug.log").exists());
+    assert!(checkout_path.join("src/main.rs").is_file());
+    assert!(checkout_path.join("test.txt").is_file());
+}
+
+#[test]
+fn test_iz_cli_version_verbose() {
+    let output = Command::new(get_iz_binary_path())
+        .args(["version", "--verbose"])
+        .output()
+        .expect("Failed to run iz version");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with(&format!("iz {}\n", env!("CARGO_PKG_VERSION"))));
+    assert!(stdout.contains("libgit2: 1."));
+    assert!(stdout.contains("target: "));
+    assert!(stdout.contains("rustc: rustc "));
+}
+
+#[test]
+fn test_iz_cli_cleanup_strategy_rename() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo
+        .write_config(r#"{"commands": {"hello": "echo hello"}, "cleanup_strategy": "rename"}"#);
+
+    let output = temp_repo.run(&["HEAD", "hello"]);
+
+    assert_success(&output);
+
+    let temp_base = temp_repo.path().join(".iztemp");
+    let trashed: Vec<PathBuf> = fs::read_dir(temp_base.join(".trash"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(trashed.len(), 1);
+    assert!(trashed[0].join("test.txt").is_file());
+    let leftover = fs::read_dir(&temp_base)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with("iz-")
+        })
+        .count();
+    assert_eq!(leftover, 0);
+
+    let output = temp_repo.run(&["clean", "--force"]);
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Successfully cleaned 1 directories"));
+    assert!(!trashed[0].exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_multiline_script() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r#"{
+            "commands": {
+                "script": {"template": "echo first\nfor f in *.txt; do echo \"found $f\"; done", "shell": true},
+                "broken": {"template": "echo ok\nno-such-command-iz", "shell": true}
+            }
+        }"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "script", "--keep", "--output", "json"]);
+
+    assert_success(&output);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["stdout"], "first\nfound test.txt\n");
+    assert_eq!(result["argv"][0], "sh");
+
+    // The script file doesn't stay behind in a kept checkout
+    let leftovers = fs::read_dir(result["temp_dir"].as_str().unwrap())
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with(".iz-script-")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+
+    let output = temp_repo.run(&["HEAD", "broken"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(": 2: no-such-command-iz") || stderr.contains("line 2: no-such-command-iz"),
+        "expected a line-numbered shell error: {stderr}"
+    );
+}
+
+#[test]
+fn test_iz_cli_head_ref() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    let branch = temp_repo.git(&["branch", "--show-current"]);
+    let branch = String::from_utf8_lossy(&branch.stdout).trim().to_string();
+
+    let output = temp_repo.run(&["HEAD", "hello"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("HEAD → {branch} @ ")));
+
+    temp_repo.git(&["checkout", "--detach"]);
+
+    let output = temp_repo.run(&["HEAD", "hello"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("HEAD detached @ "));
+
+    let output = temp_repo.run(&["HEAD", "hello", "--output", "json"]);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["head_ref"], "detached");
+}
+
+#[test]
+fn test_iz_cli_param_type_invalid() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{"commands": {"serve": {"template": "echo port #{port}", "params": ["port"], "types": {"port": "int"}}}}"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "serve", "--param", "port=abc"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Parameter 'port' must be of type int, got 'abc'"));
+
+    let output = temp_repo.run(&["HEAD", "serve", "--param", "port=8080"]);
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("port 8080"));
+}
+
+#[test]
+fn test_iz_cli_with_git() {
+    let temp_repo = TestRepo::new(&[("rev", "git rev-parse HEAD")]);
+
+    fs::write(temp_repo.path().join("test.txt"), "Changed content").unwrap();
+    temp_repo.git(&["commit", "-am", "Change content"]);
+    let previous = temp_repo.git(&["rev-parse", "HEAD~1"]);
+    let previous = String::from_utf8_lossy(&previous.stdout).trim().to_string();
+
+    let output = temp_repo.run(&["HEAD~1", "rev", "--with-git", "--output", "json"]);
+
+    assert_success(&output);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["stdout"].as_str().unwrap().trim(), previous);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_iz_env_vars() {
+    let temp_repo = TestRepo::new(&[(
+        "show",
+        "printenv IZ_COMMIT IZ_COMMAND_NAME IZ_RUN_ID IZ_TEMP_DIR",
+    )]);
+
+    let head = temp_repo.git(&["rev-parse", "HEAD"]);
+    let head = String::from_utf8_lossy(&head.stdout).trim().to_string();
+
+    let output = temp_repo.run(&["HEAD", "show", "--output", "json"]);
+
+    assert_success(&output);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let temp_dir = result["temp_dir"].as_str().unwrap();
+    let run_id = Path::new(temp_dir).file_name().unwrap().to_string_lossy();
+    assert_eq!(
+        result["stdout"],
+        format!("{head}\nshow\n{run_id}\n{temp_dir}\n")
+    );
+
+    // --env wins over the built-in variables
+    let output = temp_repo.run(&["HEAD", "show", "--env", "IZ_COMMAND_NAME=custom"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\ncustom\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_temp_dir_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    let readonly = temp_repo.path().join("readonly");
+    fs::create_dir_all(&readonly).unwrap();
+    fs::set_permissions(&readonly, fs::Permissions::from_mode(0o555)).unwrap();
+
+    // Privileged users can write anyway, so there is nothing to test
+    if fs::create_dir(readonly.join("probe")).is_ok() {
+        return;
+    }
+
+    let output = temp_repo.run(&["HEAD", "hello", "--temp-dir", "readonly"]);
+
+    fs::set_permissions(&readonly, fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Permission denied creating temp directory at readonly/iz-")
+            && stderr.contains("choose a writable location with --temp-dir or IZTEMP"),
+        "unexpected error: {stderr}"
+    );
+}
+
+#[test]
+fn test_iz_cli_after_command_reported_separately() {
+    let temp_repo = TestRepo::new(&[("build", "echo built"), ("lint", "false")]);
+
+    let output = temp_repo.run(&["HEAD", "build", "--after", "lint"]);
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("built"));
+    assert!(stderr.contains("After command 'lint' failed"));
+    assert!(stdout.contains("Operation completed with warnings (after command failed)"));
+
+    let output = temp_repo.run(&["HEAD", "build", "--after", "lint", "--output", "json"]);
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["success"], true);
+    assert_eq!(result["after"]["command"], "lint");
+    assert_eq!(result["after"]["success"], false);
+    assert_eq!(result["after"]["exit_code"], 1);
+
+    let output = temp_repo.run(&["HEAD", "build", "--after", "lint", "--fail-on-after"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("after command 'lint' failed"));
+}
+
+#[test]
+fn test_iz_cli_after_command_argv() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{"commands": {"build": "echo built", "check": {"argv": ["echo", "checked #{label}"]}}}"#,
+    );
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "build",
+        "--after",
+        "check",
+        "--param",
+        "label=twice",
+        "--output",
+        "json",
+    ]);
+
+    assert_success(&output);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["after"]["success"], true);
+    assert_eq!(result["after"]["stdout"], "checked twice\n");
+}
+
+#[test]
+fn test_iz_cli_run_id_names_temp_dir() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+    let run_dir = temp_repo.path().join("runs").join("iz-ci-42");
+
+    let run = |extra: &[&str]| {
+        temp_repo
+            .iz()
+            .args([
+                "HEAD",
+                "hello",
+                "--temp-dir",
+                "runs",
+                "--run-id",
+                "ci-42",
+                "--keep",
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to run iz CLI")
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    assert!(run_dir.join("izconfig.json").is_file());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!(
+        "Temporary directory: {}",
+        Path::new("runs").join("iz-ci-42").display()
+    )));
+
+    let output = run(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Temporary directory already exists"));
+
+    let output = run(&["--force"]);
+    assert!(output.status.success());
+    assert!(run_dir.is_dir());
+
+    // clean still picks the directory up through its iz- prefix
+    let output = temp_repo.run(&["clean", "--temp-dir", "runs", "--force"]);
+    assert!(output.status.success());
+    assert!(!run_dir.exists());
+}
+
+#[test]
+fn test_iz_cli_relative_revisions() {
+    let temp_repo = TestRepo::new(&[("show", "cat test.txt")]);
+
+    for content in ["Second content", "Third content"] {
+        fs::write(temp_repo.path().join("test.txt"), content).unwrap();
+        temp_repo.git(&["commit", "-am", content]);
+    }
+
+    let show = |revision: &str| temp_repo.run(&[revision, "show"]);
+
+    for (revision, expected) in [
+        ("HEAD~2", "Test content"),
+        ("HEAD^", "Second content"),
+        ("HEAD^^", "Test content"),
+        ("HEAD~1^", "Test content"),
+    ] {
+        let output = show(revision);
+        assert!(
+            output.status.success(),
+            "{revision} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains(expected), "{revision}: {stdout}");
+    }
+
+    let output = show("HEAD~5");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Cannot resolve 'HEAD~5': not enough history ('HEAD' has only 2 ancestors along its first-parent chain)"
+        ),
+        "unexpected error: {stderr}"
+    );
+    // git's own explanation is kept as the cause
+    assert!(
+        stderr.contains("does not exist"),
+        "unexpected error: {stderr}"
+    );
+
+    let output = show("no-such-branch~1");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'no-such-branch' in 'no-such-branch~1' does not name a commit"),
+        "unexpected error: {stderr}"
+    );
+
+    let output = show("HEAD~1^{tree}");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("'HEAD~1^{tree}' points to a tree, expected a commit"),
+        "unexpected error: {stderr}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_generated_command() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_repo = TestRepo::new(&[]);
+
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "gen": {"generator": "./gen.sh", "params": ["name"]},
+                "broken": {"generator": "./missing.sh"}
+            }
+        }"#,
+    );
+    let script = temp_repo.path().join("gen.sh");
+    fs::write(
+        &script,
+        "#!/bin/sh\necho \"echo $1 for $IZ_PARAM_NAME #{name}\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = temp_repo.run(&["HEAD", "gen", "--param", "name=world"]);
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Command: echo gen for world world"));
+    assert!(stdout.contains("gen for world world\n"));
+
+    let output = temp_repo.run(&["HEAD", "broken"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to generate command 'broken'"));
+}
+
+#[test]
+fn test_iz_cli_cleanup_failure_warns() {
+    let temp_repo = TestRepo::new(&[]);
+
+    temp_repo
+        .write_config(r#"{"commands": {"hello": "echo hello"}, "cleanup_strategy": "rename"}"#);
+    // A file where the trash directory should go blocks the rename, even for root
+    let base = temp_repo.path().join("runs");
+    fs::create_dir_all(&base).unwrap();
+    fs::write(base.join(".trash"), "not a directory").unwrap();
+
+    let run = |extra: &[&str]| {
+        temp_repo
+            .iz()
+            .args(["HEAD", "hello", "--temp-dir", "runs"])
+            .args(extra)
+            .output()
+            .expect("Failed to run iz CLI")
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Error cleaning temporary directory"));
+    assert!(stderr.contains("Temporary directory left at: runs"));
+    assert!(stdout.contains("Operation completed with warnings (temporary directory left at runs"));
+    assert!(!stdout.contains("✅ Operation completed!"));
+
+    let output = run(&["--strict"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to clean up temporary directory: runs"));
+}
+
+#[test]
+fn test_iz_cli_container_runtime_missing() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    // An empty PATH hides every runtime
+    let output = temp_repo
+        .iz()
+        .args(["HEAD", "hello", "--container", "alpine:3"])
+        .env("PATH", "")
+        .output()
+        .expect("Failed to run iz CLI");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("No container runtime found for --container"),
+        "unexpected error: {stderr}"
+    );
+}
+
+#[cfg(feature = "container-tests")]
+#[test]
+fn test_iz_cli_container_run() {
+    // `info` also needs a reachable daemon, unlike `--version`
+    let runtime = ["docker", "podman"].into_iter().find(|runtime| {
+        Command::new(runtime)
+            .arg("info")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    });
+    let Some(runtime) = runtime else {
+        eprintln!("skipping: no container runtime available");
+        return;
+    };
+
+    let temp_repo = TestRepo::new(&[("show", "cat /etc/alpine-release test.txt")]);
+
+    let output = temp_repo.run(&[
+        "HEAD",
+        "show",
+        "--container",
+        "alpine:3",
+        "--runtime",
+        runtime,
+    ]);
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Test content"));
+}
+
+#[test]
+fn test_iz_cli_config_from_env_and_flag() {
+    let temp_repo = TestRepo::new(&[("hello", "echo from-repo-config")]);
+
+    let config_dir = env::temp_dir().join(format!("iz-config-dir-{}", rand::random::<u32>()));
+    fs::create_dir_all(&config_dir).unwrap();
+    let env_config = config_dir.join("env.json");
+    let flag_config = config_dir.join("flag.json");
+    fs::write(
+        &env_config,
+        r#"{"commands": {"hello": "echo from-env-config"}}"#,
+    )
+    .unwrap();
+    fs::write(
+        &flag_config,
+        r#"{"commands": {"hello": "echo from-flag-config"}}"#,
+    )
+    .unwrap();
+
+    let run = |extra: &[&str]| {
+        let output = temp_repo
+            .iz()
+            .args(["HEAD", "hello"])
+            .args(extra)
+            .env("IZ_CONFIG", &env_config)
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let stdout = run(&[]);
+    assert!(stdout.contains("from-env-config"));
+    assert!(!stdout.contains("from-repo-config"));
+
+    let stdout = run(&["--config", flag_config.to_str().unwrap()]);
+    assert!(stdout.contains("from-flag-config"));
+
+    let _ = fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn test_iz_cli_command_not_found() {
+    let temp_repo = TestRepo::new(&[("missing", "iz-no-such-binary --version")]);
+
+    let output = temp_repo.run(&["HEAD", "missing"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Command not found: iz-no-such-binary. Is it installed and on PATH?"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_iz_cli_run_spec() {
+    let temp_repo = TestRepo::new(&[("greet", "echo Hello #{name} x#{count}")]);
+
+    fs::write(
+        temp_repo.path().join("spec.json"),
+        r#"{"version": 1, "commit": "HEAD", "command": "greet", "params": {"name": "Spec", "count": 3}}"#,
+    )
+    .unwrap();
+    // Spec params override the matching flags
+    let output = temp_repo.run(&["--spec-file", "spec.json", "--param", "name=Flag"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello Spec x3"));
+
+    // A spec with only the commit leaves the positional argument as the command
+    let output = temp_repo
+        .iz()
+        .args(["--spec", r#"{"commit": "HEAD"}"#, "greet"])
+        .args(["--param", "name=Flag", "--param", "count=1"])
+        .output()
+        .expect("Failed to run iz CLI");
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello Flag x1"));
+
+    let output = temp_repo.run(&["--spec", r#"{"version": 9}"#]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unsupported run spec version 9"));
+}
+
+#[test]
+fn test_iz_cli_diff() {
+    let temp_repo = TestRepo::new(&[("show", "cat test.txt")]);
+    fs::write(temp_repo.path().join("test.txt"), "Changed content").unwrap();
+    temp_repo.git(&["commit", "-am", "Change content"]);
+
+    let output = temp_repo.run(&["diff", "HEAD~1", "HEAD", "show"]);
+    assert!(!output.status.success(), "different output should fail");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("--- a/HEAD~1\n+++ b/HEAD\n"), "{stdout}");
+    assert!(
+        stdout.contains("-Test content\n+Changed content\n"),
+        "{stdout}"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Output of 'show' differs between HEAD~1 and HEAD"));
+
+    let output = temp_repo.run(&["diff", "HEAD", "HEAD", "show"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("is identical"));
+
+    // JSON output is one document nesting both runs
+    let output = temp_repo.run(&["diff", "HEAD~1", "HEAD", "show", "--output", "json"]);
+    assert!(!output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["identical"], false);
+    assert_eq!(result["runs"][0]["stdout"], "Test content");
+    assert_eq!(result["runs"][1]["stdout"], "Changed content");
+}
+
+#[test]
+fn test_iz_cli_bench() {
+    let temp_repo = TestRepo::new(&[("show", "cat test.txt")]);
+
+    let output = temp_repo.run(&["bench", "HEAD", "show", "--runs", "3"]);
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("run 3/3"));
+    assert!(stdout.contains("stddev"));
+    assert!(!stdout.contains("Test content"));
+
+    let output = temp_repo.run(&[
+        "bench",
+        "HEAD",
+        "show",
+        "--runs",
+        "2",
+        "--fresh-checkout",
+        "--output",
+        "json",
+    ]);
+
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["command"], "show");
+    assert_eq!(result["durations_ms"].as_array().unwrap().len(), 2);
+    assert_eq!(result["stats"]["runs"], 2);
+    let stats = &result["stats"];
+    assert!(stats["min_ms"].as_f64().unwrap() <= stats["max_ms"].as_f64().unwrap());
+}
+
+#[test]
+fn test_iz_cli_secrets_are_masked() {
+    let temp_repo = TestRepo::new(&[]);
+
+    temp_repo.write_config(
+        r#"{
+            "commands": {
+                "deploy": {
+                    "template": "echo token=#{token} user=#{user} env=#{env}",
+                    "params": ["token", "user", "env"],
+                    "secrets": ["user"]
+                }
+            }
+        }"#,
+    );
+
+    let args = [
+        "HEAD",
+        "deploy",
+        "--secret",
+        "token=s3cr3t",
+        "--param",
+        "user=alice",
+        "--param",
+        "env=prod",
+    ];
+
+    let output = temp_repo
+        .iz()
+        .args(args)
+        .arg("--verbose")
+        .output()
+        .expect("Failed to run iz CLI");
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("Command: echo token=**** user=**** env=prod"));
+    assert!(stderr.contains(r#"argv: ["echo","token=****","user=****","env=prod"]"#));
+    assert!(!stderr.contains("s3cr3t"));
+    // The command itself still receives the real values
+    assert!(stdout.contains("token=s3cr3t user=alice env=prod"));
+
+    let output = temp_repo
+        .iz()
+        .args(args)
+        .args(["--output", "json"])
+        .output()
+        .expect("Failed to run iz CLI");
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        result["final_command"],
+        "echo token=**** user=**** env=prod"
+    );
+    assert_eq!(result["argv"][1], "token=****");
+    assert_eq!(result["stdout"], "token=s3cr3t user=alice env=prod\n");
+}
+
+#[test]
+fn test_iz_cli_failed_checkout_leaves_no_temp_dir() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    // An unreadable .izignore makes the checkout fail after the directory exists
+    fs::create_dir_all(temp_repo.path().join(".izignore")).unwrap();
+
+    let output = temp_repo.run(&["HEAD", "hello", "--temp-dir", "runs"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to checkout commit"));
+
+    // bench cleans up the same way
+    let output = temp_repo.run(&["bench", "HEAD", "hello", "--temp-dir", "runs"]);
+    assert!(!output.status.success());
+
+    let leftovers: Vec<_> = fs::read_dir(temp_repo.path().join("runs"))
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("iz-"))
+        .collect();
+    assert!(leftovers.is_empty(), "leftover directories: {leftovers:?}");
+}
+
+#[test]
+fn test_iz_cli_yaml_config() {
+    let temp_repo = TestRepo::new(&[]);
+
+    fs::remove_file(temp_repo.path().join("izconfig.json")).unwrap();
+    fs::write(
+        temp_repo.path().join("izconfig.yaml"),
+        "commands:\n  hello: echo from-yaml\n",
+    )
+    .unwrap();
+
+    let output = temp_repo.run(&["HEAD", "hello"]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("from-yaml"));
+
+    // JSON wins when both exist
+    temp_repo.write_config(r#"{"commands": {"hello": "echo from-json"}}"#);
+    let output = temp_repo.run(&["HEAD", "hello"]);
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("from-json"));
+    assert!(stderr.contains("Ignoring"));
+    assert!(stderr.contains("izconfig.yaml because"));
+}
+
+#[test]
+fn test_iz_cli_watch_reruns_on_change() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+
+    let mut child = temp_repo
+        .iz()
+        .args(["HEAD", "hello", "--watch"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run iz CLI");
+
+    let (sender, lines) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let wait_for = |needle: &str| loop {
+        match lines.recv_timeout(Duration::from_secs(30)) {
+            Ok(line) if line.contains(needle) => return true,
+            Ok(_) => continue,
+            Err(_) => return false,
+        }
+    };
+
+    let watching = wait_for("Watching");
+    if watching {
+        fs::write(temp_repo.path().join("notes.txt"), "changed").unwrap();
+    }
+    let rerun = watching && wait_for("Change detected") && wait_for("Watching");
+
+    let _ = child.kill();
+    let _ = child.wait();
+    assert!(watching, "iz never started watching");
+    assert!(rerun, "iz did not re-run after a change");
+}
+
+#[test]
+fn test_iz_cli_clean_list_and_filters() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello"), ("other", "echo other")]);
+
+    for command in ["hello", "other"] {
+        let output = temp_repo.run(&["HEAD", command, "--temp-dir", "runs", "--keep"]);
+        assert!(output.status.success());
+    }
+    // A directory from before metadata was written
+    fs::create_dir_all(temp_repo.path().join("runs").join("iz-legacy")).unwrap();
+
+    let run_dir = fs::read_dir(temp_repo.path().join("runs"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.join("iz-meta.json").is_file())
+        .unwrap();
+    let meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(run_dir.join("iz-meta.json")).unwrap()).unwrap();
+    assert_eq!(meta["commit"].as_str().unwrap().len(), 40);
+    assert!(meta["started_at"].as_u64().unwrap() > 0);
+
+    let output = temp_repo.run(&["clean", "--temp-dir", "runs", "--list"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("COMMIT"));
+    assert!(stdout.contains(" hello "));
+    assert!(stdout.contains(" other "));
+    assert!(stdout.contains("iz-legacy (no metadata)"));
+
+    let output = temp_repo.run(&[
+        "clean",
+        "--temp-dir",
+        "runs",
+        "--match-command",
+        "hello",
+        "--force",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Successfully cleaned 1 directories"));
+
+    // Kept runs also leave a `latest` link next to the run directories
+    let remaining: Vec<_> = fs::read_dir(temp_repo.path().join("runs"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().unwrap() != "latest")
+        .collect();
+    assert_eq!(remaining.len(), 2);
+    assert!(remaining
+        .iter()
+        .all(|path| read_command(path).as_deref() != Some("hello")));
+}
+
+fn read_command(run_dir: &Path) -> Option<String> {
+    let content = fs::read_to_string(run_dir.join("iz-meta.json")).ok()?;
+    let meta: serde_json::Value = serde_json::from_str(&content).ok()?;
+    meta["command"].as_str().map(str::to_string)
+}
+
+#[test]
+fn test_iz_cli_command_temp_dir() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r#"{"commands": {"big": {"template": "pwd", "temp_dir": "cmd-temp"}, "small": "pwd"}, "temp_dir": "global-temp"}"#,
+    );
+
+    let run_parent = |args: &[&str]| -> PathBuf {
+        let output = temp_repo
+            .iz()
+            .args(args)
+            .args(["--output", "json"])
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let temp_dir = PathBuf::from(result["temp_dir"].as_str().unwrap());
+        PathBuf::from(temp_dir.parent().unwrap().file_name().unwrap())
+    };
+
+    assert_eq!(run_parent(&["HEAD", "big"]), PathBuf::from("cmd-temp"));
+    assert_eq!(run_parent(&["HEAD", "small"]), PathBuf::from("global-temp"));
+    assert_eq!(
+        run_parent(&["HEAD", "big", "--temp-dir", "from-flag"]),
+        PathBuf::from("from-flag")
+    );
+}
+
+#[test]
+fn test_iz_cli_print_config() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{"commands": {"test": "echo test"}, "keep": false, "temp_dir": "from-config"}"#,
+    );
+
+    let print_config = |args: &[&str]| -> serde_json::Value {
+        let output = temp_repo
+            .iz()
+            .arg("--print-config")
+            .args(args)
+            .env_remove("IZTEMP")
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        serde_json::from_slice(&output.stdout).unwrap()
+    };
+
+    let config = print_config(&[]);
+    assert_eq!(config["commands"]["test"], "echo test");
+    assert_eq!(config["keep"], false);
+    assert_eq!(config["temp_dir"], "from-config");
+
+    let config = print_config(&["--keep", "--temp-dir", "from-flag"]);
+    assert_eq!(config["keep"], true);
+    assert_eq!(config["temp_dir"], "from-flag");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_pty() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r#"{"commands": {
+            "tty": {"template": "if [ -t 1 ]; then echo is-tty; else echo not-tty; fi", "shell": true},
+            "progress": {"template": "printf '50%%\\r100%%'", "shell": true}
+        }}"#,
+    );
+
+    let run = |args: &[&str]| -> serde_json::Value {
+        let output = temp_repo
+            .iz()
+            .args(["HEAD", "tty", "--output", "json"])
+            .args(args)
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        serde_json::from_slice(&output.stdout).unwrap()
+    };
+
+    assert_eq!(run(&[])["stdout"], "not-tty\n");
+    assert_eq!(run(&["--pty"])["stdout"], "is-tty\n");
+
+    // Output is forwarded byte for byte, without waiting for or adding a newline
+    let output = temp_repo.run(&["HEAD", "progress", "--pty", "--output", "json"]);
+    assert!(output.status.success());
+    assert_eq!(output.stderr, b"50%\r100%");
+
+    let output = temp_repo.run(&["HEAD", "tty", "--pty", "--fail-on-stderr"]);
+    assert_exit_code(&output, 2);
+}
+
+#[test]
+fn test_iz_cli_auto_clean() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo
+        .write_config(r#"{"commands": {"test": "echo test"}, "auto_clean": {"older_than": "7d"}}"#);
+
+    let base = temp_repo.path().join(".iztemp");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    for (name, started_at) in [("iz-stale", now - 8 * 86400), ("iz-recent", now - 3600)] {
+        fs::create_dir_all(base.join(name)).unwrap();
+        fs::write(
+            base.join(name).join("iz-meta.json"),
+            format!(r#"{{"commit": "abc", "started_at": {started_at}}}"#),
+        )
+        .unwrap();
+    }
+
+    let run = |args: &[&str]| -> String {
+        let output = temp_repo
+            .iz()
+            .args(["HEAD", "test"])
+            .args(args)
+            .output()
+            .expect("Failed to run iz CLI");
+        assert_success(&output);
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let stdout = run(&["--no-auto-clean"]);
+    assert!(!stdout.contains("Auto-clean"), "stdout: {stdout}");
+    assert!(base.join("iz-stale").exists());
+
+    let stdout = run(&[]);
+    assert!(
+        stdout.contains("Auto-clean removed 1 temporary directories older than 7d"),
+        "stdout: {stdout}"
+    );
+    assert!(!base.join("iz-stale").exists());
+    assert!(base.join("iz-recent").exists());
+
+    // A scan that finds nothing keeps JSON output parseable
+    fs::remove_dir_all(base.join("iz-recent")).unwrap();
+    fs::remove_file(base.join(".iz-auto-clean")).unwrap();
+    let stdout = run(&["--output", "json"]);
+    let result: serde_json::Value = serde_json::from_str(&stdout).expect(&stdout);
+    assert_eq!(result["success"], true);
+}
+
+#[test]
+fn test_iz_cli_detect_change() {
+    let temp_repo = TestRepo::new(&[("show", "cat test.txt")]);
+    for (file, content) in [("test.txt", "Changed content"), ("other.txt", "Unrelated")] {
+        fs::write(temp_repo.path().join(file), content).unwrap();
+        temp_repo.git(&["add", file]);
+        temp_repo.git(&["commit", "-m", &format!("Update {file}")]);
+    }
+    fs::write(
+        temp_repo.path().join("commits.txt"),
+        "HEAD~2\nHEAD~1\nHEAD\n",
+    )
+    .unwrap();
+
+    let output = temp_repo.run(&["--commits-file", "commits.txt", "show", "--detect-change"]);
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("🔎 HEAD~1 is the first commit whose output changed"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("previous commit: HEAD~2"), "{stdout}");
+
+    let output = temp_repo.run(&["HEAD", "show", "--detect-change"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--detect-change needs --stdin-commits or --commits-file"));
+}
+
+#[test]
+fn test_iz_cli_no_emoji() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r#"{"commands": {"test": {"template": "echo test", "post": "false", "ignore_hook_failures": true}}}"#,
+    );
+
+    let assert_ascii = |output: &Output| {
+        assert_success(output);
+        for stream in [&output.stdout, &output.stderr] {
+            assert!(stream.is_ascii(), "{}", String::from_utf8_lossy(stream));
+        }
+    };
+
+    let output = temp_repo.run(&["HEAD", "test", "--keep", "--no-emoji"]);
+    assert_ascii(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[run] Executing command..."), "{stdout}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[warn] post hook failed"));
+
+    let output = temp_repo
+        .iz()
+        .args(["clean", "--force"])
+        .env("IZ_NO_EMOJI", "1")
+        .output()
+        .expect("Failed to run iz clean");
+    assert_ascii(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("[done] Successfully cleaned 1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_latest_link() {
+    let temp_repo = TestRepo::new(&[("one", "echo one"), ("two", "echo two")]);
+    let latest = temp_repo.path().join(".iztemp").join("latest");
+
+    let iz = |args: &[&str]| {
+        let output = temp_repo.run(args);
+        assert_success(&output);
+    };
+    let resolved = || fs::canonicalize(&latest).unwrap();
+
+    // Runs that are cleaned up don't move the link
+    iz(&["HEAD", "one"]);
+    assert!(fs::symlink_metadata(&latest).is_err());
+
+    iz(&["HEAD", "one", "--keep", "--run-id", "first"]);
+    assert_eq!(resolved().file_name().unwrap(), "iz-first");
+    iz(&["HEAD", "two", "--keep", "--run-id", "second"]);
+    assert_eq!(resolved().file_name().unwrap(), "iz-second");
+    assert_eq!(fs::read_link(&latest).unwrap(), PathBuf::from("iz-second"));
+
+    // Removing the newest run points the link back at the one that's left
+    iz(&["clean", "--force", "--match-command", "two"]);
+    assert_eq!(resolved().file_name().unwrap(), "iz-first");
+
+    iz(&["clean", "--force"]);
+    assert!(fs::symlink_metadata(&latest).is_err());
+}
+
+#[test]
+fn test_iz_cli_fuzzy_command() {
+    let temp_repo = TestRepo::new(&[
+        ("test", "echo running-test"),
+        ("teardown", "echo running-teardown"),
+        ("build", "echo running-build"),
+    ]);
+
+    let output = temp_repo.run(&["HEAD", "bu", "--fuzzy"]);
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("'bu' matched command 'build'"), "{stdout}");
+    assert!(stdout.contains("running-build"), "{stdout}");
+
+    let output = temp_repo.run(&["HEAD", "te", "--fuzzy"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Command prefix 'te' is ambiguous: teardown, test"));
+
+    let output = temp_repo.run(&["HEAD", "deploy", "--fuzzy"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no command starts with 'deploy'"));
+
+    // Without --fuzzy only exact names run
+    let output = temp_repo.run(&["HEAD", "bu"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Command 'bu' not found"));
+}
+
+#[test]
+fn test_iz_cli_stash() {
+    let temp_repo = TestRepo::new(&[("show", "cat test.txt")]);
+    let iz = |commit: &str| temp_repo.run(&[commit, "show"]);
+
+    fs::write(temp_repo.path().join("test.txt"), "Older work in progress").unwrap();
+    temp_repo.git(&["stash"]);
+    fs::write(temp_repo.path().join("test.txt"), "Newer work in progress").unwrap();
+    temp_repo.git(&["stash"]);
+
+    for (commit, expected) in [
+        ("stash@{0}", "Newer work in progress"),
+        ("stash@{1}", "Older work in progress"),
+        ("HEAD", "Test content"),
+    ] {
+        let output = iz(commit);
+        assert_success(&output);
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains(expected),
+            "{commit}"
+        );
+    }
+
+    let output = iz("stash@{2}");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Stash entry 'stash@{2}' does not exist: the stash has 2 entries (stash@{0} to stash@{1})"
+    ));
+}
+
+#[test]
+fn test_iz_cli_stats() {
+    let temp_repo = TestRepo::new(&[("test", "echo test")]);
+
+    fs::create_dir_all(temp_repo.path().join("src")).unwrap();
+    fs::write(temp_repo.path().join("src/big.bin"), vec![b'x'; 3000]).unwrap();
+    fs::write(temp_repo.path().join("src/small.txt"), "small").unwrap();
+    temp_repo.commit("Add src");
+
+    let stats = |commit: &str| -> serde_json::Value {
+        let output = temp_repo.run(&[commit, "test", "--stats", "--output", "json"]);
+        assert_success(&output);
+        let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        result["tree_stats"].clone()
+    };
+
+    let head = stats("HEAD");
+    assert_eq!(head["files"], 4);
+    assert_eq!(head["largest"][0]["path"], "src/big.bin");
+    assert_eq!(head["largest"][0]["bytes"], 3000);
+    assert_eq!(stats("HEAD~1")["files"], 2);
+
+    let output = temp_repo.run(&["HEAD", "test", "--stats"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("📊 4 files"), "{stdout}");
+    assert!(stdout.contains("src/big.bin"), "{stdout}");
+
+    let output = temp_repo.run(&["HEAD", "test", "--output", "json"]);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(result.get("tree_stats").is_none());
+}
+
+#[test]
+fn test_iz_cli_set_command_field() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r#"{"commands": {"slow": "sleep 5"}}"#);
+    let run = |args: &[&str]| {
+        temp_repo
+            .iz()
+            .args(["HEAD", "slow"])
+            .args(args)
+            .output()
+            .expect("Failed to run iz CLI")
+    };
+
+    let started = std::time::Instant::now();
+    let output = run(&["--set", "command.slow.timeout=300ms"]);
+    assert!(!output.status.success());
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Command timed out after 300ms"), "{stderr}");
+
+    // --timeout still wins over the overridden field
+    let output = run(&[
+        "--set",
+        "command.slow.template=echo overridden",
+        "--set",
+        "command.slow.timeout=300ms",
+        "--timeout",
+        "10s",
+    ]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("overridden"));
+
+    let output = run(&["--set", "command.slow.retries=3"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Unknown field 'retries' for command 'slow'"),
+        "{stderr}"
+    );
+
+    fs::create_dir_all(temp_repo.path().join("sub")).unwrap();
+    fs::write(temp_repo.path().join("sub").join("inner.txt"), "inner").unwrap();
+    for args in [&["add", "sub"][..], &["commit", "-m", "Add sub"]] {
+        temp_repo.git(args);
+    }
+    let output = run(&[
+        "--set",
+        "command.slow.template=cat inner.txt",
+        "--set",
+        "command.slow.workdir=sub",
+    ]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("inner"));
+
+    let output = run(&["--set", "command.slow.workdir=../outside"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("must be a relative path"));
+
+    let output = run(&["--set", "slow.timeout=1s"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("expected command.<name>.<field>=<value>")
+    );
+
+    let output = temp_repo.run(&["--print-config", "--set", "command.slow.timeout=1m"]);
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(config["commands"]["slow"]["timeout"], "1m");
+    assert_eq!(config["commands"]["slow"]["template"], "sleep 5");
+}
+
+#[test]
+fn test_iz_cli_fail_on_stderr() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r#"{"commands": {"noisy": {"template": "echo 'warning: deprecated API' >&2; echo done", "shell": true}}}"#,
+    );
+    let run = |args: &[&str]| {
+        temp_repo
+            .iz()
+            .args(["HEAD", "noisy"])
+            .args(args)
+            .output()
+            .expect("Failed to run iz CLI")
+    };
+
+    // Writing to stderr alone doesn't fail a run
+    assert!(run(&[]).status.success());
+
+    let output = run(&["--fail-on-stderr"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Command wrote 1 unexpected line(s) to stderr (--fail-on-stderr), first: warning: deprecated API"),
+        "{stderr}"
+    );
+
+    let output = run(&["--fail-on-stderr", "--output", "json"]);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["success"], false);
+    assert_eq!(result["exit_code"], 0);
+
+    let output = run(&[
+        "--fail-on-stderr",
+        "--stderr-allowlist",
+        "^warning: deprecated",
+    ]);
+    assert_success(&output);
+
+    let output = run(&["--fail-on-stderr", "--stderr-allowlist", "^note:"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_iz_cli_list_groups() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{"commands": {
+            "db:migrate": "diesel migration run",
+            "db:reset": "diesel database reset",
+            "app:run": "cargo run",
+            "test": "cargo test",
+            "t": {"alias": "test"}
+        }}"#,
+    );
+    let list = |args: &[&str]| {
+        let output = temp_repo
+            .iz()
+            .arg("list")
+            .args(args)
+            .output()
+            .expect("Failed to run iz CLI");
+        (
+            output.status.success(),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (success, stdout, _) = list(&[]);
+    assert!(success);
+    assert_eq!(
+        stdout,
+        "app:run  (cargo run)\n\
+         db:migrate  (diesel migration run)\n\
+         db:reset  (diesel database reset)\n\
+         t  (alias for test)\n\
+         test  (cargo test)\n"
+    );
+
+    let (success, stdout, _) = list(&["--group"]);
+    assert!(success);
+    assert_eq!(
+        stdout,
+        "app:\n  app:run  (cargo run)\n\
+         db:\n  db:migrate  (diesel migration run)\n  db:reset  (diesel database reset)\n\
+         (ungrouped):\n  t  (alias for test)\n  test  (cargo test)\n"
+    );
+
+    let (success, stdout, _) = list(&["db"]);
+    assert!(success);
+    assert_eq!(
+        stdout,
+        "db:migrate  (diesel migration run)\ndb:reset  (diesel database reset)\n"
+    );
+
+    let (success, stdout, _) = list(&["db", "--output", "json"]);
+    assert!(success);
+    let entries: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(entries[0]["name"], "db:migrate");
+    assert_eq!(entries[0]["group"], "db");
+    assert_eq!(entries.as_array().unwrap().len(), 2);
+
+    let (success, _, stderr) = list(&["web"]);
+    assert!(!success);
+    assert!(
+        stderr.contains("No commands in group 'web' (groups: app, db)"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_iz_cli_since_tag() {
+    let temp_repo = TestRepo::new(&[("show", "cat test.txt")]);
+    let git = |args: &[&str], date: &str| {
+        let output = Command::new("git")
+            .args(args)
+            .env("GIT_COMMITTER_DATE", date)
+            .env("GIT_AUTHOR_DATE", date)
+            .current_dir(temp_repo.path())
+            .output()
+            .expect("git failed");
+        assert!(output.status.success(), "git {args:?} failed");
+    };
+
+    let output = temp_repo.run(&["--since-tag", "show"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No tags found in the repository"));
+
+    fs::write(temp_repo.path().join("test.txt"), "Release one").unwrap();
+    git(&["commit", "-am", "Release one"], "2024-01-01T00:00:00Z");
+    git(&["tag", "-a", "v1.0", "-m", "v1.0"], "2024-01-01T00:00:00Z");
+    fs::write(temp_repo.path().join("test.txt"), "Release two").unwrap();
+    git(&["commit", "-am", "Release two"], "2024-02-01T00:00:00Z");
+    git(&["tag", "v2.0"], "2024-02-01T00:00:00Z");
+    // An untagged commit after the newest tag must not be picked
+    fs::write(temp_repo.path().join("test.txt"), "Unreleased").unwrap();
+    git(&["commit", "-am", "Unreleased"], "2024-03-01T00:00:00Z");
+
+    for args in [&["--since-tag", "show"][..], &["@latest-tag", "show"][..]] {
+        let output = temp_repo.run(args);
+        assert_success(&output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("Latest tag: v2.0"), "{stdout}");
+        assert!(stdout.contains("Release two"), "{stdout}");
+    }
+
+    let output = temp_repo.run(&["--since-tag", "HEAD", "show"]);
+    assert!(!output.status.success());
+
+    // Subcommands resolve it too
+    let output = temp_repo.run(&["diff", "v1.0", "@latest-tag", "show"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("+Release two"), "{stdout}");
+    let output = temp_repo.run(&["bench", "@latest-tag", "show", "--runs", "1"]);
+    assert!(
+        output.status.success(),
+        "iz bench failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_iz_cli_argv_command() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r##"{"commands": {"show": {"argv": ["printf", "[%s]", "#{message}", "two  spaces"], "params": ["message"]}}}"##,
+    );
+    let run = |args: &[&str]| {
+        temp_repo
+            .iz()
+            .args(["HEAD", "show", "--param", "message=hello world"])
+            .args(args)
+            .output()
+            .expect("Failed to run iz CLI")
+    };
+
+    let output = run(&["--output", "json"]);
+    assert_success(&output);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["stdout"], "[hello world][two  spaces]");
+    assert_eq!(
+        result["argv"],
+        serde_json::json!(["printf", "[%s]", "hello world", "two  spaces"])
+    );
+    assert_eq!(
+        result["final_command"],
+        "printf '[%s]' 'hello world' 'two  spaces'"
+    );
+
+    let output = run(&["--shell"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Command 'show' is given as an argv and can't run through the shell"));
+}
+
+#[test]
+fn test_iz_cli_trace_fs() {
+    let temp_repo = TestRepo::new(&[("test", "echo test")]);
+    fs::create_dir_all(temp_repo.path().join("src/nested")).unwrap();
+    fs::write(temp_repo.path().join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(temp_repo.path().join("src/nested/mod.rs"), "").unwrap();
+    temp_repo.commit("Add src");
+
+    let files = String::from_utf8(
+        temp_repo
+            .git(&["ls-tree", "-r", "--name-only", "HEAD"])
+            .stdout,
+    )
+    .unwrap();
+    let file_count = files.lines().count();
+    assert_eq!(file_count, 4);
+
+    let count = |log: &str, operation: &str| {
+        log.lines()
+            .filter(|line| line.starts_with(&format!("[fs] {operation} ")))
+            .count()
+    };
+
+    let output = temp_repo.run(&["HEAD", "test", "--trace-fs"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(count(&stderr, "write"), file_count, "{stderr}");
+    assert_eq!(count(&stderr, "mkdir"), 2, "{stderr}");
+    assert!(stderr.contains("/src/nested/mod.rs"), "{stderr}");
+
+    let trace_file = temp_repo.path().join("trace.log");
+    let output = temp_repo.run(&["HEAD", "test", "--trace-fs=trace.log"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("[fs]"));
+    let log = fs::read_to_string(trace_file).unwrap();
+    assert_eq!(count(&log, "write"), file_count, "{log}");
+
+    // Without the flag nothing is traced
+    let output = temp_repo.run(&["HEAD", "test"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("[fs]"));
+}
+
+#[test]
+fn test_iz_cli_require_clean() {
+    let temp_repo = TestRepo::new(&[("test", "echo test")]);
+    let run = || temp_repo.run(&["HEAD", "test", "--require-clean"]);
+
+    // Untracked files, such as the temp dir itself, don't make the tree dirty
+    fs::write(temp_repo.path().join("notes.txt"), "untracked").unwrap();
+    let output = run();
+    assert_success(&output);
+
+    fs::write(temp_repo.path().join("test.txt"), "Uncommitted change").unwrap();
+    let output = run();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The working tree has uncommitted changes (test.txt)"),
+        "{stderr}"
+    );
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Executing command"));
+
+    // Staged changes count too
+    temp_repo.git(&["add", "test.txt"]);
+    assert!(!run().status.success());
+}
+
+#[test]
+fn test_iz_cli_clean_confirmation() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let temp_repo = TestRepo::new(&[("test", "echo test")]);
+    let run_dir = temp_repo.path().join(".iztemp").join("iz-test1");
+    let clean = |input: Option<&str>, assume_yes: Option<&str>| {
+        let mut command = temp_repo.iz();
+        command
+            .arg("clean")
+            .env_remove("IZ_ASSUME_YES")
+            .stdout(Stdio::piped())
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            });
+        if let Some(assume_yes) = assume_yes {
+            command.env("IZ_ASSUME_YES", assume_yes);
+        }
+        let mut child = command.spawn().expect("Failed to run iz clean");
+        if let Some(input) = input {
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(input.as_bytes())
+                .unwrap();
+        }
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // Empty stdin is a "no" rather than a hang
+    fs::create_dir_all(&run_dir).unwrap();
+    let stdout = clean(None, None);
+    assert!(stdout.contains("[y/N]"), "{stdout}");
+    assert!(stdout.contains("Cleanup cancelled"), "{stdout}");
+    assert!(run_dir.exists());
+
+    // An empty line takes the default, which is no
+    clean(Some("\n"), None);
+    assert!(run_dir.exists());
+
+    // Only a truthy IZ_ASSUME_YES skips the prompt
+    let stdout = clean(None, Some("0"));
+    assert!(stdout.contains("[y/N]"), "{stdout}");
+    assert!(run_dir.exists());
+
+    // IZ_ASSUME_YES skips the prompt like --force
+    let stdout = clean(None, Some("1"));
+    assert!(!stdout.contains("[y/N]"), "{stdout}");
+    assert!(!run_dir.exists());
+
+    // clean_confirm_default flips what an empty line means, but not EOF
+    temp_repo.write_config(r#"{"commands": {"test": "echo test"}, "clean_confirm_default": true}"#);
+    fs::create_dir_all(&run_dir).unwrap();
+    let stdout = clean(None, None);
+    assert!(stdout.contains("[Y/n]"), "{stdout}");
+    assert!(run_dir.exists());
+    clean(Some("\n"), None);
+    assert!(!run_dir.exists());
+}
+
+#[test]
+fn test_iz_cli_changed_since() {
+    let temp_repo = TestRepo::new(&[("files", "find . -type f")]);
+
+    fs::create_dir_all(temp_repo.path().join("src/old")).unwrap();
+    fs::write(temp_repo.path().join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(temp_repo.path().join("src/old/gone.rs"), "// removed later").unwrap();
+    temp_repo.commit("Base");
+
+    fs::write(temp_repo.path().join("test.txt"), "Changed content").unwrap();
+    fs::create_dir_all(temp_repo.path().join("docs/guide")).unwrap();
+    fs::write(temp_repo.path().join("docs/guide/intro.md"), "# Intro").unwrap();
+    fs::remove_dir_all(temp_repo.path().join("src/old")).unwrap();
+    temp_repo.commit("Target");
+
+    let output = temp_repo.run(&["HEAD", "files", "--changed-since", "HEAD~1"]);
+    assert_success(&output);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("2 files changed since HEAD~1"), "{stdout}");
+    assert!(stdout.contains("./docs/guide/intro.md"), "{stdout}");
+    assert!(stdout.contains("./test.txt"), "{stdout}");
+    // Unchanged and deleted files are not checked out
+    assert!(!stdout.contains("./src/main.rs"), "{stdout}");
+    assert!(!stdout.contains("./izconfig.json"), "{stdout}");
+    assert!(!stdout.contains("gone.rs"), "{stdout}");
+}
+
+#[test]
+fn test_iz_cli_list_null_separated() {
+    let temp_repo = TestRepo::new(&[
+        ("test", "cargo test"),
+        ("build release", "cargo build --release"),
+    ]);
+
+    for flag in ["--null", "-0"] {
+        let output = temp_repo.run(&["list", flag]);
+        assert_success(&output);
+        assert_eq!(output.stdout, b"build release\0test\0");
+    }
+
+    let output = temp_repo.run(&["list", "--null", "--group"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_iz_cli_max_temp_bytes() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(r#"{"commands": {"test": "echo test"}, "max_temp_bytes": 4096}"#);
+
+    let base = temp_repo.path().join(".iztemp");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    for (name, started_at, bytes) in [
+        ("iz-oldest", now - 300, 2000),
+        ("iz-middle", now - 200, 2000),
+        ("iz-newest", now - 100, 1000),
+    ] {
+        fs::create_dir_all(base.join(name)).unwrap();
+        fs::write(
+            base.join(name).join("iz-meta.json"),
+            format!(r#"{{"commit": "abc", "started_at": {started_at}}}"#),
+        )
+        .unwrap();
+        fs::write(base.join(name).join("artifact.bin"), vec![0u8; bytes]).unwrap();
+    }
+    // A run still in progress in a live process is never pruned
+    fs::create_dir_all(base.join("iz-busy")).unwrap();
+    fs::write(
+        base.join("iz-busy").join("iz-meta.json"),
+        format!(
+            r#"{{"commit": "abc", "started_at": {}, "pid": {}}}"#,
+            now - 400,
+            std::process::id()
+        ),
+    )
+    .unwrap();
+    fs::write(base.join("iz-busy").join("artifact.bin"), vec![0u8; 2000]).unwrap();
+
+    let output = temp_repo.run(&["HEAD", "test"]);
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Pruned"), "{stdout}");
+    assert!(stdout.contains("iz-oldest"), "{stdout}");
+    assert!(stdout.contains("to stay under max_temp_bytes"), "{stdout}");
+    // Only as many of the oldest runs as needed are removed
+    assert!(!base.join("iz-oldest").exists());
+    assert!(base.join("iz-busy").exists());
+    assert!(base.join("iz-middle").exists());
+    assert!(base.join("iz-newest").exists());
+
+    // A checkout that can't fit on its own is an error
+    temp_repo.write_config(r#"{"commands": {"test": "echo test"}, "max_temp_bytes": 10}"#);
+    let output = temp_repo.run(&["HEAD", "test"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("more than max_temp_bytes allows"),
+        "{stderr}"
+    );
+    assert!(base.join("iz-middle").exists());
+}
+
+#[test]
+fn test_iz_cli_batch_summary() {
+    let temp_repo = TestRepo::new(&[("check", "grep -q Test test.txt")]);
+
+    fs::write(temp_repo.path().join("test.txt"), "Broken content").unwrap();
+    temp_repo.git(&["commit", "-am", "Break the check"]);
+    fs::write(
+        temp_repo.path().join("commits.txt"),
+        "HEAD~1\nHEAD\nHEAD~1\n",
+    )
+    .unwrap();
+    let batch = |extra: &[&str]| {
+        temp_repo
+            .iz()
+            .args(["--commits-file", "commits.txt", "check"])
+            .args(extra)
+            .output()
+            .expect("Failed to run iz CLI")
+    };
+
+    let output = batch(&[]);
+    assert_exit_code(&output, 1);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let table: Vec<&str> = stdout
+        .lines()
+        .skip_while(|line| !line.contains("TOTAL"))
+        .collect();
+    assert_eq!(table.len(), 2, "{stdout}");
+    let headers: Vec<&str> = table[0].split_whitespace().collect();
+    assert_eq!(
+        headers,
+        ["TOTAL", "PASSED", "FAILED", "SKIPPED", "DURATION"]
+    );
+    let values: Vec<&str> = table[1].split_whitespace().collect();
+    assert_eq!(values[..4], ["3", "2", "1", "0"]);
+    assert!(values[4].ends_with('s'));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 of 3 commits failed"));
+
+    let output = batch(&["--output", "json"]);
+    assert_exit_code(&output, 1);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let summary: serde_json::Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    let summary = &summary["summary"];
+    assert_eq!(summary["total"], 3);
+    assert_eq!(summary["passed"], 2);
+    assert_eq!(summary["failed"], 1);
+    assert_eq!(summary["skipped"], 0);
+    assert!(summary["duration_ms"].is_u64());
+}
+
+#[test]
+fn test_iz_cli_file_placeholder() {
+    let temp_repo = TestRepo::new(&[
+        ("show", "echo #{file:test.txt}"),
+        ("missing", "cat #{file:config/app.toml}"),
+    ]);
+    let run = |command: &str| temp_repo.run(&["HEAD", command, "--keep"]);
+
+    let output = run("show");
+    assert_success(&output);
+    // The placeholder became the absolute path inside the run's checkout
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let run_dir = fs::read_dir(temp_repo.path().join(".iztemp"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.join("test.txt").is_file())
+        .unwrap();
+    let resolved = run_dir.canonicalize().unwrap().join("test.txt");
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line == resolved.display().to_string()),
+        "{stdout}"
+    );
+
+    let output = run("missing");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("File referenced by #{file:config/app.toml} not found in the checkout"),
+        "{stderr}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_iz_cli_notify_command() {
+    let temp_repo = TestRepo::new(&[("pass", "echo ok"), ("fail", "false")]);
+    let notify_file = temp_repo.path().join("notify.txt");
+    let notify_command = format!(
+        "echo \"$IZ_COMMAND_NAME $IZ_SUCCESS $IZ_EXIT_CODE $IZ_COMMIT\" > {}",
+        notify_file.display()
+    );
+    let run =
+        |command: &str| temp_repo.run(&["HEAD", command, "--notify-command", &notify_command]);
+
+    assert!(run("pass").status.success());
+    let notified = fs::read_to_string(&notify_file).unwrap();
+    let fields: Vec<&str> = notified.split_whitespace().collect();
+    assert_eq!(fields[..3], ["pass", "true", "0"]);
+    assert_eq!(fields[3].len(), 40, "{notified}");
+
+    // Notifications go out for failed runs too, without changing the exit status
+    let output = run("fail");
+    assert!(!output.status.success());
+    let notified = fs::read_to_string(&notify_file).unwrap();
+    assert!(notified.starts_with("fail false 1 "), "{notified}");
+
+    let output = temp_repo.run(&["HEAD", "pass", "--notify-command", "exit 7"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Notify command failed"));
+
+    if !cfg!(feature = "notify") {
+        let output = temp_repo.run(&["HEAD", "pass", "--notify", "http://127.0.0.1:9/"]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("`notify` feature"));
+    }
+}
+
+#[cfg(feature = "notify")]
+#[test]
+fn test_iz_cli_notify_url() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    let temp_repo = TestRepo::new(&[("test", "echo test")]);
+
+    // A one-request mock server that hands back the request line and body
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/iz", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (request_line, body)
+    });
+
+    let output = temp_repo.run(&["HEAD", "test", "--notify", &url]);
+    assert_success(&output);
+
+    let (request_line, body) = server.join().unwrap();
+    assert!(
+        request_line.starts_with("POST /hooks/iz "),
+        "{request_line}"
+    );
+    let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(summary["command"], "test");
+    assert_eq!(summary["success"], true);
+    assert_eq!(summary["exit_code"], 0);
+    assert_eq!(summary["commit"].as_str().unwrap().len(), 40);
+    assert!(summary["duration_ms"].is_u64());
+}
+
+#[test]
+fn test_iz_cli_keep_duration_expires() {
+    let temp_repo = TestRepo::new(&[("hello", "echo hello")]);
+    let base = temp_repo.path().join(".iztemp");
+
+    let iz = |args: &[&str]| {
+        let output = temp_repo.run(args);
+        assert_success(&output);
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    // Long enough that the second run can't expire it on a busy machine
+    iz(&["HEAD", "hello", "--keep=3s", "--run-id", "short"]);
+    let meta: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(base.join("iz-short").join("iz-meta.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        meta["expires_at"].as_u64().unwrap(),
+        meta["started_at"].as_u64().unwrap() + 3
+    );
+    iz(&["HEAD", "hello", "--keep=1h", "--run-id", "long"]);
+
+    std::thread::sleep(std::time::Duration::from_secs(4));
+
+    // The next run removes the expired directory and leaves the other one
+    let stdout = iz(&["HEAD", "hello"]);
+    assert!(stdout.contains("past their --keep duration"), "{stdout}");
+    assert!(!base.join("iz-short").exists());
+    assert!(base.join("iz-long").join("iz-meta.json").is_file());
+
+    // A user-chosen --into directory never expires
+    let output = temp_repo.run(&["HEAD", "hello", "--keep=1h", "--into", "out"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("can't be combined with --into"), "{stderr}");
+
+    let output = temp_repo.run(&["HEAD", "hello", "--keep=soon"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid --keep value 'soon'"), "{stderr}");
+}
+
+#[test]
+fn test_iz_cli_config_format() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let temp_repo = TestRepo::new(&[]);
+
+    // A generated YAML config piped in through stdin
+    let mut child = temp_repo
+        .iz()
+        .args(["--config", "-", "--config-format", "yaml", "HEAD", "piped"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run iz CLI");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"commands:\n  piped: echo from-stdin\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("from-stdin"));
+
+    // An extension that says nothing about the format
+    fs::write(
+        temp_repo.path().join("iz.conf"),
+        "[commands]\nhello = \"echo from-toml\"\n",
+    )
+    .unwrap();
+    let run = |format: &str| {
+        temp_repo.run(&[
+            "--config",
+            "iz.conf",
+            "--config-format",
+            format,
+            "HEAD",
+            "hello",
+        ])
+    };
+    let output = run("toml");
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("from-toml"));
+
+    let output = run("json");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to parse config file"), "{stderr}");
+}
+
+#[test]
+fn test_iz_cli_propagates_exit_code() {
+    let temp_repo = TestRepo::new(&[]);
+    temp_repo.write_config(
+        r#"{"commands": {"two": {"template": "exit 2", "shell": true}, "fails": "false"}}"#,
+    );
+
+    let output = temp_repo.run(&["HEAD", "two"]);
+    assert_exit_code(&output, 2);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Command failed with exit code 2"),
+        "{stderr}"
+    );
+
+    assert_exit_code(&temp_repo.run(&["HEAD", "two", "--output", "json"]), 2);
+    assert_exit_code(&temp_repo.run(&["HEAD", "fails"]), 1);
+
+    // An accepted code is a success
+    assert_exit_code(&temp_repo.run(&["HEAD", "two", "--success-code", "2"]), 0);
+
+    // Failing for another reason than the command's exit code exits 1
+    assert_exit_code(&temp_repo.run(&["HEAD", "missing"]), 1);
+}